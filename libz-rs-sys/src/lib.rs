@@ -639,9 +639,7 @@ unsafe extern "C-unwind" fn inflateInit2(strm: z_streamp, windowBits: c_int) ->
         return ReturnCode::StreamError as _;
     };
 
-    let config = InflateConfig {
-        window_bits: windowBits,
-    };
+    let config = InflateConfig::default().with_window_bits(windowBits);
 
     zlib_rs::inflate::init(strm, config) as _
 }
@@ -724,9 +722,7 @@ pub unsafe extern "C-unwind" fn inflateReset(strm: *mut z_stream) -> i32 {
 #[export_name = prefix!(inflateReset2)]
 pub unsafe extern "C-unwind" fn inflateReset2(strm: *mut z_stream, windowBits: c_int) -> i32 {
     if let Some(stream) = InflateStream::from_stream_mut(strm) {
-        let config = InflateConfig {
-            window_bits: windowBits,
-        };
+        let config = InflateConfig::default().with_window_bits(windowBits);
         zlib_rs::inflate::reset_with_config(stream, config) as _
     } else {
         ReturnCode::StreamError as _
@@ -1518,13 +1514,11 @@ pub unsafe extern "C-unwind" fn deflateInit2_(
         return ReturnCode::StreamError as _;
     };
 
-    let config = DeflateConfig {
-        level,
-        method,
-        window_bits: windowBits,
-        mem_level: memLevel,
-        strategy,
-    };
+    let config = DeflateConfig::new(level)
+        .with_method(method)
+        .with_window_bits(windowBits)
+        .with_mem_level(memLevel)
+        .with_strategy(strategy);
 
     zlib_rs::deflate::init(strm, config) as _
 }
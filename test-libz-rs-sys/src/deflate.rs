@@ -586,13 +586,11 @@ fn deflate_bound_correct_windows() {
     // on windows, c_ulong is just 32 bits wide. That leads to rounding that is different to what
     // we'd get when using usize in rust
 
-    let config = DeflateConfig {
-        level: 9,
-        method: Method::Deflated,
-        window_bits: -13,
-        mem_level: 5,
-        strategy: Strategy::Filtered,
-    };
+    let config = DeflateConfig::new(9)
+        .with_method(Method::Deflated)
+        .with_window_bits(-13)
+        .with_mem_level(5)
+        .with_strategy(Strategy::Filtered);
 
     // this value is dangerously close to u32::MAX, and the calculation will run into overflow
     //    u32::MAX = 4294967296
@@ -600,13 +598,11 @@ fn deflate_bound_correct_windows() {
 
     assert_deflate_bound_correct((config, source_len));
 
-    let config = DeflateConfig {
-        level: 0,
-        method: Method::Deflated,
-        window_bits: 15,
-        mem_level: 5,
-        strategy: Strategy::HuffmanOnly,
-    };
+    let config = DeflateConfig::new(0)
+        .with_method(Method::Deflated)
+        .with_window_bits(15)
+        .with_mem_level(5)
+        .with_strategy(Strategy::HuffmanOnly);
     // this value is dangerously close to u32::MAX, and the calculation will run into overflow
     //    u32::MAX = 4294967296
     let source_len = 4294967289;
@@ -771,10 +767,7 @@ fn test_compress_param() {
 
 #[test]
 fn test_dict_deflate() {
-    let config = DeflateConfig {
-        level: Z_BEST_COMPRESSION,
-        ..Default::default()
-    };
+    let config = DeflateConfig::new(Z_BEST_COMPRESSION);
 
     const DICTIONARY: &str = "hello";
     const HELLO: &str = "hello, hello!\0";
@@ -832,13 +825,11 @@ fn test_deflate_prime() {
         libz_rs_sys::deflatePrime(stream, 16, value >> 16)
     }
 
-    let config = DeflateConfig {
-        level: -1,
-        method: Method::Deflated,
-        window_bits: -15, // deflate as raw bytes
-        mem_level: 8,
-        strategy: Strategy::Default,
-    };
+    let config = DeflateConfig::new(-1)
+        .with_method(Method::Deflated)
+        .with_window_bits(-15) // deflate as raw bytes
+        .with_mem_level(8)
+        .with_strategy(Strategy::Default);
 
     unsafe {
         let mut strm = MaybeUninit::zeroed();
@@ -909,9 +900,7 @@ fn test_deflate_prime() {
         assert_eq!(err, 0); // inflate with gzip header
 
         // now inflate it again
-        let inflate_config = InflateConfig {
-            window_bits: 15 + 32,
-        };
+        let inflate_config = InflateConfig::default().with_window_bits(15 + 32);
 
         let mut strm = MaybeUninit::zeroed();
 
@@ -944,17 +933,13 @@ fn test_deflate_prime() {
 
 #[test]
 fn small_window() {
-    let deflate_config = DeflateConfig {
-        level: Z_BEST_COMPRESSION,
-        method: Method::Deflated,
-        window_bits: -9,
-        mem_level: 8,
-        strategy: Strategy::Default,
-    };
+    let deflate_config = DeflateConfig::new(Z_BEST_COMPRESSION)
+        .with_method(Method::Deflated)
+        .with_window_bits(-9)
+        .with_mem_level(8)
+        .with_strategy(Strategy::Default);
 
-    let inflate_config = InflateConfig {
-        window_bits: deflate_config.window_bits,
-    };
+    let inflate_config = InflateConfig::default().with_window_bits(deflate_config.window_bits);
 
     let plain: [u8; 128] = std::array::from_fn(|i| i as u8);
     let dictionary1 = vec![b'a'; (1 << 9) - plain.len() / 2];
@@ -1418,10 +1403,7 @@ fn gzip_with_header() {
     let name = "nomen est omen\0";
     let comment = "such comment\0";
 
-    let config = DeflateConfig {
-        window_bits: 31,
-        ..Default::default()
-    };
+    let config = DeflateConfig::default().with_window_bits(31);
 
     let output = assert_eq_rs_ng!({
         let mut stream = MaybeUninit::<z_stream>::zeroed();
@@ -1646,13 +1628,11 @@ mod fuzz_based_tests {
     fn simple_rle() {
         fuzz_based_test(
             "\0\0\0\0\u{6}".as_bytes(),
-            DeflateConfig {
-                level: -1,
-                method: Method::Deflated,
-                window_bits: 11,
-                mem_level: 4,
-                strategy: Strategy::Rle,
-            },
+            DeflateConfig::new(-1)
+                .with_method(Method::Deflated)
+                .with_window_bits(11)
+                .with_mem_level(4)
+                .with_strategy(Strategy::Rle),
             &[56, 17, 99, 0, 2, 54, 0, 0, 11, 0, 7],
         )
     }
@@ -1714,13 +1694,11 @@ mod fuzz_based_tests {
 
         fuzz_based_test(
             INPUT,
-            DeflateConfig {
-                level: -1,
-                method: Method::Deflated,
-                window_bits: 9,
-                mem_level: 1,
-                strategy: Strategy::HuffmanOnly,
-            },
+            DeflateConfig::new(-1)
+                .with_method(Method::Deflated)
+                .with_window_bits(9)
+                .with_mem_level(1)
+                .with_strategy(Strategy::HuffmanOnly),
             &[
                 0x18, 0x19, 0x4, 0xc1, 0x21, 0x1, 0xc4, 0x0, 0x10, 0x3, 0xb0, 0x18, 0x29, 0x1e,
                 0x7e, 0x17, 0x83, 0xf5, 0x70, 0x6c, 0xac, 0xfe, 0xc9, 0x27, 0xdb, 0xb6, 0x6f, 0xdb,
@@ -1754,25 +1732,20 @@ mod fuzz_based_tests {
 
         fuzz_based_test(
             INPUT.as_bytes(),
-            DeflateConfig {
-                level: 0,
-                method: Method::Deflated,
-                window_bits: 10,
-                mem_level: 6,
-                strategy: Strategy::Default,
-            },
+            DeflateConfig::new(0)
+                .with_method(Method::Deflated)
+                .with_window_bits(10)
+                .with_mem_level(6)
+                .with_strategy(Strategy::Default),
             &[],
         )
     }
 
     #[test]
     fn gzip_no_header() {
-        let config = DeflateConfig {
-            level: 9,
-            method: Method::Deflated,
-            window_bits: 31, // gzip
-            ..Default::default()
-        };
+        let config = DeflateConfig::new(9)
+            .with_method(Method::Deflated)
+            .with_window_bits(31); // gzip
 
         let input = b"Hello World!";
         let os = gz_header::OS_CODE;
@@ -1794,13 +1767,11 @@ mod fuzz_based_tests {
             &[
                 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 9, 0,
             ],
-            DeflateConfig {
-                level: 0,
-                method: Method::Deflated,
-                window_bits: 26,
-                mem_level: 6,
-                strategy: Strategy::Default,
-            },
+            DeflateConfig::new(0)
+                .with_method(Method::Deflated)
+                .with_window_bits(26)
+                .with_mem_level(6)
+                .with_strategy(Strategy::Default),
             &[
                 31, 139, 8, 0, 0, 0, 0, 0, 4, gz_header::OS_CODE, 1, 18, 0, 237, 255, 27, 27, 27, 27, 27, 27, 27,
                 27, 27, 27, 27, 27, 27, 27, 27, 27, 9, 0, 60, 101, 156, 55, 18, 0, 0, 0,
@@ -1845,13 +1816,11 @@ mod fuzz_based_tests {
                 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 38, 0, 46,
                 46, 46, 46, 46, 46, 0, 49, 0, 2, 4, 0, 0, 8, 0, 0, 0, 0, 0, 0, 38,
             ],
-            DeflateConfig {
-                level: -1,
-                method: Method::Deflated,
-                window_bits: 8,
-                mem_level: 2,
-                strategy: Strategy::Default,
-            },
+            DeflateConfig::new(-1)
+                .with_method(Method::Deflated)
+                .with_window_bits(8)
+                .with_mem_level(2)
+                .with_strategy(Strategy::Default),
             if cfg!(any(miri, target_arch = "s390x", target_family = "wasm")) {
                 output_s390x
             } else {
@@ -1917,13 +1886,11 @@ mod fuzz_based_tests {
                 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 78, 78, 78, 78,
                 78, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             ],
-            DeflateConfig {
-                level: 4,
-                method: Method::Deflated,
-                window_bits: 8,
-                mem_level: 6,
-                strategy: Strategy::Default,
-            },
+            DeflateConfig::new(4)
+                .with_method(Method::Deflated)
+                .with_window_bits(8)
+                .with_mem_level(6)
+                .with_strategy(Strategy::Default),
             if cfg!(target_arch = "x86") {
                 output_i686
             } else if cfg!(target_arch = "s390x") {
@@ -2068,13 +2035,11 @@ fn flood_pending_buffer() {
     .to_vec();
 
     assert_eq_rs_ng!({
-        let config = DeflateConfig {
-            level: Z_DEFAULT_COMPRESSION,
-            method: Method::Deflated,
-            window_bits: 31,
-            mem_level: 1, // NOTE: this is extremely small
-            strategy: Strategy::HuffmanOnly,
-        };
+        let config = DeflateConfig::new(Z_DEFAULT_COMPRESSION)
+            .with_method(Method::Deflated)
+            .with_window_bits(31)
+            .with_mem_level(1) // NOTE: this is extremely small
+            .with_strategy(Strategy::HuffmanOnly);
 
         let mut stream = MaybeUninit::zeroed();
 
@@ -2211,13 +2176,11 @@ fn copy_uninitialized_window_section() {
         0, 16, 0, 0, 0, 0,
     ];
 
-    let config = DeflateConfig {
-        level: 0,
-        method: Method::Deflated,
-        window_bits: 25,
-        mem_level: 3,
-        strategy: Strategy::Default,
-    };
+    let config = DeflateConfig::new(0)
+        .with_method(Method::Deflated)
+        .with_window_bits(25)
+        .with_mem_level(3)
+        .with_strategy(Strategy::Default);
 
     assert_eq_rs_ng!({
         let mut header = gz_header {
@@ -2353,13 +2316,11 @@ fn crc32_hash_calc_uninitialized_memory() {
         0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
     ];
 
-    let config = DeflateConfig {
-        level: 0,
-        method: Method::Deflated,
-        window_bits: 31,
-        mem_level: 3,
-        strategy: Strategy::Default,
-    };
+    let config = DeflateConfig::new(0)
+        .with_method(Method::Deflated)
+        .with_window_bits(31)
+        .with_mem_level(3)
+        .with_strategy(Strategy::Default);
 
     assert_eq_rs_ng!({
         let mut stream = MaybeUninit::zeroed();
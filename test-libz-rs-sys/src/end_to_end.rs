@@ -32,20 +32,21 @@ pub fn test(data: String, config: DeflateConfig) -> bool {
     let error = ReturnCode::from(error as i32);
     assert_eq!(ReturnCode::Ok, error);
 
-    let mut deflated_ng = [0; LENGTH];
-    let (deflated_ng, error) = compress_slice_ng(&mut deflated_ng, data.as_bytes(), config);
+    // zlib-identical output and split blocks are (deliberately) not what zlib-ng produces
+    if !config.zlib_identical && !config.block_splitting {
+        let mut deflated_ng = [0; LENGTH];
+        let (deflated_ng, error) = compress_slice_ng(&mut deflated_ng, data.as_bytes(), config);
 
-    let error = ReturnCode::from(error as i32);
-    assert_eq!(ReturnCode::Ok, error);
+        let error = ReturnCode::from(error as i32);
+        assert_eq!(ReturnCode::Ok, error);
 
-    assert_eq!(deflated_rs, deflated_ng);
+        assert_eq!(deflated_rs, deflated_ng);
+    }
 
-    let config = InflateConfig {
-        window_bits: match config.window_bits {
-            8 => 9,
-            n => n,
-        },
-    };
+    let config = InflateConfig::default().with_window_bits(match config.window_bits {
+        8 => 9,
+        n => n,
+    });
 
     let mut dest_vec_ng = vec![0u8; data.len()];
     let (output_ng, error) = uncompress_slice_ng(&mut dest_vec_ng, deflated_rs, config);
@@ -1160,12 +1160,12 @@ fn inflate_window_bits_0_is_15() {
     let (compressed, err) = compress_slice(&mut compressed, input, DeflateConfig::new(6));
     assert_eq!(err, ReturnCode::Ok);
 
-    let config = InflateConfig { window_bits: 15 };
+    let config = InflateConfig::default().with_window_bits(15);
     let mut output_15 = [0; 64];
     let (output_15, err) = uncompress_slice(&mut output_15, compressed, config);
     assert_eq!(err, ReturnCode::Ok);
 
-    let config = InflateConfig { window_bits: 0 };
+    let config = InflateConfig::default().with_window_bits(0);
     let mut output_0 = [0; 64];
     let (output_0, err) = uncompress_slice(&mut output_0, compressed, config);
     assert_eq!(err, ReturnCode::Ok);
@@ -1180,7 +1180,7 @@ fn inflate_window_bits_0_is_15() {
 
 #[test]
 fn uncompress_edge_cases() {
-    let config = InflateConfig { window_bits: 15 };
+    let config = InflateConfig::default().with_window_bits(15);
 
     let (result, err) = uncompress_slice(&mut [], &[], config);
     assert_eq!(err, ReturnCode::DataError);
@@ -1215,10 +1215,7 @@ fn gzip_header_fields_insufficient_space() {
     let comment =
         "indirectly. They, along with many frameworks that require async, do their best to hide\0";
 
-    let config = DeflateConfig {
-        window_bits: 31,
-        ..Default::default()
-    };
+    let config = DeflateConfig::default().with_window_bits(31);
 
     let mut stream = MaybeUninit::<libz_rs_sys::z_stream>::zeroed();
 
@@ -1426,10 +1423,7 @@ fn gzip_chunked(chunk_size: usize) {
     let comment =
         "indirectly. They, along with many frameworks that require async, do their best to hide\0";
 
-    let config = DeflateConfig {
-        window_bits: 31,
-        ..Default::default()
-    };
+    let config = DeflateConfig::default().with_window_bits(31);
 
     let mut stream = MaybeUninit::<libz_rs_sys::z_stream>::zeroed();
 
@@ -1842,10 +1836,7 @@ fn test_inflate_flush_block() {
     // Create a compressed vector of random data that's bigger then the zlib block size
     let mut data = vec![0u8; 160000];
     prng_bytes(314159, &mut data, 4);
-    let config = DeflateConfig {
-        window_bits,
-        ..DeflateConfig::default()
-    };
+    let config = DeflateConfig::default().with_window_bits(window_bits);
     let mut output = vec![0u8; 80000];
     // Compress the data
     let (compressed_data, return_code) = compress_slice(&mut output, &data, config);
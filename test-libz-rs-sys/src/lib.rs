@@ -4,6 +4,8 @@ pub mod end_to_end;
 mod helpers;
 #[cfg(test)]
 mod inflate;
+#[cfg(all(test, not(target_family = "wasm")))]
+mod zlib_identical;
 #[cfg(test)]
 mod zlib_ng_cve;
 
@@ -347,10 +349,7 @@ mod null {
         let input = b"Ferris";
         let mut buf = [0; 64];
 
-        let config = zlib_rs::deflate::DeflateConfig {
-            window_bits: 16 + 15,
-            ..Default::default()
-        };
+        let config = zlib_rs::deflate::DeflateConfig::default().with_window_bits(16 + 15);
 
         #[cfg(not(miri))]
         assert_eq_rs_ng!({
@@ -1731,3 +1730,39 @@ fn deflate_stored_window_out_of_bounds() {
         let _ = unsafe { deflateEnd(strm) };
     });
 }
+
+#[test]
+#[cfg_attr(miri, ignore = "too slow")]
+fn deflate_window_bits_8() {
+    use zlib_rs::{
+        deflate::{compress_bound, compress_slice, DeflateConfig},
+        inflate::InflateConfig,
+        ReturnCode,
+    };
+
+    // zlib-ng turns a window_bits of 8 into 9, zlib-rs uses a 256-byte window and advertises it in
+    // the header. zlib-ng decompresses both.
+    let input = include_bytes!("test-data/paper-100k.pdf");
+
+    for level in 0..=9 {
+        let config = DeflateConfig::new(level).with_window_bits(8);
+
+        let mut output_rs = vec![0; compress_bound(input.len())];
+        let (output_rs, err) = compress_slice(&mut output_rs, input, config);
+        assert_eq!(err, ReturnCode::Ok);
+
+        let mut output_ng = vec![0; compress_bound(input.len())];
+        let (output_ng, err) = helpers::compress_slice_ng(&mut output_ng, input, config);
+        assert_eq!(err, ReturnCode::Ok);
+
+        // CINFO, the base-2 logarithm of the window size minus 8
+        assert_eq!(output_rs[0] >> 4, 0, "level {level}");
+        assert_eq!(output_ng[0] >> 4, 1, "level {level}");
+
+        let mut decompressed = vec![0; input.len()];
+        let (decompressed, err) =
+            helpers::uncompress_slice_ng(&mut decompressed, output_rs, InflateConfig::default());
+        assert_eq!(err, ReturnCode::Ok, "level {level}");
+        assert!(decompressed == input, "level {level}");
+    }
+}
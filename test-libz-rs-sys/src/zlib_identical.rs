@@ -0,0 +1,95 @@
+//! Compare `DeflateConfig::zlib_identical` output with a stock zlib.
+//!
+//! The stock zlib is loaded at runtime, e.g.
+//!
+//! > DYNAMIC_LIBZ_SYS=/usr/lib/x86_64-linux-gnu/libz.so cargo test zlib_identical -- --ignored
+//!
+//! CI runs these in the `zlib-identical` job. The golden outputs in the `zlib_identical_output`
+//! test of `zlib-rs` cover every level and strategy without a stock zlib.
+
+use zlib_rs::{
+    deflate::{compress_bound, compress_slice, DeflateConfig, Method, Strategy},
+    ReturnCode,
+};
+
+const STRATEGIES: [Strategy; 5] = [
+    Strategy::Default,
+    Strategy::Filtered,
+    Strategy::HuffmanOnly,
+    Strategy::Rle,
+    Strategy::Fixed,
+];
+
+fn assert_identical(input: &[u8], config: DeflateConfig) {
+    let config = config.with_zlib_identical(true);
+
+    let mut output_zlib = vec![0; compress_bound(input.len())];
+    let (output_zlib, err) = dynamic_libz_sys::compress_slice(
+        &mut output_zlib,
+        input,
+        config.level,
+        config.method as i32,
+        config.window_bits,
+        config.mem_level,
+        config.strategy as i32,
+    );
+    assert_eq!(err, 0);
+
+    let mut output_rs = vec![0; compress_bound(input.len())];
+    let (output_rs, err) = compress_slice(&mut output_rs, input, config);
+    assert_eq!(err, ReturnCode::Ok);
+
+    assert!(
+        output_rs == output_zlib,
+        "output differs for {config:?} (zlib: {} bytes, rs: {} bytes)",
+        output_zlib.len(),
+        output_rs.len(),
+    );
+}
+
+fn all_levels_and_strategies(input: &[u8]) {
+    for level in 0..=9 {
+        for strategy in STRATEGIES {
+            let config = DeflateConfig::new(level).with_strategy(strategy);
+
+            assert_identical(input, config);
+        }
+    }
+}
+
+#[test]
+#[ignore = "set DYNAMIC_LIBZ_SYS to a stock zlib build"]
+fn paper_100k() {
+    all_levels_and_strategies(include_bytes!("test-data/paper-100k.pdf"));
+}
+
+#[test]
+#[ignore = "set DYNAMIC_LIBZ_SYS to a stock zlib build"]
+fn lcet10() {
+    all_levels_and_strategies(include_bytes!("test-data/lcet10.txt"));
+}
+
+#[test]
+#[ignore = "set DYNAMIC_LIBZ_SYS to a stock zlib build"]
+fn fireworks() {
+    all_levels_and_strategies(include_bytes!("test-data/fireworks.jpg"));
+}
+
+#[test]
+#[ignore = "set DYNAMIC_LIBZ_SYS to a stock zlib build"]
+fn window_bits_and_mem_level() {
+    let input = include_bytes!("test-data/lcet10.txt");
+
+    for window_bits in [9, 12, 15, -15, 31] {
+        for mem_level in [1, 4, 8, 9] {
+            for level in [1, 4, 6, 9] {
+                let config = DeflateConfig::new(level)
+                    .with_method(Method::Deflated)
+                    .with_window_bits(window_bits)
+                    .with_mem_level(mem_level);
+
+                assert_identical(input, config);
+            }
+        }
+    }
+}
@@ -5,11 +5,9 @@ use crate::helpers::compress_slice_ng;
 fn cve_test(input: &[u8]) {
     let mut output_ng = [0; 1 << 17];
     // flush type 4 = Finish is the default
-    let config = DeflateConfig {
-        window_bits: 15,
-        mem_level: 1,
-        ..DeflateConfig::default()
-    };
+    let config = DeflateConfig::default()
+        .with_window_bits(15)
+        .with_mem_level(1);
     let (output_ng, err) = compress_slice_ng(&mut output_ng, input, config);
     assert_eq!(err, ReturnCode::Ok);
 
@@ -20,7 +18,7 @@ fn cve_test(input: &[u8]) {
     assert_eq!(output_ng, output_rs);
 
     let mut output = vec![0; input.len()];
-    let config = zlib_rs::inflate::InflateConfig { window_bits: 15 };
+    let config = zlib_rs::inflate::InflateConfig::default().with_window_bits(15);
     let (output, err) = zlib_rs::inflate::uncompress_slice(&mut output, output_rs, config);
     assert_eq!(err, ReturnCode::Ok);
 
@@ -52,6 +52,36 @@ impl Crc32Fold {
         }
     }
 
+    /// Continue a computation that had reached `crc`. The state of the SIMD accumulators cannot be
+    /// recovered from a checksum, so instead this folds in 4 bytes whose crc32 is `crc`.
+    #[cfg(feature = "rust-allocator")]
+    pub(crate) fn resume(crc: u32) -> Self {
+        const TABLE: [u32; 256] = braid::build_crc32_table::<256, 1, 1>()[0];
+
+        // One step of the byte-wise algorithm is `reg = (reg >> 8) ^ TABLE[(reg ^ byte) as u8]`.
+        // The top byte of the table entries is unique, so walking back from the final register
+        // determines the table index used in each step.
+        let mut indices = [0u8; 4];
+        let mut reg = !crc;
+        for index in indices.iter_mut().rev() {
+            let i = TABLE.iter().position(|t| t >> 24 == reg >> 24).unwrap();
+            *index = i as u8;
+            reg = (reg ^ TABLE[i]) << 8;
+        }
+
+        // then pick the bytes that produce those indices, starting from the initial register
+        let mut bytes = [0u8; 4];
+        let mut reg = !CRC32_INITIAL_VALUE;
+        for (byte, index) in bytes.iter_mut().zip(indices) {
+            *byte = reg as u8 ^ index;
+            reg = (reg >> 8) ^ TABLE[index as usize];
+        }
+
+        let mut fold = Self::new();
+        fold.fold(&bytes, CRC32_INITIAL_VALUE);
+        fold
+    }
+
     #[cfg_attr(not(target_arch = "x86_64"), allow(unused))]
     pub(crate) fn is_pclmulqdq_enabled() -> bool {
         crate::cpu_features::is_enabled_pclmulqdq()
@@ -123,6 +153,17 @@ mod test {
         assert_eq!(crc32(CRC32_INITIAL_VALUE, &INPUT), h.finalize());
     }
 
+    #[test]
+    fn resume() {
+        for split in [0, 1, 5, 64, 200, 1000] {
+            let (start, end) = INPUT.split_at(split);
+
+            let mut fold = Crc32Fold::resume(crc32(CRC32_INITIAL_VALUE, start));
+            fold.fold(end, CRC32_INITIAL_VALUE);
+            assert_eq!(fold.finish(), crc32(CRC32_INITIAL_VALUE, &INPUT));
+        }
+    }
+
     #[test]
     fn test_crc32_fold_align() {
         // SIMD algorithm is sensitive to alignment;
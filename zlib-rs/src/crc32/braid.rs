@@ -36,7 +36,8 @@ impl<const N: usize> Crc32BraidTable<N> {
 // multiplication and exponentiation, respectively) from the paper,
 // but with identical results. This function is const, so it should be
 // fully evaluated at compile time.
-const fn build_crc32_table<const A: usize, const W: usize, const N: usize>() -> [[u32; A]; W] {
+pub(crate) const fn build_crc32_table<const A: usize, const W: usize, const N: usize>(
+) -> [[u32; A]; W] {
     let mut arr = [[0u32; A]; W];
     let mut i = 0;
     while i < W {
@@ -1,5 +1,11 @@
 #![warn(unsafe_op_in_unsafe_fn)]
-use core::{ffi::CStr, marker::PhantomData, mem::MaybeUninit, ops::ControlFlow};
+use core::{
+    ffi::CStr,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ops::{ControlFlow, Range},
+    ptr::NonNull,
+};
 
 use crate::{
     adler32::adler32,
@@ -13,17 +19,26 @@ use crate::{
 };
 
 use self::{
-    algorithm::CONFIGURATION_TABLE,
-    hash_calc::{Crc32HashCalc, HashCalcVariant, RollHashCalc, StandardHashCalc},
+    algorithm::{CompressFunc, Config, CONFIGURATION_TABLE, ZLIB_CONFIGURATION_TABLE},
+    hash_calc::{Crc32HashCalc, HashCalcVariant, RollHashCalc, StandardHashCalc, ZlibHashCalc},
     pending::Pending,
     trees_tbl::STATIC_LTREE,
     window::Window,
 };
 
+#[cfg(feature = "std")]
+pub use self::adaptive::{deflate_adaptive, AdaptiveLevel, Target};
+
+#[cfg(feature = "std")]
+mod adaptive;
 mod algorithm;
+mod block_split;
+#[cfg(feature = "rust-allocator")]
+pub mod checkpoint;
 mod compare256;
 mod hash_calc;
 mod longest_match;
+mod package_merge;
 mod pending;
 mod slide_hash;
 mod trees_tbl;
@@ -123,12 +138,70 @@ impl TryFrom<i32> for Method {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "__internal-fuzz", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
 pub struct DeflateConfig {
     pub level: i32,
     pub method: Method,
     pub window_bits: i32,
     pub mem_level: i32,
     pub strategy: Strategy,
+    /// Produce exactly the same bytes as stock zlib would for the same configuration and the
+    /// same sequence of `deflate` calls.
+    ///
+    /// By default this crate follows zlib-ng, which uses different hashing and different match
+    /// finding algorithms (e.g. `deflate_quick` and `deflate_medium`). Those are faster, but their
+    /// output differs from what stock zlib produces. With this flag set, zlib's `deflate_fast`,
+    /// `deflate_slow` and `deflate_rle` and zlib's level table are used instead.
+    pub zlib_identical: bool,
+    /// Split blocks at the points where the statistics of the data change, so that every part
+    /// gets huffman trees that fit it well.
+    ///
+    /// By default a block ends when the symbol buffer (sized by `mem_level`) is full, or on an
+    /// explicit flush. With this flag set, each such block is first checked for better split
+    /// points using an estimate of the huffman cost. This improves the compression of
+    /// heterogeneous data (e.g. a mix of text and binary data), at the cost of some speed.
+    /// It has no effect on levels 0 and 1 with the default strategy (they do not buffer symbols),
+    /// with [`Strategy::Fixed`], and when [`zlib_identical`](Self::zlib_identical) is set.
+    pub block_splitting: bool,
+    /// Use these match finding parameters instead of the entry for `level` in the level table.
+    ///
+    /// The `level` is still used for everything else, e.g. the level flags in the zlib and gzip
+    /// headers. [`init`] returns [`ReturnCode::StreamError`] when the configuration is not valid
+    /// (see [`LevelConfig`]), when `level` is 0, or when [`zlib_identical`](Self::zlib_identical)
+    /// is set.
+    pub level_config: Option<LevelConfig>,
+    /// Copy regions of input that look incompressible (e.g. encrypted or already compressed
+    /// data) into stored blocks, instead of searching them for matches.
+    ///
+    /// The input is checked in regions of 32K, based on how evenly the byte values are
+    /// distributed. Other regions are compressed normally. This saves most of the time spent on
+    /// data that would end up in stored blocks anyway, at the cost of a few bytes per switch
+    /// between the two. It has no effect on level 0, with [`Strategy::HuffmanOnly`] and
+    /// [`Strategy::Rle`], and when [`zlib_identical`](Self::zlib_identical) is set.
+    pub skip_incompressible: bool,
+    /// Use optimal length-limited huffman codes.
+    ///
+    /// The code lengths of deflate are limited to 15 bits (7 bits for the code that encodes the
+    /// code lengths). When a huffman code would be longer, zlib shortens it with a quick heuristic
+    /// that is not always optimal. With this flag set, the package-merge algorithm is used in that
+    /// case instead, which finds the best code within the limit. This mostly helps with skewed
+    /// distributions and large blocks, i.e. with high levels and memory levels. It has no effect
+    /// when [`zlib_identical`](Self::zlib_identical) is set.
+    pub optimal_huffman: bool,
+    /// Produce Deflate64 (ZIP compression method 9) instead of deflate.
+    ///
+    /// Deflate64 has a 64K window, and matches can be up to 64K long. This improves the
+    /// compression of large repetitive inputs (e.g. disk images), but the output can only be
+    /// decompressed by decoders that support Deflate64, see
+    /// [`InflateConfig::deflate64`](crate::inflate::InflateConfig::deflate64).
+    ///
+    /// Deflate64 streams have no zlib or gzip wrapper, so `window_bits` must be negative. Its
+    /// value is otherwise ignored. [`init`] returns [`ReturnCode::StreamError`] for a zlib or gzip
+    /// wrapper, and when [`zlib_identical`](Self::zlib_identical) is set. All levels above 0 use
+    /// lazy matching (the algorithm of a [`level_config`](Self::level_config) is ignored), and
+    /// [`block_splitting`](Self::block_splitting) and
+    /// [`skip_incompressible`](Self::skip_incompressible) have no effect.
+    pub deflate64: bool,
 }
 
 #[cfg(any(test, feature = "__internal-test"))]
@@ -156,6 +229,12 @@ impl quickcheck::Arbitrary for DeflateConfig {
                     Strategy::Fixed,
                 ])
                 .unwrap(),
+            zlib_identical: false,
+            block_splitting: false,
+            level_config: None,
+            skip_incompressible: false,
+            optimal_huffman: false,
+            deflate64: false,
         }
     }
 }
@@ -167,6 +246,72 @@ impl DeflateConfig {
             ..Self::default()
         }
     }
+
+    /// Set [`level`](Self::level).
+    pub fn with_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Set [`method`](Self::method).
+    pub fn with_method(mut self, method: Method) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Set [`window_bits`](Self::window_bits).
+    pub fn with_window_bits(mut self, window_bits: i32) -> Self {
+        self.window_bits = window_bits;
+        self
+    }
+
+    /// Set [`mem_level`](Self::mem_level).
+    pub fn with_mem_level(mut self, mem_level: i32) -> Self {
+        self.mem_level = mem_level;
+        self
+    }
+
+    /// Set [`strategy`](Self::strategy).
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set [`zlib_identical`](Self::zlib_identical).
+    pub fn with_zlib_identical(mut self, zlib_identical: bool) -> Self {
+        self.zlib_identical = zlib_identical;
+        self
+    }
+
+    /// Set [`block_splitting`](Self::block_splitting).
+    pub fn with_block_splitting(mut self, block_splitting: bool) -> Self {
+        self.block_splitting = block_splitting;
+        self
+    }
+
+    /// Set [`level_config`](Self::level_config).
+    pub fn with_level_config(mut self, level_config: Option<LevelConfig>) -> Self {
+        self.level_config = level_config;
+        self
+    }
+
+    /// Set [`skip_incompressible`](Self::skip_incompressible).
+    pub fn with_skip_incompressible(mut self, skip_incompressible: bool) -> Self {
+        self.skip_incompressible = skip_incompressible;
+        self
+    }
+
+    /// Set [`optimal_huffman`](Self::optimal_huffman).
+    pub fn with_optimal_huffman(mut self, optimal_huffman: bool) -> Self {
+        self.optimal_huffman = optimal_huffman;
+        self
+    }
+
+    /// Set [`deflate64`](Self::deflate64).
+    pub fn with_deflate64(mut self, deflate64: bool) -> Self {
+        self.deflate64 = deflate64;
+        self
+    }
 }
 
 impl Default for DeflateConfig {
@@ -177,6 +322,109 @@ impl Default for DeflateConfig {
             window_bits: MAX_WBITS,
             mem_level: DEF_MEM_LEVEL,
             strategy: Strategy::Default,
+            zlib_identical: false,
+            block_splitting: false,
+            level_config: None,
+            skip_incompressible: false,
+            optimal_huffman: false,
+            deflate64: false,
+        }
+    }
+}
+
+/// The match finding algorithm of a [`LevelConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "__internal-fuzz", derive(arbitrary::Arbitrary))]
+pub enum Algorithm {
+    /// Greedy matching with a single hash lookup, and static huffman trees. Used by level 1.
+    Quick,
+    /// The matching of `Quick`, but the symbols of a block are collected first, so that the block
+    /// can use dynamic huffman trees. Compresses text noticeably better than `Quick`, at a small
+    /// cost in speed.
+    QuickDynamic,
+    /// Greedy matching. Used by level 2.
+    Fast,
+    /// Matching with a limited lookahead for a better match. Used by levels 3 to 6.
+    Medium,
+    /// Lazy matching. Used by levels 7 to 9.
+    Slow,
+}
+
+/// The hash function that is used to find match candidates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "__internal-fuzz", derive(arbitrary::Arbitrary))]
+pub enum HashFunction {
+    /// Pick a hash function based on `max_chain`, like the built-in levels do.
+    #[default]
+    Auto,
+    /// Hash 4 bytes with a multiplicative hash.
+    Standard,
+    /// Hash 4 bytes with the crc32 instruction. Only available on some CPUs.
+    Crc32,
+    /// Hash 3 bytes with a rolling hash. Used by level 9.
+    Roll,
+}
+
+/// An entry of the compression level table, see [`DeflateConfig::level_config`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "__internal-fuzz", derive(arbitrary::Arbitrary))]
+pub struct LevelConfig {
+    pub algorithm: Algorithm,
+    pub hash_function: HashFunction,
+    /// Reduce the lazy search above this match length.
+    pub good_length: u16,
+    /// Do not perform a lazy search above this match length.
+    pub max_lazy: u16,
+    /// Stop searching above this match length.
+    pub nice_length: u16,
+    /// The maximum number of hash chain entries that is searched.
+    pub max_chain: u16,
+}
+
+impl LevelConfig {
+    /// The built-in configuration of `level`, or `None` for levels that do not search for
+    /// matches (level 0 and invalid levels).
+    pub fn for_level(level: i32) -> Option<Self> {
+        let algorithm = match level {
+            1 => Algorithm::Quick,
+            2 => Algorithm::Fast,
+            3..=6 => Algorithm::Medium,
+            7..=9 => Algorithm::Slow,
+            _ => return None,
+        };
+
+        let config = &CONFIGURATION_TABLE[level as usize];
+
+        Some(Self {
+            algorithm,
+            hash_function: HashFunction::Auto,
+            good_length: config.good_length,
+            max_lazy: config.max_lazy,
+            nice_length: config.nice_length,
+            max_chain: config.max_chain,
+        })
+    }
+
+    /// A configuration is valid when the lengths are at most 258 (the longest match), the
+    /// algorithms that search hash chains search at least 4 entries, and the hash function is
+    /// supported by this CPU.
+    fn is_valid(&self) -> bool {
+        let max = STD_MAX_MATCH as u16;
+
+        let lengths = self.good_length <= max && self.max_lazy <= max && self.nice_length <= max;
+        let chain = matches!(self.algorithm, Algorithm::Quick | Algorithm::QuickDynamic)
+            || self.max_chain >= 4;
+        let hash = self.hash_function != HashFunction::Crc32 || Crc32HashCalc::is_supported();
+
+        lengths && chain && hash
+    }
+
+    fn hash_calc_variant(&self) -> HashCalcVariant {
+        match self.hash_function {
+            HashFunction::Auto => HashCalcVariant::for_max_chain_length(self.max_chain as usize),
+            HashFunction::Standard => HashCalcVariant::Standard,
+            HashFunction::Crc32 => HashCalcVariant::Crc32,
+            HashFunction::Roll => HashCalcVariant::Roll,
         }
     }
 }
@@ -188,6 +436,12 @@ pub fn init(stream: &mut z_stream, config: DeflateConfig) -> ReturnCode {
         mut window_bits,
         mem_level,
         strategy,
+        zlib_identical,
+        block_splitting,
+        level_config,
+        skip_incompressible,
+        optimal_huffman,
+        deflate64,
     } = config;
 
     /* Todo: ignore strm->next_in if we use it as window */
@@ -237,11 +491,25 @@ pub fn init(stream: &mut z_stream, config: DeflateConfig) -> ReturnCode {
         return ReturnCode::StreamError;
     }
 
-    let window_bits = if window_bits == 8 {
-        9 /* until 256-byte window bug fixed */
-    } else {
-        window_bits as usize
+    if let Some(level_config) = level_config {
+        if level == 0 || zlib_identical || !level_config.is_valid() {
+            return ReturnCode::StreamError;
+        }
+    }
+
+    if deflate64 && (wrap != 0 || zlib_identical) {
+        return ReturnCode::StreamError;
+    }
+
+    // A 256-byte window cannot hold the lookahead that the match finders need, so the buffers
+    // are sized for 512 bytes. Distances are still at most `w_size - MIN_LOOKAHEAD`, which is less
+    // than 256, so the zlib header can advertise a 256-byte window. Stock zlib advertises 512 bytes.
+    let window_bits = match window_bits {
+        _ if deflate64 => 16,
+        8 if zlib_identical => 9,
+        _ => window_bits as usize,
     };
+    let buffer_bits = Ord::max(window_bits, 9);
 
     let alloc = Allocator {
         zalloc: stream.zalloc.unwrap(),
@@ -255,8 +523,8 @@ pub fn init(stream: &mut z_stream, config: DeflateConfig) -> ReturnCode {
         return ReturnCode::MemError;
     };
 
-    let w_size = 1 << window_bits;
-    let window = Window::new_in(&alloc, window_bits);
+    let w_size = 1 << buffer_bits;
+    let window = Window::new_in(&alloc, buffer_bits);
 
     let prev = alloc.allocate_slice_raw::<u16>(w_size);
     let head = alloc.allocate_raw::<[u16; HASH_SIZE]>();
@@ -372,6 +640,21 @@ pub fn init(stream: &mut z_stream, config: DeflateConfig) -> ReturnCode {
 
         // just provide a valid default; gets set properly later
         hash_calc_variant: HashCalcVariant::Standard,
+        hash_bits: mem_level as usize + 7,
+
+        zlib_identical,
+        block_splitting: block_splitting && !zlib_identical && !deflate64,
+        level_config,
+        skip_incompressible: skip_incompressible && !zlib_identical && !deflate64,
+        bypassing: false,
+        bypass_region: usize::MAX,
+        optimal_huffman: optimal_huffman && !zlib_identical,
+        deflate64,
+        observer: None,
+        quick_block_start: 0,
+        quick_matches: 0,
+        progress: None,
+        aborted: false,
     };
 
     unsafe { state_allocation.write(state) };
@@ -400,13 +683,16 @@ pub fn params(stream: &mut DeflateStream, level: i32, strategy: Strategy) -> Ret
 
     let level = level as i8;
 
-    let func = CONFIGURATION_TABLE[stream.state.level as usize].func;
+    // a custom level configuration only applies to the level it was configured for
+    let func = stream.state.compress_func();
+    let new_func = match stream.state.level_config {
+        Some(_) if level == stream.state.level => func,
+        _ => stream.state.configuration_table()[level as usize].func,
+    };
 
     let state = &mut stream.state;
 
-    if (strategy != state.strategy || func != CONFIGURATION_TABLE[level as usize].func)
-        && state.last_flush != -2
-    {
+    if (strategy != state.strategy || func != new_func) && state.last_flush != -2 {
         // Flush the last buffer.
         let err = deflate(stream, DeflateFlush::Block);
         if err == ReturnCode::StreamError {
@@ -434,6 +720,7 @@ pub fn params(stream: &mut DeflateStream, level: i32, strategy: Strategy) -> Ret
             state.matches = 0;
         }
 
+        state.level_config = None;
         lm_set_level(state, level);
     }
 
@@ -501,6 +788,11 @@ pub fn set_dictionary(stream: &mut DeflateStream, mut dictionary: &[u8]) -> Retu
     state.prev_length = 0;
     state.match_available = false;
 
+    if state.zlib_identical {
+        state.match_length = STD_MIN_MATCH - 1;
+        state.prev_length = STD_MIN_MATCH - 1;
+    }
+
     // restore the state
     stream.next_in = next;
     stream.avail_in = avail;
@@ -509,6 +801,164 @@ pub fn set_dictionary(stream: &mut DeflateStream, mut dictionary: &[u8]) -> Retu
     ReturnCode::Ok
 }
 
+/// A preset dictionary with precomputed hash chains, see [`set_prepared_dictionary`].
+///
+/// [`set_dictionary`] inserts every position of the dictionary into the hash table, which costs
+/// about as much as compressing the dictionary. A prepared dictionary does that work once: it
+/// records the window and hash chains that [`set_dictionary`] produces, and attaching it to a
+/// stream is just a copy.
+#[cfg(feature = "rust-allocator")]
+#[derive(Debug, Clone)]
+pub struct PreparedDictionary {
+    /// the window after the dictionary was inserted
+    window: alloc::vec::Vec<u8>,
+    /// the used entries of the hash table, as `(hash, position)`
+    head: alloc::vec::Vec<(u16, u16)>,
+    /// the hash chains of the dictionary positions, indexed like `State::prev`
+    prev: alloc::vec::Vec<u16>,
+    ins_h: usize,
+    insert: usize,
+
+    /// the length of the whole dictionary
+    len: usize,
+    /// the Adler-32 checksum of the whole dictionary
+    id: u32,
+
+    // the hash chains are only valid for streams that hash the same way
+    w_bits: usize,
+    hash_calc_variant: HashCalcVariant,
+    hash_bits: usize,
+}
+
+#[cfg(feature = "rust-allocator")]
+impl PreparedDictionary {
+    /// Prepare `dictionary` for streams that are initialized with `config`. The wrapper (zlib,
+    /// gzip or raw) is irrelevant, but the window size, level and memory level determine how
+    /// strings are hashed.
+    pub fn new(dictionary: &[u8], config: DeflateConfig) -> Result<Self, ReturnCode> {
+        // gzip streams don't support dictionaries, so use a raw stream
+        let config = DeflateConfig {
+            window_bits: -(config.window_bits.abs() & 15),
+            ..config
+        };
+
+        let mut stream = z_stream::default();
+        let err = init(&mut stream, config);
+        if err != ReturnCode::Ok {
+            return Err(err);
+        }
+
+        // SAFETY: the stream was successfully initialized
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let err = set_dictionary(stream, dictionary);
+        if err != ReturnCode::Ok {
+            let _ = end(stream);
+            return Err(err);
+        }
+
+        let state = &stream.state;
+
+        let head = state
+            .head
+            .as_slice()
+            .iter()
+            .enumerate()
+            .filter(|(_, &pos)| pos != 0)
+            .map(|(hash, &pos)| (hash as u16, pos))
+            .collect();
+
+        let prepared = Self {
+            window: state.window.filled()[..state.strstart].to_vec(),
+            head,
+            prev: state.prev.as_slice()[..Ord::min(state.strstart, state.w_size)].to_vec(),
+            ins_h: state.ins_h,
+            insert: state.insert,
+            len: dictionary.len(),
+            id: adler32(ADLER32_INITIAL_VALUE as u32, dictionary),
+            w_bits: state.w_bits,
+            hash_calc_variant: state.hash_calc_variant,
+            hash_bits: state.hash_bits,
+        };
+
+        let _ = end(stream);
+
+        Ok(prepared)
+    }
+
+    /// The Adler-32 checksum of the dictionary, which zlib streams use to identify it.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// Like [`set_dictionary`], but with hash chains that were computed in advance.
+///
+/// The result is the same as calling [`set_dictionary`] with the original dictionary. When the
+/// stream already has history, or hashes differently than the stream the dictionary was prepared
+/// for (see [`PreparedDictionary::new`]), the dictionary is inserted the slow way.
+#[cfg(feature = "rust-allocator")]
+pub fn set_prepared_dictionary(
+    stream: &mut DeflateStream,
+    dictionary: &PreparedDictionary,
+) -> ReturnCode {
+    let state = &mut stream.state;
+
+    let wrap = state.wrap;
+
+    if wrap == 2 || (wrap == 1 && state.status != Status::Init) || state.lookahead != 0 {
+        return ReturnCode::StreamError;
+    }
+
+    let compatible = state.strstart == 0
+        && state.w_bits == dictionary.w_bits
+        && state.hash_calc_variant == dictionary.hash_calc_variant
+        && state.hash_bits == dictionary.hash_bits;
+
+    // when using zlib wrappers, compute Adler-32 for provided dictionary
+    let adler = match wrap {
+        1 => crate::adler32_combine(stream.adler as u32, dictionary.id, dictionary.len as u64),
+        _ => stream.adler as u32,
+    };
+
+    if !compatible {
+        // the window contains (at least) the part of the dictionary that can be referenced
+        let err = set_dictionary(stream, &dictionary.window);
+        stream.adler = adler as z_checksum;
+        return err;
+    }
+
+    stream.adler = adler as z_checksum;
+
+    let state = &mut stream.state;
+    let len = dictionary.window.len();
+
+    state.window.initialize_at_least(len);
+    state.window.filled_mut()[..len].copy_from_slice(&dictionary.window);
+    state.window.initialize_out_of_bounds();
+
+    // the hash table is empty, because the stream has no history
+    let head = state.head.as_mut_slice();
+    for &(hash, pos) in &dictionary.head {
+        head[hash as usize] = pos;
+    }
+    state.prev.as_mut_slice()[..dictionary.prev.len()].copy_from_slice(&dictionary.prev);
+
+    state.ins_h = dictionary.ins_h;
+    state.strstart = len;
+    state.block_start = len as isize;
+    state.insert = dictionary.insert;
+    state.prev_length = 0;
+    state.match_available = false;
+
+    if state.zlib_identical {
+        state.match_length = STD_MIN_MATCH - 1;
+        state.prev_length = STD_MIN_MATCH - 1;
+    }
+
+    ReturnCode::Ok
+}
+
 pub fn prime(stream: &mut DeflateStream, mut bits: i32, value: i32) -> ReturnCode {
     // our logic actually supports up to 32 bits.
     debug_assert!(bits <= 16, "zlib only supports up to 16 bits here");
@@ -618,8 +1068,7 @@ pub fn copy<'a>(
     };
 
     let head = unsafe {
-        head.write_bytes(0, 1);
-        head.cast::<u16>().write(source_state.head.as_slice()[0]);
+        head.copy_from_nonoverlapping(source_state.head.as_slice().as_ptr().cast(), 1);
         WeakArrayMut::from_ptr(head)
     };
 
@@ -667,6 +1116,20 @@ pub fn copy<'a>(
         ins_h: source_state.ins_h,
         heap: source_state.heap.clone(),
         hash_calc_variant: source_state.hash_calc_variant,
+        hash_bits: source_state.hash_bits,
+        zlib_identical: source_state.zlib_identical,
+        block_splitting: source_state.block_splitting,
+        level_config: source_state.level_config,
+        skip_incompressible: source_state.skip_incompressible,
+        bypassing: source_state.bypassing,
+        bypass_region: source_state.bypass_region,
+        optimal_huffman: source_state.optimal_huffman,
+        deflate64: source_state.deflate64,
+        observer: None,
+        quick_block_start: source_state.quick_block_start,
+        quick_matches: source_state.quick_matches,
+        progress: None,
+        aborted: source_state.aborted,
         crc_fold: source_state.crc_fold,
         gzhead: None,
         gzindex: source_state.gzindex,
@@ -758,6 +1221,9 @@ fn reset_keep(stream: &mut DeflateStream) -> ReturnCode {
     };
 
     state.last_flush = -2;
+    state.aborted = false;
+    state.bypassing = false;
+    state.bypass_region = usize::MAX;
 
     state.zng_tr_init();
 
@@ -781,15 +1247,39 @@ fn lm_init(state: &mut State) {
     state.match_available = false;
     state.match_start = 0;
     state.ins_h = 0;
+
+    if state.zlib_identical {
+        // zlib's deflate_fast and deflate_slow rely on these starting out at STD_MIN_MATCH - 1
+        state.match_length = STD_MIN_MATCH - 1;
+        state.prev_length = STD_MIN_MATCH - 1;
+    }
 }
 
 fn lm_set_level(state: &mut State, level: i8) {
-    state.max_lazy_match = CONFIGURATION_TABLE[level as usize].max_lazy as usize;
-    state.good_match = CONFIGURATION_TABLE[level as usize].good_length as usize;
-    state.nice_match = CONFIGURATION_TABLE[level as usize].nice_length as usize;
-    state.max_chain_length = CONFIGURATION_TABLE[level as usize].max_chain as usize;
+    if let Some(config) = state.level_config {
+        state.max_lazy_match = config.max_lazy as usize;
+        state.good_match = config.good_length as usize;
+        state.nice_match = config.nice_length as usize;
+        state.max_chain_length = config.max_chain as usize;
+
+        state.hash_calc_variant = config.hash_calc_variant();
+        state.level = level;
+
+        return;
+    }
+
+    let config = &state.configuration_table()[level as usize];
+
+    state.max_lazy_match = config.max_lazy as usize;
+    state.good_match = config.good_length as usize;
+    state.nice_match = config.nice_length as usize;
+    state.max_chain_length = config.max_chain as usize;
 
-    state.hash_calc_variant = HashCalcVariant::for_max_chain_length(state.max_chain_length);
+    state.hash_calc_variant = if state.zlib_identical {
+        HashCalcVariant::Zlib
+    } else {
+        HashCalcVariant::for_max_chain_length(state.max_chain_length)
+    };
     state.level = level;
 }
 
@@ -869,6 +1359,9 @@ pub(crate) const L_CODES: usize = LITERALS + 1 + LENGTH_CODES;
 /// number of distance codes
 pub(crate) const D_CODES: usize = 30;
 
+/// number of distance codes of Deflate64, which adds two codes for distances of 32K and more
+pub(crate) const D_CODES_64: usize = 32;
+
 /// number of codes used to transfer the bit lengths
 const BL_CODES: usize = 19;
 
@@ -881,7 +1374,7 @@ const MAX_BITS: usize = 15;
 /// Bit length codes must not exceed MAX_BL_BITS bits
 const MAX_BL_BITS: usize = 7;
 
-pub(crate) const DIST_CODE_LEN: usize = 512;
+pub(crate) const DIST_CODE_LEN: usize = 768;
 
 struct BitWriter<'a> {
     pub(crate) pending: Pending<'a>, // output still pending
@@ -923,7 +1416,7 @@ const fn encode_len(ltree: &[Value], lc: u8) -> (u64, usize) {
 const fn encode_dist(dtree: &[Value], mut dist: u16) -> (u64, usize) {
     dist -= 1; /* dist is now the match distance - 1 */
     let code = State::d_code(dist as usize) as usize;
-    assert!(code < D_CODES, "bad d_code");
+    assert!(code < D_CODES_64, "bad d_code");
     // send_code_trace(s, code);
 
     /* Send the distance code */
@@ -940,6 +1433,84 @@ const fn encode_dist(dtree: &[Value], mut dist: u16) -> (u64, usize) {
     (match_bits, match_bits_len)
 }
 
+/// The maximum match length of Deflate64
+const MAX_MATCH_64: usize = STD_MIN_MATCH + u16::MAX as usize;
+
+/// A literal or match of a Deflate64 block, see [`symbols_64`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Symbol64 {
+    Literal(u8),
+    Match { len: usize, dist: u16 },
+}
+
+/// The symbols of a Deflate64 block. The symbol buffer stores lengths of at most
+/// [`STD_MAX_MATCH`], so a longer match is stored as a match of [`STD_MAX_MATCH`] bytes followed
+/// by more matches at the same distance. Those are combined here (copying twice from the same
+/// distance is the same as copying once), up to [`MAX_MATCH_64`] bytes.
+fn symbols_64(sym_buf: &[u8]) -> impl Iterator<Item = Symbol64> + '_ {
+    let mut symbols = sym_buf
+        .chunks_exact(3)
+        .map(|chunk| {
+            let [dist_low, dist_high, lc] = *chunk else {
+                unreachable!("out of bound access on the symbol buffer");
+            };
+
+            (u16::from_le_bytes([dist_low, dist_high]), lc)
+        })
+        .peekable();
+
+    core::iter::from_fn(move || {
+        let (dist, lc) = symbols.next()?;
+        if dist == 0 {
+            return Some(Symbol64::Literal(lc));
+        }
+
+        let mut len = lc as usize + STD_MIN_MATCH;
+        if len == STD_MAX_MATCH {
+            while let Some(&(next_dist, next_lc)) = symbols.peek() {
+                let next_len = next_lc as usize + STD_MIN_MATCH;
+                if next_dist != dist || len + next_len > MAX_MATCH_64 {
+                    break;
+                }
+
+                len += next_len;
+                symbols.next();
+            }
+        }
+
+        Some(Symbol64::Match { len, dist })
+    })
+}
+
+/// The length code of a Deflate64 match, relative to the first length code. In Deflate64 the last
+/// length code has 16 extra bits, so a length of [`STD_MAX_MATCH`] uses the code before it.
+const fn length_code_64(len: usize) -> usize {
+    if len < STD_MAX_MATCH {
+        self::trees_tbl::LENGTH_CODE[len - STD_MIN_MATCH] as usize
+    } else if len == STD_MAX_MATCH {
+        LENGTH_CODES - 2
+    } else {
+        LENGTH_CODES - 1
+    }
+}
+
+#[inline]
+const fn encode_len_64(ltree: &[Value], len: usize) -> (u64, usize) {
+    let code = length_code_64(len);
+
+    let lnode = ltree[code + LITERALS + 1];
+    let mut match_bits: u64 = lnode.code() as u64;
+    let mut match_bits_len = lnode.len() as usize;
+    let extra = StaticTreeDesc::EXTRA_LBITS_64[code] as usize;
+    if extra != 0 {
+        let lc = len - STD_MIN_MATCH - self::trees_tbl::BASE_LENGTH[code] as usize;
+        match_bits |= (lc as u64) << match_bits_len;
+        match_bits_len += extra;
+    }
+
+    (match_bits, match_bits_len)
+}
+
 impl<'a> BitWriter<'a> {
     pub(crate) const BIT_BUF_SIZE: u8 = 64;
 
@@ -968,6 +1539,11 @@ impl<'a> BitWriter<'a> {
         self.bit_buffer = self.bit_buffer.checked_shr(removed as u32).unwrap_or(0);
     }
 
+    /// The number of bits that are waiting to be written to the output.
+    fn pending_bits(&self) -> usize {
+        self.pending.pending().len() * 8 + self.bits_used as usize
+    }
+
     fn emit_align(&mut self) {
         debug_assert!(self.bits_used <= 64);
         let keep_bytes = self.bits_used.div_ceil(8);
@@ -1145,6 +1721,28 @@ impl<'a> BitWriter<'a> {
         self.emit_end_block(ltree, false)
     }
 
+    fn compress_block_64(&mut self, sym_buf: &[u8], ltree: &[Value], dtree: &[Value]) {
+        for symbol in symbols_64(sym_buf) {
+            match symbol {
+                Symbol64::Literal(lc) => {
+                    self.emit_lit(ltree, lc);
+                }
+                Symbol64::Match { len, dist } => {
+                    let (mut match_bits, mut match_bits_len) = encode_len_64(ltree, len);
+
+                    let (dist_match_bits, dist_match_bits_len) = encode_dist(dtree, dist);
+
+                    match_bits |= dist_match_bits << match_bits_len;
+                    match_bits_len += dist_match_bits_len;
+
+                    self.send_bits(match_bits, match_bits_len as u8);
+                }
+            }
+        }
+
+        self.emit_end_block(ltree, false)
+    }
+
     fn send_tree(&mut self, tree: &[Value], bl_tree: &[Value], max_code: usize) {
         /* tree: the tree to be scanned */
         /* max_code and its largest code of non zero frequency */
@@ -1237,8 +1835,8 @@ pub(crate) struct State<'a> {
     //    dyn_ltree: [Value; ],
     //    dyn_dtree: [Value; ],
     //    bl_tree: [Value; ],
-    l_desc: TreeDesc<HEAP_SIZE>,             /* literal and length tree */
-    d_desc: TreeDesc<{ 2 * D_CODES + 1 }>,   /* distance tree */
+    l_desc: TreeDesc<HEAP_SIZE>, /* literal and length tree */
+    d_desc: TreeDesc<{ 2 * D_CODES_64 + 1 }>, /* distance tree */
     bl_desc: TreeDesc<{ 2 * BL_CODES + 1 }>, /* Huffman tree for bit lengths */
 
     pub(crate) bl_count: [u16; MAX_BITS + 1],
@@ -1309,9 +1907,9 @@ pub(crate) struct State<'a> {
     /// bytes at end of window left to insert
     pub(crate) insert: usize,
 
-    pub(crate) w_size: usize,    /* LZ77 window size (32K by default) */
-    pub(crate) w_bits: usize,    /* log2(w_size)  (8..16) */
-    pub(crate) w_mask: usize,    /* w_size - 1 */
+    pub(crate) w_size: usize, /* LZ77 window size (32K by default, at least 512) */
+    pub(crate) w_bits: usize, /* log2 of the window size in the header (8..15), 16 for Deflate64 */
+    pub(crate) w_mask: usize, /* w_size - 1 */
     pub(crate) lookahead: usize, /* number of valid bytes ahead in window */
 
     pub(crate) prev: WeakSliceMut<'a, u16>,
@@ -1324,6 +1922,49 @@ pub(crate) struct State<'a> {
 
     pub(crate) hash_calc_variant: HashCalcVariant,
 
+    /// log2 of the hash table size used by [`HashCalcVariant::Zlib`]. Stock zlib scales its hash
+    /// table with the memory level: `hash_bits = mem_level + 7`.
+    pub(crate) hash_bits: usize,
+
+    /// Reproduce the output of stock zlib, see [`DeflateConfig::zlib_identical`].
+    pub(crate) zlib_identical: bool,
+
+    /// Split blocks based on their estimated cost, see [`DeflateConfig::block_splitting`].
+    block_splitting: bool,
+
+    /// Replaces the level table entry of `level`, see [`DeflateConfig::level_config`].
+    level_config: Option<LevelConfig>,
+
+    /// Copy incompressible regions into stored blocks, see [`DeflateConfig::skip_incompressible`].
+    pub(crate) skip_incompressible: bool,
+    /// The current region is copied into stored blocks.
+    pub(crate) bypassing: bool,
+    /// The number of input bytes since `bypassing` last changed.
+    pub(crate) bypass_region: usize,
+
+    /// Limit code lengths optimally, see [`DeflateConfig::optimal_huffman`].
+    optimal_huffman: bool,
+
+    /// Produce Deflate64, see [`DeflateConfig::deflate64`]. The window is 64K, and the hash chains
+    /// store positions modulo 64K (they are not moved when the window slides).
+    pub(crate) deflate64: bool,
+
+    /// Called for every block that is flushed. Only set for the duration of [`deflate_with_trace`].
+    observer: Option<NonNull<Observer<'static>>>,
+    /// Where the block that `deflate_quick` has open starts in the output, in bits, see
+    /// [`BlockTrace::size`].
+    pub(crate) quick_block_start: usize,
+
+    /// The number of matches in the block that `deflate_quick` has open, see [`Progress::matches`].
+    pub(crate) quick_matches: usize,
+
+    /// Called for every block that is flushed. Only set for the duration of
+    /// [`deflate_with_progress`].
+    progress: Option<NonNull<ProgressCallback<'static>>>,
+
+    /// The progress callback aborted the stream. Until it is reset, deflate returns an error.
+    pub(crate) aborted: bool,
+
     crc_fold: crate::crc32::Crc32Fold,
     gzhead: Option<&'a mut gz_header>,
     gzindex: usize,
@@ -1365,10 +2006,28 @@ enum DataType {
 impl<'a> State<'a> {
     pub const BIT_BUF_SIZE: u8 = BitWriter::BIT_BUF_SIZE;
 
+    /// The longest distance that the match finders use. It is shorter than the window size in the
+    /// header, also for 256-byte windows where `w_size` is 512.
     pub(crate) fn max_dist(&self) -> usize {
         self.w_size - MIN_LOOKAHEAD
     }
 
+    /// The level table in use: zlib-ng's, or stock zlib's when producing zlib-identical output.
+    pub(crate) fn configuration_table(&self) -> &'static [Config; 10] {
+        match self.zlib_identical {
+            true => &ZLIB_CONFIGURATION_TABLE,
+            false => &CONFIGURATION_TABLE,
+        }
+    }
+
+    /// The algorithm of the current level, or of the custom level configuration.
+    pub(crate) fn compress_func(&self) -> CompressFunc {
+        match self.level_config {
+            Some(config) => algorithm::compress_func(config.algorithm),
+            None => self.configuration_table()[self.level as usize].func,
+        }
+    }
+
     // TODO untangle this mess! zlib uses the same field differently based on compression level
     // we should just have 2 fields for clarity!
     pub(crate) fn max_insert_length(&self) -> usize {
@@ -1386,9 +2045,10 @@ impl<'a> State<'a> {
         match self.hash_calc_variant {
             HashCalcVariant::Standard => StandardHashCalc::update_hash(h, val),
             // SAFETY: self.hash_calc_variant is set by HashCalcVariant::for_max_chain_length,
-            // which avoids choosing Crc32 if the system doesn't have support.
+            // or from a validated LevelConfig. Both avoid Crc32 if the system doesn't support it.
             HashCalcVariant::Crc32 => unsafe { Crc32HashCalc::update_hash(h, val) },
             HashCalcVariant::Roll => RollHashCalc::update_hash(h, val),
+            HashCalcVariant::Zlib => ZlibHashCalc::update_hash(self.hash_bits, h, val),
         }
     }
 
@@ -1397,9 +2057,10 @@ impl<'a> State<'a> {
         match self.hash_calc_variant {
             HashCalcVariant::Standard => StandardHashCalc::quick_insert_string(self, string),
             // SAFETY: self.hash_calc_variant is set by HashCalcVariant::for_max_chain_length,
-            // which avoids choosing Crc32 if the system doesn't have support.
+            // or from a validated LevelConfig. Both avoid Crc32 if the system doesn't support it.
             HashCalcVariant::Crc32 => unsafe { Crc32HashCalc::quick_insert_string(self, string) },
             HashCalcVariant::Roll => RollHashCalc::quick_insert_string(self, string),
+            HashCalcVariant::Zlib => ZlibHashCalc::quick_insert_string(self, string),
         }
     }
 
@@ -1408,9 +2069,10 @@ impl<'a> State<'a> {
         match self.hash_calc_variant {
             HashCalcVariant::Standard => StandardHashCalc::insert_string(self, string, count),
             // SAFETY: self.hash_calc_variant is set by HashCalcVariant::for_max_chain_length,
-            // which avoids choosing Crc32 if the system doesn't have support.
+            // or from a validated LevelConfig. Both avoid Crc32 if the system doesn't support it.
             HashCalcVariant::Crc32 => unsafe { Crc32HashCalc::insert_string(self, string, count) },
             HashCalcVariant::Roll => RollHashCalc::insert_string(self, string, count),
+            HashCalcVariant::Zlib => ZlibHashCalc::insert_string(self, string, count),
         }
     }
 
@@ -1451,7 +2113,7 @@ impl<'a> State<'a> {
         dist -= 1;
 
         assert!(
-            dist < self.max_dist() && Self::d_code(dist) < D_CODES as u8,
+            dist < self.max_dist() && (Self::d_code(dist) as usize) < self.d_desc.stat_desc.elems,
             "tally_dist: bad match"
         );
 
@@ -1493,9 +2155,15 @@ impl<'a> State<'a> {
         DataType::Binary
     }
 
-    fn compress_block_static_trees(&mut self) {
+    fn compress_block_static_trees(&mut self, symbols: Range<usize>) {
         let ltree = self::trees_tbl::STATIC_LTREE.as_slice();
-        for chunk in self.sym_buf.filled().chunks_exact(3) {
+        if self.deflate64 {
+            let dtree = self::trees_tbl::STATIC_DTREE.as_slice();
+            let sym_buf = &self.sym_buf.filled()[symbols];
+            return self.bit_writer.compress_block_64(sym_buf, ltree, dtree);
+        }
+
+        for chunk in self.sym_buf.filled()[symbols].chunks_exact(3) {
             let [dist_low, dist_high, lc] = *chunk else {
                 unreachable!("out of bound access on the symbol buffer");
             };
@@ -1509,9 +2177,17 @@ impl<'a> State<'a> {
         self.bit_writer.emit_end_block(ltree, false)
     }
 
-    fn compress_block_dynamic_trees(&mut self) {
+    fn compress_block_dynamic_trees(&mut self, symbols: Range<usize>) {
+        if self.deflate64 {
+            return self.bit_writer.compress_block_64(
+                &self.sym_buf.filled()[symbols],
+                &self.l_desc.dyn_tree,
+                &self.d_desc.dyn_tree,
+            );
+        }
+
         self.bit_writer.compress_block_help(
-            self.sym_buf.filled(),
+            &self.sym_buf.filled()[symbols],
             &self.l_desc.dyn_tree,
             &self.d_desc.dyn_tree,
         );
@@ -1548,9 +2224,10 @@ impl<'a> State<'a> {
     }
 
     fn zng_tr_init(&mut self) {
-        self.l_desc.stat_desc = &StaticTreeDesc::L;
-
-        self.d_desc.stat_desc = &StaticTreeDesc::D;
+        (self.l_desc.stat_desc, self.d_desc.stat_desc) = match self.deflate64 {
+            false => (&StaticTreeDesc::L, &StaticTreeDesc::D),
+            true => (&StaticTreeDesc::L64, &StaticTreeDesc::D64),
+        };
 
         self.bl_desc.stat_desc = &StaticTreeDesc::BL;
 
@@ -1569,14 +2246,20 @@ impl<'a> State<'a> {
 
     /// initializes a new block
     fn init_block(&mut self) {
-        // Initialize the trees.
+        self.init_trees();
+        self.sym_buf.clear();
+        self.matches = 0;
+    }
+
+    /// Initialize the trees.
+    fn init_trees(&mut self) {
         // TODO would a memset also work here?
 
         for value in &mut self.l_desc.dyn_tree[..L_CODES] {
             *value.freq_mut() = 0;
         }
 
-        for value in &mut self.d_desc.dyn_tree[..D_CODES] {
+        for value in &mut self.d_desc.dyn_tree[..D_CODES_64] {
             *value.freq_mut() = 0;
         }
 
@@ -1590,8 +2273,51 @@ impl<'a> State<'a> {
         *self.l_desc.dyn_tree[END_BLOCK].freq_mut() = 1;
         self.opt_len = 0;
         self.static_len = 0;
-        self.sym_buf.clear();
+    }
+
+    /// Count the symbol frequencies of `sym_buf[symbols]`, like [`State::tally_lit`] and
+    /// [`State::tally_dist`] do when the symbols are first added. For Deflate64, long matches are
+    /// combined first, see [`symbols_64`].
+    fn count_symbols(&mut self, symbols: Range<usize>) {
+        // the matches of this part of the buffer, which is flushed as a block of its own
         self.matches = 0;
+
+        if self.deflate64 {
+            for symbol in symbols_64(&self.sym_buf.filled()[symbols]) {
+                match symbol {
+                    Symbol64::Literal(lc) => *self.l_desc.dyn_tree[lc as usize].freq_mut() += 1,
+                    Symbol64::Match { len, dist } => {
+                        self.matches += 1;
+                        let index = length_code_64(len) + LITERALS + 1;
+                        *self.l_desc.dyn_tree[index].freq_mut() += 1;
+
+                        let code = Self::d_code(dist as usize - 1);
+                        *self.d_desc.dyn_tree[code as usize].freq_mut() += 1;
+                    }
+                }
+            }
+
+            return;
+        }
+
+        for chunk in self.sym_buf.filled()[symbols].chunks_exact(3) {
+            let [dist_low, dist_high, lc] = *chunk else {
+                unreachable!("out of bound access on the symbol buffer");
+            };
+
+            match u16::from_le_bytes([dist_low, dist_high]) {
+                0 => *self.l_desc.dyn_tree[lc as usize].freq_mut() += 1,
+                dist => {
+                    self.matches += 1;
+
+                    let index = self::trees_tbl::LENGTH_CODE[lc as usize] as usize + LITERALS + 1;
+                    *self.l_desc.dyn_tree[index].freq_mut() += 1;
+
+                    let code = Self::d_code(dist as usize - 1);
+                    *self.d_desc.dyn_tree[code as usize].freq_mut() += 1;
+                }
+            }
+        }
     }
 }
 
@@ -1672,9 +2398,14 @@ pub(crate) fn read_buf_window(stream: &mut DeflateStream, offset: usize, size: u
     len
 }
 
-pub(crate) enum BlockType {
+/// The encoding of a deflate block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockType {
+    /// The bytes are stored without compression.
     StoredBlock = 0,
+    /// The symbols are encoded with the fixed huffman trees of the deflate format.
     StaticTrees = 1,
+    /// The symbols are encoded with huffman trees that are sent along with the block.
     DynamicTrees = 2,
 }
 
@@ -1709,49 +2440,149 @@ pub(crate) fn zng_tr_stored_block(
     }
 }
 
-/// The minimum match length mandated by the deflate standard
-pub(crate) const STD_MIN_MATCH: usize = 3;
-/// The maximum match length mandated by the deflate standard
-pub(crate) const STD_MAX_MATCH: usize = 258;
+/// The number of bits that the stream has produced so far, including the pending output. Only the
+/// difference between two calls is meaningful.
+pub(crate) fn bits_written(stream: &DeflateStream) -> usize {
+    (stream.total_out as usize)
+        .wrapping_mul(8)
+        .wrapping_add(stream.state.bit_writer.pending_bits())
+}
 
-/// The minimum wanted match length, affects deflate_quick, deflate_fast, deflate_medium and deflate_slow
-pub(crate) const WANT_MIN_MATCH: usize = 4;
+/// Report a block for which deflate does not choose an encoding to the observer of
+/// [`deflate_with_trace`]: the stored blocks of level 0, the empty stored blocks of a flush, and
+/// the blocks of `deflate_quick`. Their symbols are not recorded.
+pub(crate) fn trace_block(
+    state: &mut State,
+    block_type: BlockType,
+    last: bool,
+    input_len: usize,
+    size: usize,
+) {
+    if let Some(mut observer) = state.observer {
+        let trace = BlockTrace {
+            block_type,
+            last,
+            input_len,
+            opt_len: 0,
+            static_len: 0,
+            size,
+            symbols: &[],
+        };
 
-pub(crate) const MIN_LOOKAHEAD: usize = STD_MAX_MATCH + STD_MIN_MATCH + 1;
+        // SAFETY: the observer is only set for the duration of `deflate_with_trace`, which
+        // borrows it mutably.
+        unsafe { (observer.as_mut())(&trace) };
+    }
+}
 
-pub(crate) fn fill_window(stream: &mut DeflateStream) {
-    debug_assert!(stream.state.lookahead < MIN_LOOKAHEAD);
+/// Write the empty stored block that marks a [`DeflateFlush::SyncFlush`] or
+/// [`DeflateFlush::FullFlush`], and report it like any other block.
+fn flush_marker(stream: &mut DeflateStream) {
+    let state = &mut stream.state;
+    let bits_before = state.bit_writer.pending_bits();
 
-    let wsize = stream.state.w_size;
+    zng_tr_stored_block(state, 0..0, false);
 
-    loop {
-        let state = &mut stream.state;
-        let mut more = state.window_size - state.lookahead - state.strstart;
+    let size = state.bit_writer.pending_bits() - bits_before;
+    trace_block(state, BlockType::StoredBlock, false, 0, size);
 
-        // If the window is almost full and there is insufficient lookahead,
-        // move the upper half to the lower one to make room in the upper half.
-        if state.strstart >= wsize + state.max_dist() {
-            // in some cases zlib-ng copies uninitialized bytes here. We cannot have that, so
-            // explicitly initialize them with zeros.
-            //
-            // see also the "fill_window_out_of_bounds" test.
-            state.window.initialize_at_least(2 * wsize);
-            state.window.filled_mut().copy_within(wsize..2 * wsize, 0);
-
-            if state.match_start >= wsize {
-                state.match_start -= wsize;
-            } else {
-                state.match_start = 0;
-                state.prev_length = 0;
-            }
-            state.strstart -= wsize; /* we now have strstart >= MAX_DIST */
-            state.block_start -= wsize as isize;
-            if state.insert > state.strstart {
-                state.insert = state.strstart;
-            }
+    let unflushed = state.lookahead + (state.strstart as isize - state.block_start).max(0) as usize;
+    report_progress(stream, BlockType::StoredBlock, 0, unflushed, 0);
+}
+
+/// Report a block that was just emitted to the progress callback, see [`deflate_with_progress`].
+/// `unflushed` is the input that has been read, but that is not part of a block yet.
+pub(crate) fn report_progress(
+    stream: &mut DeflateStream,
+    block_type: BlockType,
+    block_input_len: usize,
+    unflushed: usize,
+    matches: usize,
+) {
+    let state = &mut stream.state;
+
+    // after an abort, the stream may still finish the current block
+    let Some(mut callback) = state.progress.filter(|_| !state.aborted) else {
+        return;
+    };
+
+    let progress = Progress {
+        total_in: stream.total_in as usize - unflushed,
+        total_out: stream.total_out as usize + state.bit_writer.pending_bits() / 8,
+        block_type,
+        block_input_len,
+        matches,
+    };
+
+    // SAFETY: the callback is only set for the duration of `deflate_with_progress`, which
+    // borrows it mutably.
+    if unsafe { (callback.as_mut())(&progress) }.is_break() {
+        state.aborted = true;
+    }
+}
+
+/// The minimum match length mandated by the deflate standard
+pub(crate) const STD_MIN_MATCH: usize = 3;
+/// The maximum match length mandated by the deflate standard
+pub(crate) const STD_MAX_MATCH: usize = 258;
+
+/// The minimum wanted match length, affects deflate_quick, deflate_fast, deflate_medium and deflate_slow
+pub(crate) const WANT_MIN_MATCH: usize = 4;
+
+pub(crate) const MIN_LOOKAHEAD: usize = STD_MAX_MATCH + STD_MIN_MATCH + 1;
+
+/// Move the upper half of the window to the lower half, to make room for more input.
+pub(crate) fn slide_window(state: &mut State) {
+    let wsize = state.w_size;
+    let more = state.window_size - state.lookahead - state.strstart;
+
+    // in some cases zlib-ng copies uninitialized bytes here. We cannot have that, so
+    // explicitly initialize them with zeros.
+    //
+    // see also the "fill_window_out_of_bounds" test.
+    state.window.initialize_at_least(2 * wsize);
+    if state.zlib_identical {
+        // zlib only moves the bytes that are in use. The bytes after them are stale, but
+        // longest_match can look at them, so they must be the same as in zlib.
+        state
+            .window
+            .filled_mut()
+            .copy_within(wsize..2 * wsize - more, 0);
+    } else {
+        state.window.filled_mut().copy_within(wsize..2 * wsize, 0);
+    }
+
+    if state.match_start >= wsize {
+        state.match_start -= wsize;
+    } else {
+        state.match_start = 0;
+        // zlib keeps prev_length, its deflate_fast uses it as the initial best length
+        if !state.zlib_identical {
+            state.prev_length = 0;
+        }
+    }
+    state.strstart -= wsize; /* we now have strstart >= MAX_DIST */
+    state.block_start -= wsize as isize;
+    if state.insert > state.strstart {
+        state.insert = state.strstart;
+    }
+
+    self::slide_hash::slide_hash(state);
+}
+
+pub(crate) fn fill_window(stream: &mut DeflateStream) {
+    debug_assert!(stream.state.lookahead < MIN_LOOKAHEAD);
 
-            self::slide_hash::slide_hash(state);
+    let wsize = stream.state.w_size;
+
+    loop {
+        let state = &mut stream.state;
+        let mut more = state.window_size - state.lookahead - state.strstart;
 
+        // If the window is almost full and there is insufficient lookahead,
+        // move the upper half to the lower one to make room in the upper half.
+        if state.strstart >= wsize + state.max_dist() {
+            slide_window(state);
             more += wsize;
         }
 
@@ -1779,7 +2610,7 @@ pub(crate) fn fill_window(stream: &mut DeflateStream) {
         // Initialize the hash value now that we have some input:
         if state.lookahead + state.insert >= STD_MIN_MATCH {
             let string = state.strstart - state.insert;
-            if state.max_chain_length > 1024 {
+            if state.hash_calc_variant == HashCalcVariant::Roll || state.zlib_identical {
                 let v0 = state.window.filled()[string] as u32;
                 let v1 = state.window.filled()[string + 1] as u32;
                 state.ins_h = state.update_hash(v0, v1) as usize;
@@ -1842,10 +2673,15 @@ impl StaticTreeDesc {
         0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
     ];
 
+    /// extra bits for each length code of Deflate64, where the last code has 16 extra bits
+    const EXTRA_LBITS_64: [u8; LENGTH_CODES] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 16,
+    ];
+
     /// extra bits for each distance code
-    const EXTRA_DBITS: [u8; D_CODES] = [
+    const EXTRA_DBITS: [u8; D_CODES_64] = [
         0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12,
-        13, 13,
+        13, 13, 14, 14,
     ];
 
     /// extra bits for each bit length code
@@ -1873,6 +2709,16 @@ impl StaticTreeDesc {
         max_length: MAX_BITS as u16,
     };
 
+    pub(crate) const L64: Self = Self {
+        extra_bits: &Self::EXTRA_LBITS_64,
+        ..Self::L
+    };
+
+    pub(crate) const D64: Self = Self {
+        elems: D_CODES_64,
+        ..Self::D
+    };
+
     pub(crate) const BL: Self = Self {
         static_tree: &[],
         extra_bits: &Self::EXTRA_BLBITS,
@@ -2006,6 +2852,36 @@ fn gen_bitlen<const N: usize>(state: &mut State, desc: &mut TreeDesc<N>) {
         return;
     }
 
+    if state.optimal_huffman {
+        // the leaves, in order of increasing frequency
+        let mut symbols = [0usize; L_CODES];
+        let mut freqs = [0u16; L_CODES];
+        let mut n = 0;
+        for h in (heap.heap_max + 1..HEAP_SIZE).rev() {
+            let m = heap.heap[h] as usize;
+            if m <= max_code {
+                symbols[n] = m;
+                freqs[n] = tree[m].freq();
+                n += 1;
+            }
+        }
+
+        let mut lengths = [0u16; L_CODES];
+        package_merge::code_lengths(&freqs[..n], max_length as usize, &mut lengths[..n]);
+
+        state.bl_count.fill(0);
+        for (&m, &bits) in symbols[..n].iter().zip(&lengths[..n]) {
+            state.bl_count[bits as usize] += 1;
+
+            let freq = tree[m].freq() as usize;
+            state.opt_len += bits as usize * freq;
+            state.opt_len -= tree[m].len() as usize * freq;
+            *tree[m].len_mut() = bits;
+        }
+
+        return;
+    }
+
     /* Find the first bit length which could increase: */
     loop {
         let mut bits = max_length as usize - 1;
@@ -2179,7 +3055,7 @@ fn send_all_trees(state: &mut State, lcodes: usize, dcodes: usize, blcodes: usiz
         "not enough codes"
     );
     assert!(
-        lcodes <= L_CODES && dcodes <= D_CODES && blcodes <= BL_CODES,
+        lcodes <= L_CODES && dcodes <= D_CODES_64 && blcodes <= BL_CODES,
         "too many codes"
     );
 
@@ -2266,10 +3142,12 @@ fn build_bl_tree(state: &mut State) -> usize {
 
 fn zng_tr_flush_block(
     stream: &mut DeflateStream,
+    symbols: Range<usize>,
     window_offset: Option<usize>,
     stored_len: u32,
     last: bool,
 ) {
+    /* symbols: the range of sym_buf that makes up this block */
     /* window_offset: offset of the input block into the window */
     /* stored_len: length of input block */
     /* last: one if this is the last block for a file */
@@ -2280,11 +3158,19 @@ fn zng_tr_flush_block(
 
     let state = &mut stream.state;
 
-    if state.sym_buf.is_empty() {
+    let bits_before = state.bit_writer.pending_bits();
+
+    if symbols.is_empty() {
         opt_lenb = 0;
         static_lenb = 0;
         state.static_len = 7;
     } else if state.level > 0 {
+        if state.deflate64 {
+            // the frequencies counted by `tally_dist` are those of deflate's length codes
+            state.init_trees();
+            state.count_symbols(symbols.clone());
+        }
+
         if stream.data_type == DataType::Unknown as i32 {
             stream.data_type = State::detect_data_type(&state.l_desc.dyn_tree) as i32;
         }
@@ -2331,7 +3217,7 @@ fn zng_tr_flush_block(
             static_lenb,
             state.static_len,
             stored_len,
-            state.sym_buf.len() / 3
+            symbols.len() / 3
         );
 
         if static_lenb <= opt_lenb || state.strategy == Strategy::Fixed {
@@ -2344,7 +3230,7 @@ fn zng_tr_flush_block(
         static_lenb = stored_len as usize + 5;
     }
 
-    if stored_len as usize + 4 <= opt_lenb && window_offset.is_some() {
+    let block_type = if stored_len as usize + 4 <= opt_lenb && window_offset.is_some() {
         /* 4: two words for the lengths
          * The test buf != NULL is only necessary if LIT_BUFSIZE > WSIZE.
          * Otherwise we can't have processed more than WSIZE input bytes since
@@ -2355,10 +3241,12 @@ fn zng_tr_flush_block(
         let window_offset = window_offset.unwrap();
         let range = window_offset..window_offset + stored_len as usize;
         zng_tr_stored_block(state, range, last);
+        BlockType::StoredBlock
     } else if static_lenb == opt_lenb {
         state.bit_writer.emit_tree(BlockType::StaticTrees, last);
-        state.compress_block_static_trees();
-    // cmpr_bits_add(s, s.static_len);
+        state.compress_block_static_trees(symbols.clone());
+        // cmpr_bits_add(s, s.static_len);
+        BlockType::StaticTrees
     } else {
         state.bit_writer.emit_tree(BlockType::DynamicTrees, last);
         send_all_trees(
@@ -2368,14 +3256,40 @@ fn zng_tr_flush_block(
             max_blindex + 1,
         );
 
-        state.compress_block_dynamic_trees();
+        state.compress_block_dynamic_trees(symbols.clone());
+        BlockType::DynamicTrees
+    };
+
+    if let Some(mut observer) = state.observer {
+        let trace = BlockTrace {
+            block_type,
+            last,
+            input_len: stored_len as usize,
+            opt_len: state.opt_len,
+            static_len: state.static_len,
+            size: state.bit_writer.pending_bits() - bits_before,
+            symbols: &state.sym_buf.filled()[symbols.clone()],
+        };
+
+        // SAFETY: the observer is only set for the duration of `deflate_with_trace`, which
+        // borrows it mutably.
+        unsafe { (observer.as_mut())(&trace) };
     }
 
+    // input after the end of this block has been read, but not yet compressed. When the block
+    // starts before the window, it ends at `strstart` (unless it was split)
+    let block_end = window_offset.map_or(state.strstart, |offset| offset + stored_len as usize);
+    let unflushed = state.lookahead + (state.strstart - block_end);
+    let matches = state.matches;
+    report_progress(stream, block_type, stored_len as usize, unflushed, matches);
+
+    let state = &mut stream.state;
+
     // TODO
     // This check is made mod 2^32, for files larger than 512 MB and unsigned long implemented on 32 bits.
     // assert_eq!(state.compressed_len, state.bits_sent, "bad compressed size");
 
-    state.init_block();
+    state.init_trees();
     if last {
         state.bit_writer.emit_align();
     }
@@ -2384,17 +3298,78 @@ fn zng_tr_flush_block(
 }
 
 pub(crate) fn flush_block_only(stream: &mut DeflateStream, is_last: bool) {
-    zng_tr_flush_block(
-        stream,
-        (stream.state.block_start >= 0).then_some(stream.state.block_start as usize),
-        (stream.state.strstart as isize - stream.state.block_start) as u32,
-        is_last,
-    );
+    let window_offset =
+        (stream.state.block_start >= 0).then_some(stream.state.block_start as usize);
+    let stored_len = (stream.state.strstart as isize - stream.state.block_start) as u32;
 
+    if stream.state.block_splitting && stream.state.strategy != Strategy::Fixed {
+        flush_split_block(stream, window_offset, stored_len, is_last);
+    } else {
+        let symbols = 0..stream.state.sym_buf.len();
+        zng_tr_flush_block(stream, symbols, window_offset, stored_len, is_last);
+    }
+
+    stream.state.init_block();
     stream.state.block_start = stream.state.strstart as isize;
     flush_pending(stream)
 }
 
+/// Flush the current block as one or more blocks, split where the estimated cost is lowest.
+fn flush_split_block(
+    stream: &mut DeflateStream,
+    window_offset: Option<usize>,
+    stored_len: u32,
+    is_last: bool,
+) {
+    let blocks = block_split::split(stream.state.sym_buf.filled());
+    let count = blocks.count();
+
+    // the number of input bytes covered by the blocks so far
+    let mut covered = 0;
+
+    for (i, symbols) in blocks.ranges().enumerate() {
+        let last_part = i + 1 == count;
+
+        // the last part also covers any input that is not in a symbol yet
+        let block_len = match last_part {
+            true => stored_len - covered,
+            false => input_length(&stream.state.sym_buf.filled()[symbols.clone()]),
+        };
+
+        let state = &mut stream.state;
+        state.init_trees();
+        state.count_symbols(symbols.clone());
+
+        zng_tr_flush_block(
+            stream,
+            symbols,
+            window_offset.map(|offset| offset + covered as usize),
+            block_len,
+            is_last && last_part,
+        );
+
+        covered += block_len;
+    }
+}
+
+/// The number of input bytes that these symbols encode.
+fn input_length(symbols: &[u8]) -> u32 {
+    let mut length = 0;
+
+    for chunk in symbols.chunks_exact(3) {
+        let [dist_low, dist_high, lc] = *chunk else {
+            unreachable!("out of bound access on the symbol buffer");
+        };
+
+        length += match u16::from_le_bytes([dist_low, dist_high]) {
+            0 => 1,
+            _ => lc as u32 + STD_MIN_MATCH as u32,
+        };
+    }
+
+    length
+}
+
 #[must_use]
 fn flush_bytes(stream: &mut DeflateStream, mut bytes: &[u8]) -> ControlFlow<ReturnCode> {
     let mut state = &mut stream.state;
@@ -2438,9 +3413,226 @@ fn flush_bytes(stream: &mut DeflateStream, mut bytes: &[u8]) -> ControlFlow<Retu
 }
 
 pub fn deflate(stream: &mut DeflateStream, flush: DeflateFlush) -> ReturnCode {
+    deflate_help(stream, None, flush)
+}
+
+/// A symbol of an LZ77 parse of the input, see [`deflate_tokens`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Token {
+    /// A single byte of input.
+    Literal(u8),
+    /// A copy of `length` bytes, starting `distance` bytes back from the current position.
+    Match { length: u16, distance: u16 },
+}
+
+/// Like [`deflate`], but with input in the form of LZ77 tokens rather than bytes.
+///
+/// This is useful when a domain-specific match finder is available. The tokens are encoded with
+/// the same huffman coding and block logic that [`deflate`] uses, so the compression level is only
+/// relevant for the choice between stored and compressed blocks (level 0 always stores). The
+/// input bytes are reconstructed from the tokens, to compute the checksum and to fall back to
+/// stored blocks.
+///
+/// `tokens` is advanced past the tokens that were consumed. Like `next_in` for [`deflate`],
+/// remaining tokens must be passed again on the next call.
+///
+/// A match must have a `length` in `3..=258` and a `distance` of at least 1 that does not reach
+/// further back than the start of the input (or the preset dictionary), nor further than
+/// `2^window_bits - 262` bytes, or 250 bytes when `window_bits` is 8 (the same limit that the
+/// built-in match finders use). When any of the tokens is invalid, no tokens are consumed and
+/// [`ReturnCode::DataError`] is returned.
+///
+/// Byte input and tokens cannot be mixed within one call: `avail_in` must be zero. Byte input of
+/// an earlier [`deflate`] call must also be fully processed, which a [`DeflateFlush`] other than
+/// [`DeflateFlush::NoFlush`] does. Otherwise [`ReturnCode::StreamError`] is returned.
+pub fn deflate_tokens(
+    stream: &mut DeflateStream,
+    tokens: &mut &[Token],
+    flush: DeflateFlush,
+) -> ReturnCode {
+    // the tokens are written to the window at `strstart`, over any input that is still pending
+    let state = &stream.state;
+    if stream.avail_in != 0 || state.lookahead != 0 || state.match_available {
+        let err = ReturnCode::StreamError;
+        stream.msg = err.error_message();
+        return err;
+    }
+
+    // the number of bytes a match can reach back
+    let mut history = stream.state.strstart;
+    let max_dist = stream.state.max_dist();
+
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal(_) => history += 1,
+            Token::Match { length, distance } => {
+                let length = length as usize;
+                let distance = distance as usize;
+
+                if !(STD_MIN_MATCH..=STD_MAX_MATCH).contains(&length)
+                    || distance == 0
+                    || distance > Ord::min(history, max_dist)
+                {
+                    let err = ReturnCode::DataError;
+                    stream.msg = err.error_message();
+                    return err;
+                }
+
+                history += length;
+            }
+        }
+    }
+
+    deflate_help(stream, Some(tokens), flush)
+}
+
+/// A block that was emitted by [`deflate_with_trace`].
+#[derive(Debug, Clone, Copy)]
+pub struct BlockTrace<'a> {
+    /// How the block is encoded.
+    pub block_type: BlockType,
+    /// Whether this is the final block of the stream.
+    pub last: bool,
+    /// The number of input bytes in the block.
+    pub input_len: usize,
+    /// The estimated size in bits when encoded with dynamic trees, including the trees but
+    /// excluding the 3-bit block header. Zero when deflate did not compare encodings, see
+    /// [`BlockTrace::tokens`].
+    pub opt_len: usize,
+    /// The size in bits when encoded with the fixed trees, excluding the 3-bit block header. Zero
+    /// when deflate did not compare encodings, see [`BlockTrace::tokens`].
+    pub static_len: usize,
+    /// The size in bits of the block as it was written, including the block header.
+    pub size: usize,
+    symbols: &'a [u8],
+}
+
+impl<'a> BlockTrace<'a> {
+    /// The literals and matches of the block, in order. Stored blocks are also tokenized: the
+    /// tokens are what deflate found, before it decided that storing is smaller.
+    ///
+    /// Some blocks are written without first recording their tokens, and have none: the stored
+    /// blocks of level 0, the blocks of `deflate_quick` (level 1 with the default strategy), and
+    /// the empty stored blocks that mark a [`DeflateFlush::SyncFlush`] or
+    /// [`DeflateFlush::FullFlush`].
+    pub fn tokens(&self) -> impl Iterator<Item = Token> + 'a {
+        self.symbols.chunks_exact(3).map(|chunk| {
+            let [dist_low, dist_high, lc] = *chunk else {
+                unreachable!("out of bound access on the symbol buffer");
+            };
+
+            match u16::from_le_bytes([dist_low, dist_high]) {
+                0 => Token::Literal(lc),
+                distance => Token::Match {
+                    length: lc as u16 + STD_MIN_MATCH as u16,
+                    distance,
+                },
+            }
+        })
+    }
+}
+
+type Observer<'t> = &'t mut dyn FnMut(&BlockTrace);
+
+/// Like [`deflate`], but calls `observer` for every block that is emitted.
+///
+/// Every block is reported, also those that have no tokens, see [`BlockTrace::tokens`].
+/// `deflate_quick` only ends a block when deflate is called with a [`DeflateFlush`] other than
+/// [`DeflateFlush::NoFlush`], so its blocks may be reported by a later call than the one that
+/// started them.
+pub fn deflate_with_trace(
+    stream: &mut DeflateStream,
+    flush: DeflateFlush,
+    mut observer: Observer<'_>,
+) -> ReturnCode {
+    struct ClearObserver<'s, 'a>(&'s mut DeflateStream<'a>);
+
+    impl Drop for ClearObserver<'_, '_> {
+        fn drop(&mut self) {
+            self.0.state.observer = None;
+        }
+    }
+
+    // the observer cannot be used once this function returns (or unwinds)
+    stream.state.observer = Some(NonNull::from(&mut observer).cast());
+    let guard = ClearObserver(stream);
+
+    deflate(guard.0, flush)
+}
+
+/// The progress of a deflate stream, reported after every block, see [`deflate_with_progress`].
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    /// The number of input bytes that have been compressed so far. Input that deflate has read,
+    /// but that is not part of a block yet, is not included.
+    pub total_in: usize,
+    /// The number of compressed bytes produced so far, including output that is still pending.
+    pub total_out: usize,
+    /// How the block that was just emitted is encoded.
+    pub block_type: BlockType,
+    /// The number of input bytes in the block.
+    pub block_input_len: usize,
+    /// The number of matches in the block.
+    pub matches: usize,
+}
+
+impl Progress {
+    /// The size of the output relative to the input so far, e.g. `0.25` when the output is a
+    /// quarter of the size of the input. A ratio close to 1 means that the input is
+    /// incompressible.
+    pub fn ratio(&self) -> f64 {
+        match self.total_in {
+            0 => 1.0,
+            total_in => self.total_out as f64 / total_in as f64,
+        }
+    }
+}
+
+/// A callback that is called after every block, and can abort the stream by returning
+/// [`ControlFlow::Break`].
+pub type ProgressCallback<'a> = &'a mut dyn FnMut(&Progress) -> ControlFlow<()>;
+
+/// Like [`deflate`], but calls `callback` for every block that is emitted.
+///
+/// When the callback returns [`ControlFlow::Break`], deflate stops at the end of the current
+/// block and returns [`ReturnCode::StreamError`], as does every further call until the stream is
+/// reset.
+///
+/// Every block is reported, also the stored blocks of level 0, the blocks of `deflate_quick`
+/// (level 1 with the default strategy), and the empty stored blocks of
+/// [`DeflateFlush::SyncFlush`] and [`DeflateFlush::FullFlush`]. `deflate_quick` only ends a block
+/// when deflate is called with a [`DeflateFlush`] other than [`DeflateFlush::NoFlush`].
+pub fn deflate_with_progress(
+    stream: &mut DeflateStream,
+    flush: DeflateFlush,
+    mut callback: ProgressCallback<'_>,
+) -> ReturnCode {
+    struct ClearCallback<'s, 'a>(&'s mut DeflateStream<'a>);
+
+    impl Drop for ClearCallback<'_, '_> {
+        fn drop(&mut self) {
+            self.0.state.progress = None;
+        }
+    }
+
+    // the callback cannot be used once this function returns (or unwinds)
+    stream.state.progress = Some(NonNull::from(&mut callback).cast());
+    let guard = ClearCallback(stream);
+
+    deflate(guard.0, flush)
+}
+
+fn deflate_help(
+    stream: &mut DeflateStream,
+    tokens: Option<&mut &[Token]>,
+    flush: DeflateFlush,
+) -> ReturnCode {
+    let has_input = stream.avail_in != 0 || tokens.as_ref().is_some_and(|t| !t.is_empty());
+
     if stream.next_out.is_null()
         || (stream.avail_in != 0 && stream.next_in.is_null())
         || (stream.state.status == Status::Finish && flush != DeflateFlush::Finish)
+        || stream.state.aborted
     {
         let err = ReturnCode::StreamError;
         stream.msg = err.error_message();
@@ -2474,7 +3666,7 @@ pub fn deflate(stream: &mut DeflateStream, flush: DeflateFlush) -> ReturnCode {
          * flushes. For repeated and useless calls with Z_FINISH, we keep
          * returning Z_STREAM_END instead of Z_BUF_ERROR.
          */
-    } else if stream.avail_in == 0
+    } else if !has_input
         && rank_flush(flush as i8) <= rank_flush(old_flush)
         && flush != DeflateFlush::Finish
     {
@@ -2484,7 +3676,7 @@ pub fn deflate(stream: &mut DeflateStream, flush: DeflateFlush) -> ReturnCode {
     }
 
     /* User must not provide more input after the first FINISH: */
-    if stream.state.status == Status::Finish && stream.avail_in != 0 {
+    if stream.state.status == Status::Finish && has_input {
         let err = ReturnCode::BufError;
         stream.msg = err.error_message();
         return err;
@@ -2582,12 +3774,22 @@ pub fn deflate(stream: &mut DeflateStream, flush: DeflateFlush) -> ReturnCode {
             if !gzhead.extra.is_null() {
                 let gzhead_extra = gzhead.extra;
 
+                // the header may have been replaced (or the stream restored from a checkpoint)
+                // since part of the extra field was written
+                let gzindex = stream.state.gzindex;
+                let Some(remaining) = ((gzhead.extra_len & 0xffff) as usize).checked_sub(gzindex)
+                else {
+                    let err = ReturnCode::StreamError;
+                    stream.msg = err.error_message();
+                    return err;
+                };
+
                 let extra = unsafe {
                     core::slice::from_raw_parts(
-                        // SAFETY: gzindex is always less than extra_len, and the user
-                        // guarantees the pointer is valid for extra_len.
-                        gzhead_extra.add(stream.state.gzindex),
-                        (gzhead.extra_len & 0xffff) as usize - stream.state.gzindex,
+                        // SAFETY: gzindex is at most extra_len, and the user guarantees the
+                        // pointer is valid for extra_len.
+                        gzhead_extra.add(gzindex),
+                        remaining,
                     )
                 };
 
@@ -2649,14 +3851,23 @@ pub fn deflate(stream: &mut DeflateStream, flush: DeflateFlush) -> ReturnCode {
 
     // Start a new block or continue the current one.
     let state = &mut stream.state;
-    if stream.avail_in != 0
+    if has_input
         || state.lookahead != 0
         || (flush != DeflateFlush::NoFlush && state.status != Status::Finish)
     {
-        let bstate = self::algorithm::run(stream, flush);
+        let bstate = match tokens {
+            None => self::algorithm::run(stream, flush),
+            Some(tokens) => self::algorithm::run_tokens(stream, tokens, flush),
+        };
 
         let state = &mut stream.state;
 
+        if state.aborted {
+            let err = ReturnCode::StreamError;
+            stream.msg = err.error_message();
+            return err;
+        }
+
         if matches!(bstate, BlockState::FinishStarted | BlockState::FinishDone) {
             state.status = Status::Finish;
         }
@@ -2685,14 +3896,15 @@ pub fn deflate(stream: &mut DeflateStream, flush: DeflateFlush) -> ReturnCode {
                         // add an empty stored block that is marked as not final. This is useful for
                         // parallel deflate where we want to make sure the intermediate blocks are not
                         // marked as "last block".
-                        zng_tr_stored_block(state, 0..0, false);
+                        flush_marker(stream);
                     }
                     DeflateFlush::FullFlush => {
                         // add an empty stored block that is marked as not final. This is useful for
                         // parallel deflate where we want to make sure the intermediate blocks are not
                         // marked as "last block".
-                        zng_tr_stored_block(state, 0..0, false);
+                        flush_marker(stream);
 
+                        let state = &mut stream.state;
                         state.head.as_mut_slice().fill(0); // forget history
 
                         if state.lookahead == 0 {
@@ -2776,6 +3988,69 @@ pub(crate) fn flush_pending(stream: &mut DeflateStream) {
     state.bit_writer.pending.advance(len);
 }
 
+/// Compress a sequence of LZ77 tokens into `output`, see [`deflate_tokens`].
+pub fn compress_tokens<'a>(
+    output: &'a mut [u8],
+    mut tokens: &[Token],
+    config: DeflateConfig,
+) -> (&'a mut [u8], ReturnCode) {
+    let mut stream = z_stream {
+        next_in: core::ptr::null_mut(),
+        avail_in: 0,
+        total_in: 0,
+        next_out: output.as_mut_ptr(),
+        avail_out: 0, // for special logic on the first iteration
+        total_out: 0,
+        msg: core::ptr::null_mut(),
+        state: core::ptr::null_mut(),
+        zalloc: None,
+        zfree: None,
+        opaque: core::ptr::null_mut(),
+        data_type: 0,
+        adler: 0,
+        reserved: 0,
+    };
+
+    let err = init(&mut stream, config);
+    if err != ReturnCode::Ok {
+        return (&mut [], err);
+    }
+
+    let max = core::ffi::c_uint::MAX as usize;
+
+    let mut left = output.len();
+
+    let err = loop {
+        if stream.avail_out == 0 {
+            stream.avail_out = Ord::min(left, max) as _;
+            left -= stream.avail_out as usize;
+        }
+
+        let err = if let Some(stream) = unsafe { DeflateStream::from_stream_mut(&mut stream) } {
+            deflate_tokens(stream, &mut tokens, DeflateFlush::Finish)
+        } else {
+            ReturnCode::StreamError
+        };
+
+        if err != ReturnCode::Ok {
+            break err;
+        }
+    };
+
+    if let Some(stream) = unsafe { DeflateStream::from_stream_mut(&mut stream) } {
+        let _ = end(stream);
+    }
+
+    let return_code = match err {
+        ReturnCode::StreamEnd => ReturnCode::Ok,
+        // insufficient output space, reported like `compress_slice` does
+        ReturnCode::BufError => ReturnCode::DataError,
+        err => err,
+    };
+
+    (&mut output[..stream.total_out as usize], return_code)
+}
+
 pub fn compress_slice<'a>(
     output: &'a mut [u8],
     input: &[u8],
@@ -2890,31 +4165,182 @@ pub fn compress_with_flush<'a>(
     (output_slice, return_code)
 }
 
-pub const fn compress_bound(source_len: usize) -> usize {
-    compress_bound_help(source_len, ZLIB_WRAPLEN)
-}
-
-const fn compress_bound_help(source_len: usize, wrap_len: usize) -> usize {
-    source_len // The source size itself */
-        // Always at least one byte for any input
-        .wrapping_add(if source_len == 0 { 1 } else { 0 })
-        // One extra byte for lengths less than 9
-        .wrapping_add(if source_len < 9 { 1 } else { 0 })
-        // Source encoding overhead, padded to next full byte
-        .wrapping_add(deflate_quick_overhead(source_len))
-        // Deflate block overhead bytes
-        .wrapping_add(DEFLATE_BLOCK_OVERHEAD)
-        // none, zlib or gzip wrapper
-        .wrapping_add(wrap_len)
-}
-
-///  heap used to build the Huffman trees
+/// Compress as much of `input` as fits into `output`, as a complete (finished) stream.
 ///
-/// The sons of heap[n] are heap[2*n] and heap[2*n+1]. heap[0] is not used.
-/// The same heap array is used to build all trees.
-#[derive(Clone)]
-struct Heap {
-    heap: [u32; 2 * L_CODES + 1],
+/// Returns the used part of `output` and the number of bytes of `input` that it contains. This
+/// is what zlib's `fitblk.c` example does: the input is fed in steps, and after every step a
+/// copy of the stream is finished to check that the output still fits. When it does not, the
+/// stream is rolled back to before the step and a smaller step is tried. The first step is the
+/// amount of input that [`bound`] guarantees to fit.
+///
+/// There are no loose bits to pack with [`prime`]: finishing the stream writes the last block
+/// and pads it to a byte boundary, and a rollback restores the complete stream state, including
+/// any bits that were not yet written.
+///
+/// Returns [`ReturnCode::DataError`] when `output` is too small for even an empty stream.
+pub fn compress_fit<'a>(
+    output: &'a mut [u8],
+    input: &[u8],
+    config: DeflateConfig,
+) -> (&'a mut [u8], usize, ReturnCode) {
+    let max = core::ffi::c_uint::MAX as usize;
+    let output_len = Ord::min(output.len(), max);
+
+    let mut stream = z_stream {
+        next_in: input.as_ptr() as *mut u8,
+        avail_in: 0,
+        total_in: 0,
+        next_out: output.as_mut_ptr(),
+        avail_out: 0,
+        total_out: 0,
+        msg: core::ptr::null_mut(),
+        state: core::ptr::null_mut(),
+        zalloc: None,
+        zfree: None,
+        opaque: core::ptr::null_mut(),
+        data_type: 0,
+        adler: 0,
+        reserved: 0,
+    };
+
+    let err = init(&mut stream, config);
+    if err != ReturnCode::Ok {
+        return (&mut [], 0, err);
+    }
+
+    let Some(stream) = (unsafe { DeflateStream::from_stream_mut(&mut stream) }) else {
+        return (&mut [], 0, ReturnCode::StreamError);
+    };
+
+    // the largest input for which the bound fits
+    let (mut low, mut high) = (0, Ord::min(input.len(), output_len));
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        match bound(Some(stream), mid) <= output_len {
+            true => low = mid,
+            false => high = mid - 1,
+        }
+    }
+
+    let mut step = Ord::max(low, 1);
+    let mut grow = true;
+
+    while step > 0 && (stream.total_in as usize) < input.len() {
+        let mut checkpoint = MaybeUninit::uninit();
+        let err = copy(&mut checkpoint, stream);
+        if err != ReturnCode::Ok {
+            let _ = end(stream);
+            return (&mut [], 0, err);
+        }
+
+        // SAFETY: `copy` initialized the checkpoint
+        let checkpoint = unsafe { checkpoint.assume_init_mut() };
+
+        let consumed = stream.total_in as usize;
+        let chunk = Ord::min(step, Ord::min(input.len() - consumed, max));
+
+        stream.next_in = input[consumed..].as_ptr() as *mut u8;
+        stream.avail_in = chunk as _;
+        stream.avail_out = (output_len - stream.total_out as usize) as _;
+
+        let err = deflate(stream, DeflateFlush::NoFlush);
+        let fits = err == ReturnCode::Ok && stream.avail_in == 0 && finishes(stream, output_len);
+
+        if fits {
+            let _ = end(checkpoint);
+        } else {
+            // roll back to before this step
+            core::mem::swap(stream, checkpoint);
+            let _ = end(checkpoint);
+            grow = false;
+        }
+
+        step = match grow {
+            true => step.saturating_mul(2),
+            false => step / 2,
+        };
+    }
+
+    stream.avail_in = 0;
+    stream.avail_out = (output_len - stream.total_out as usize) as _;
+
+    let err = deflate(stream, DeflateFlush::Finish);
+    let finished = is_finished(stream, err);
+
+    let consumed = stream.total_in as usize;
+    let written = stream.total_out as usize;
+    let _ = end(stream);
+
+    match err {
+        _ if finished => (&mut output[..written], consumed, ReturnCode::Ok),
+        // insufficient output space, reported like `compress_slice` does
+        ReturnCode::Ok | ReturnCode::BufError => (&mut [], 0, ReturnCode::DataError),
+        err => (&mut [], 0, err),
+    }
+}
+
+/// Whether a `deflate` call with [`DeflateFlush::Finish`] that returned `err` finished the stream.
+///
+/// When the output buffer is filled exactly, `deflate` returns [`ReturnCode::Ok`] even when all
+/// output has been written. Like zlib, it only reports the end of the stream on the next call.
+fn is_finished(stream: &DeflateStream, err: ReturnCode) -> bool {
+    let state = &stream.state;
+
+    match err {
+        ReturnCode::StreamEnd => true,
+        ReturnCode::Ok => {
+            state.status == Status::Finish
+                && state.wrap <= 0
+                && state.bit_writer.pending.pending().is_empty()
+                && state.bit_writer.bits_used == 0
+        }
+        _ => false,
+    }
+}
+
+/// Whether `stream` can be finished in the first `output_len` bytes of its output buffer.
+fn finishes(stream: &mut DeflateStream, output_len: usize) -> bool {
+    let mut trial = MaybeUninit::uninit();
+    if copy(&mut trial, stream) != ReturnCode::Ok {
+        return false;
+    }
+
+    // SAFETY: `copy` initialized the trial stream
+    let trial = unsafe { trial.assume_init_mut() };
+    trial.avail_out = (output_len - trial.total_out as usize) as _;
+
+    let err = deflate(trial, DeflateFlush::Finish);
+    let finished = is_finished(trial, err);
+    let _ = end(trial);
+
+    finished
+}
+
+pub const fn compress_bound(source_len: usize) -> usize {
+    compress_bound_help(source_len, ZLIB_WRAPLEN)
+}
+
+const fn compress_bound_help(source_len: usize, wrap_len: usize) -> usize {
+    source_len // The source size itself */
+        // Always at least one byte for any input
+        .wrapping_add(if source_len == 0 { 1 } else { 0 })
+        // One extra byte for lengths less than 9
+        .wrapping_add(if source_len < 9 { 1 } else { 0 })
+        // Source encoding overhead, padded to next full byte
+        .wrapping_add(deflate_quick_overhead(source_len))
+        // Deflate block overhead bytes
+        .wrapping_add(DEFLATE_BLOCK_OVERHEAD)
+        // none, zlib or gzip wrapper
+        .wrapping_add(wrap_len)
+}
+
+///  heap used to build the Huffman trees
+///
+/// The sons of heap[n] are heap[2*n] and heap[2*n+1]. heap[0] is not used.
+/// The same heap array is used to build all trees.
+#[derive(Clone)]
+struct Heap {
+    heap: [u32; 2 * L_CODES + 1],
 
     /// number of elements in the heap
     heap_len: usize,
@@ -3409,10 +4835,25 @@ mod test {
 
         #[test]
         fn window_bits_correction() {
-            // window_bits of 8 gets turned into 9 internally
+            // a window_bits of 8 uses buffers for a 512-byte window
+            let mut stream = z_stream::default();
+            let config = DeflateConfig {
+                window_bits: 8,
+                ..Default::default()
+            };
+            assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+            let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+            assert_eq!(stream.state.w_bits, 8);
+            assert_eq!(stream.state.w_size, 512);
+            assert!(stream.state.max_dist() <= 256);
+
+            assert!(end(stream).is_ok());
+
+            // stock zlib turns it into 9
             let mut stream = z_stream::default();
             let config = DeflateConfig {
                 window_bits: 8,
+                zlib_identical: true,
                 ..Default::default()
             };
             assert_eq!(init(&mut stream, config), ReturnCode::Ok);
@@ -3508,6 +4949,7 @@ mod test {
             window_bits: crate::MAX_WBITS,
             mem_level: DEF_MEM_LEVEL,
             strategy: Strategy::HuffmanOnly,
+            ..Default::default()
         };
 
         let (output, err) = compress_slice(&mut output, input.as_bytes(), config);
@@ -3536,6 +4978,7 @@ mod test {
             window_bits: crate::MAX_WBITS,
             mem_level: DEF_MEM_LEVEL,
             strategy: Strategy::Default,
+            ..Default::default()
         };
 
         let (output, err) = compress_slice(&mut output, input.as_bytes(), config);
@@ -3564,6 +5007,7 @@ mod test {
             window_bits: crate::MAX_WBITS,
             mem_level: DEF_MEM_LEVEL,
             strategy: Strategy::Default,
+            ..Default::default()
         };
 
         let (output, err) = compress_slice(&mut output, input.as_bytes(), config);
@@ -3593,6 +5037,7 @@ mod test {
                 window_bits: 11,
                 mem_level: 4,
                 strategy: Strategy::Rle,
+                ..Default::default()
             },
             &[56, 17, 99, 0, 2, 54, 0, 0, 11, 0, 7],
         )
@@ -3661,6 +5106,7 @@ mod test {
                 window_bits: 9,
                 mem_level: 1,
                 strategy: Strategy::HuffmanOnly,
+                ..Default::default()
             },
             &[
                 0x18, 0x19, 0x4, 0xc1, 0x21, 0x1, 0xc4, 0x0, 0x10, 0x3, 0xb0, 0x18, 0x29, 0x1e,
@@ -3723,6 +5169,7 @@ mod test {
                 window_bits: 26,
                 mem_level: 6,
                 strategy: Strategy::Default,
+                ..Default::default()
             },
             &[
                 31, 139, 8, 0, 0, 0, 0, 0, 4, gz_header::OS_CODE, 1, 18, 0, 237, 255, 27, 27, 27, 27, 27, 27, 27,
@@ -3859,6 +5306,7 @@ mod test {
 
             let config = InflateConfig {
                 window_bits: config.window_bits,
+                ..Default::default()
             };
 
             assert_eq!(crate::inflate::init(&mut stream, config), ReturnCode::Ok);
@@ -3943,6 +5391,7 @@ mod test {
                 window_bits: 10,
                 mem_level: 6,
                 strategy: Strategy::Default,
+                ..Default::default()
             };
 
             let (output, err) = compress_slice(deflate_buf, DATA, config);
@@ -3950,6 +5399,7 @@ mod test {
 
             let config = InflateConfig {
                 window_bits: config.window_bits,
+                ..Default::default()
             };
 
             let mut uncompr = [0; 1 << 17];
@@ -3980,6 +5430,7 @@ mod test {
             window_bits: 16 + crate::MAX_WBITS,
             mem_level: DEF_MEM_LEVEL,
             strategy: Strategy::Default,
+            ..Default::default()
         };
 
         let mut output_rs = vec![0; 128];
@@ -4060,6 +5511,7 @@ mod test {
             window_bits: 16 + crate::MAX_WBITS,
             mem_level: DEF_MEM_LEVEL,
             strategy: Strategy::Default,
+            ..Default::default()
         };
 
         // see also the docs on `SyncFlush`. it makes sure everything is flushed, ends on a byte
@@ -4082,6 +5534,7 @@ mod test {
 
         let inflate_config = crate::inflate::InflateConfig {
             window_bits: 16 + 15,
+            ..Default::default()
         };
 
         // cuts off the length and crc
@@ -4159,7 +5612,10 @@ mod test {
             28, 224, 64, 146, 2, 139, 1, 196, 95, 196, 223, 94, 10, 96, 92, 33, 86, 2, 0, 0,
         ];
 
-        let config = InflateConfig { window_bits: 25 };
+        let config = InflateConfig {
+            window_bits: 25,
+            ..Default::default()
+        };
 
         let mut dest_vec_rs = vec![0u8; uncompressed.len()];
         let (output_rs, error) =
@@ -4201,6 +5657,7 @@ mod test {
             window_bits: 9,
             mem_level: 8,
             strategy: Strategy::Default,
+            ..Default::default()
         };
 
         let crc32 = [
@@ -4228,9 +5685,1574 @@ mod test {
                     fuzz_based_test(&input, config, &other);
                 }
             }
-            HashCalcVariant::Standard | HashCalcVariant::Roll => {
+            HashCalcVariant::Standard | HashCalcVariant::Roll | HashCalcVariant::Zlib => {
                 fuzz_based_test(&input, config, &other);
             }
         }
     }
+
+    #[test]
+    fn zlib_identical_output() {
+        // (level, strategy, length, crc32) of what stock zlib (1.2.13) produces for this input, with a
+        // single `deflate(Z_FINISH)` call into an output buffer of `deflateBound` bytes
+        const EXPECTED: &[(i32, Strategy, usize, u32)] = &[
+            (0, Strategy::Default, 102416, 0x86a7d37e),
+            (0, Strategy::Filtered, 102416, 0x86a7d37e),
+            (0, Strategy::HuffmanOnly, 102416, 0x86a7d37e),
+            (0, Strategy::Rle, 102416, 0x86a7d37e),
+            (0, Strategy::Fixed, 102416, 0x86a7d37e),
+            (1, Strategy::Default, 81690, 0xa5173ff4),
+            (1, Strategy::Filtered, 81690, 0xa5173ff4),
+            (1, Strategy::HuffmanOnly, 92554, 0x8efbc62c),
+            (1, Strategy::Rle, 90980, 0xd2fa529f),
+            (1, Strategy::Fixed, 84943, 0x624d63d2),
+            (2, Strategy::Default, 81619, 0xfc71c397),
+            (2, Strategy::Filtered, 81619, 0xfc71c397),
+            (2, Strategy::HuffmanOnly, 92554, 0x8efbc62c),
+            (2, Strategy::Rle, 90980, 0xd2fa529f),
+            (2, Strategy::Fixed, 84874, 0xb6faaf6d),
+            (3, Strategy::Default, 81560, 0x8f92b250),
+            (3, Strategy::Filtered, 81560, 0x8f92b250),
+            (3, Strategy::HuffmanOnly, 92554, 0x8efbc62c),
+            (3, Strategy::Rle, 90980, 0xd2fa529f),
+            (3, Strategy::Fixed, 84817, 0xd7846a93),
+            (4, Strategy::Default, 81496, 0xdf121ac4),
+            (4, Strategy::Filtered, 82237, 0x5fd55b44),
+            (4, Strategy::HuffmanOnly, 92554, 0x8efbc62c),
+            (4, Strategy::Rle, 90980, 0xd2fa529f),
+            (4, Strategy::Fixed, 84722, 0x348eeaba),
+            (5, Strategy::Default, 81349, 0x07894cc7),
+            (5, Strategy::Filtered, 82123, 0x894c1abb),
+            (5, Strategy::HuffmanOnly, 92554, 0x8efbc62c),
+            (5, Strategy::Rle, 90980, 0xd2fa529f),
+            (5, Strategy::Fixed, 84597, 0xe06c132c),
+            (6, Strategy::Default, 81312, 0x56cbc059),
+            (6, Strategy::Filtered, 82083, 0x4b2145f7),
+            (6, Strategy::HuffmanOnly, 92554, 0x8efbc62c),
+            (6, Strategy::Rle, 90980, 0xd2fa529f),
+            (6, Strategy::Fixed, 84575, 0x3bc59288),
+            (7, Strategy::Default, 81289, 0x5c14c4f1),
+            (7, Strategy::Filtered, 82058, 0x4061de21),
+            (7, Strategy::HuffmanOnly, 92554, 0x8efbc62c),
+            (7, Strategy::Rle, 90980, 0xd2fa529f),
+            (7, Strategy::Fixed, 84560, 0xc683b956),
+            (8, Strategy::Default, 81270, 0x96249782),
+            (8, Strategy::Filtered, 82042, 0xcf969787),
+            (8, Strategy::HuffmanOnly, 92554, 0x8efbc62c),
+            (8, Strategy::Rle, 90980, 0xd2fa529f),
+            (8, Strategy::Fixed, 84543, 0x3a6072bf),
+            (9, Strategy::Default, 81262, 0xb6aaa315),
+            (9, Strategy::Filtered, 82031, 0x9c7bf83c),
+            (9, Strategy::HuffmanOnly, 92554, 0x8efbc62c),
+            (9, Strategy::Rle, 90980, 0xd2fa529f),
+            (9, Strategy::Fixed, 84533, 0x89e84bc6),
+        ];
+
+        // (window_bits, mem_level, level, length, crc32), with the default strategy
+        const EXPECTED_WINDOW: &[(i32, i32, i32, usize, u32)] = &[
+            (9, 1, 1, 91119, 0xd5b0a855),
+            (9, 1, 6, 90578, 0x46a48b8e),
+            (9, 1, 9, 90578, 0x9d6d8777),
+            (9, 9, 1, 87389, 0xf9d56fcd),
+            (9, 9, 6, 86925, 0xc6e935d8),
+            (9, 9, 9, 86925, 0x399d6ebf),
+            (12, 1, 1, 86345, 0x4ad9deaf),
+            (12, 1, 6, 85493, 0x32937f19),
+            (12, 1, 9, 85435, 0x22b0f28b),
+            (12, 9, 1, 82587, 0x75a4efcd),
+            (12, 9, 6, 81958, 0x41eb53ef),
+            (12, 9, 9, 81902, 0x9686e0a4),
+            (-15, 1, 1, 86061, 0x49ea3d1e),
+            (-15, 1, 6, 84742, 0x4dc03eb4),
+            (-15, 1, 9, 84710, 0xaa6b4739),
+            (-15, 9, 1, 81647, 0x5ed64dbb),
+            (-15, 9, 6, 81258, 0x66f22800),
+            (-15, 9, 9, 81206, 0x2d1ae3c0),
+            (31, 1, 1, 86079, 0x9ea878e2),
+            (31, 1, 6, 84760, 0x9d020d49),
+            (31, 1, 9, 84728, 0x11eb4881),
+            (31, 9, 1, 81665, 0xcc8ccde4),
+            (31, 9, 6, 81276, 0x1e716622),
+            (31, 9, 9, 81224, 0x4b0834c2),
+        ];
+
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+        let mut output = vec![0; compress_bound(input.len())];
+
+        let configs = (EXPECTED.iter())
+            .map(|&(level, strategy, len, crc)| {
+                let config = DeflateConfig {
+                    level,
+                    strategy,
+                    ..DeflateConfig::default()
+                };
+                (config, len, crc)
+            })
+            .chain(
+                EXPECTED_WINDOW
+                    .iter()
+                    .map(|&(window_bits, mem_level, level, len, crc)| {
+                        let config = DeflateConfig {
+                            window_bits,
+                            mem_level,
+                            ..DeflateConfig::new(level)
+                        };
+                        (config, len, crc)
+                    }),
+            );
+
+        for (config, len, crc) in configs {
+            let config = DeflateConfig {
+                zlib_identical: true,
+                ..config
+            };
+
+            let (output, err) = compress_slice(&mut output, input, config);
+            assert_eq!(err, ReturnCode::Ok);
+
+            let actual = (output.len(), crate::crc32(0, output));
+            assert_eq!(actual, (len, crc), "{config:?}");
+        }
+    }
+
+    #[test]
+    fn block_splitting() {
+        // a block that starts out as text and continues as a pdf
+        let mut input = b"The quick brown fox jumps over the lazy dog. ".repeat(400);
+        input.extend_from_slice(&include_bytes!("deflate/test-data/paper-100k.pdf")[..32 * 1024]);
+
+        let strategies = [
+            Strategy::Default,
+            Strategy::Filtered,
+            Strategy::HuffmanOnly,
+            Strategy::Rle,
+        ];
+
+        for level in 1..=9 {
+            for strategy in strategies {
+                let mut sizes = [0; 2];
+
+                for (size, block_splitting) in sizes.iter_mut().zip([false, true]) {
+                    let config = DeflateConfig {
+                        level,
+                        strategy,
+                        block_splitting,
+                        ..DeflateConfig::default()
+                    };
+
+                    let mut output = vec![0; compress_bound(input.len())];
+                    let (output, err) = compress_slice(&mut output, &input, config);
+                    assert_eq!(err, ReturnCode::Ok);
+
+                    let mut decompressed = vec![0; input.len()];
+                    let (decompressed, err) =
+                        uncompress_slice(&mut decompressed, output, InflateConfig::default());
+                    assert_eq!(err, ReturnCode::Ok);
+                    assert_eq!(decompressed, input);
+
+                    *size = output.len();
+                }
+
+                let [unsplit, split] = sizes;
+                if level == 1 && matches!(strategy, Strategy::Default | Strategy::Filtered) {
+                    // deflate_quick does not buffer symbols, so there is nothing to split
+                    assert_eq!(split, unsplit);
+                } else {
+                    assert!(
+                        split < unsplit,
+                        "level {level}, {strategy:?}: {split} >= {unsplit}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn level_config() {
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+
+        let compress = |level, level_config| {
+            let config = DeflateConfig {
+                level,
+                level_config,
+                ..DeflateConfig::default()
+            };
+
+            let mut output = vec![0; compress_bound(input.len())];
+            let (compressed, err) = compress_slice(&mut output, input, config);
+            assert_eq!(err, ReturnCode::Ok);
+            let len = compressed.len();
+            output.truncate(len);
+
+            let mut decompressed = vec![0; input.len()];
+            let (decompressed, err) =
+                uncompress_slice(&mut decompressed, &output, InflateConfig::default());
+            assert_eq!(err, ReturnCode::Ok);
+            assert_eq!(decompressed, input);
+
+            output
+        };
+
+        // the built-in configurations produce the same output as the levels themselves
+        for level in 1..=9 {
+            let level_config = LevelConfig::for_level(level);
+            assert!(level_config.is_some());
+            assert_eq!(compress(level, level_config), compress(level, None));
+        }
+        assert_eq!(LevelConfig::for_level(0), None);
+
+        // other combinations are valid too
+        let algorithms = [
+            Algorithm::Quick,
+            Algorithm::QuickDynamic,
+            Algorithm::Fast,
+            Algorithm::Medium,
+            Algorithm::Slow,
+        ];
+
+        let mut hash_functions = vec![
+            HashFunction::Auto,
+            HashFunction::Standard,
+            HashFunction::Roll,
+        ];
+        if Crc32HashCalc::is_supported() {
+            hash_functions.push(HashFunction::Crc32);
+        }
+
+        // the slow match finder of long chains, and the seeding of the rolling hash, depend on the
+        // hash function rather than on the chain length
+        for algorithm in algorithms {
+            for &hash_function in &hash_functions {
+                for max_chain in [512, 4096] {
+                    let level_config = LevelConfig {
+                        algorithm,
+                        hash_function,
+                        good_length: 16,
+                        max_lazy: 64,
+                        nice_length: 192,
+                        max_chain,
+                    };
+
+                    compress(6, Some(level_config));
+                }
+            }
+        }
+
+        // a slow search with the parameters of level 6 compresses better than level 6
+        let level_config = LevelConfig {
+            algorithm: Algorithm::Slow,
+            ..LevelConfig::for_level(6).unwrap()
+        };
+        assert!(compress(6, Some(level_config)).len() < compress(6, None).len());
+    }
+
+    #[test]
+    fn level_config_is_validated() {
+        let valid = LevelConfig::for_level(4).unwrap();
+
+        let invalid = [
+            (
+                4,
+                false,
+                LevelConfig {
+                    max_chain: 3,
+                    ..valid
+                },
+            ),
+            (
+                4,
+                false,
+                LevelConfig {
+                    nice_length: 259,
+                    ..valid
+                },
+            ),
+            (
+                4,
+                false,
+                LevelConfig {
+                    good_length: 259,
+                    ..valid
+                },
+            ),
+            (
+                4,
+                false,
+                LevelConfig {
+                    max_lazy: 259,
+                    ..valid
+                },
+            ),
+            (0, false, valid),
+            (4, true, valid),
+        ];
+
+        for (level, zlib_identical, level_config) in invalid {
+            let config = DeflateConfig {
+                level,
+                zlib_identical,
+                level_config: Some(level_config),
+                ..DeflateConfig::default()
+            };
+
+            let mut stream = z_stream::default();
+            assert_eq!(init(&mut stream, config), ReturnCode::StreamError);
+        }
+
+        // quick does not search hash chains
+        let config = DeflateConfig {
+            level: 1,
+            level_config: Some(LevelConfig::for_level(1).unwrap()),
+            ..DeflateConfig::default()
+        };
+        assert_eq!(config.level_config.unwrap().max_chain, 0);
+
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        // the configuration applies until the level changes
+        assert_eq!(params(stream, 1, Strategy::Filtered), ReturnCode::Ok);
+        assert!(stream.state.level_config.is_some());
+        assert_eq!(params(stream, 2, Strategy::Filtered), ReturnCode::Ok);
+        assert!(stream.state.level_config.is_none());
+        assert_eq!(stream.state.max_chain_length, 4);
+
+        assert!(end(stream).is_ok());
+    }
+
+    #[test]
+    fn quick_dynamic() {
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+
+        let compress = |level_config, chunk: usize| {
+            let config = DeflateConfig {
+                level_config,
+                ..DeflateConfig::new(1)
+            };
+
+            let mut stream = z_stream::default();
+            assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+            let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+            // feed the output in small pieces, so that blocks are interrupted
+            let mut output = vec![0; compress_bound(input.len())];
+            stream.next_in = input.as_ptr() as *mut u8;
+            stream.avail_in = input.len() as _;
+            stream.next_out = output.as_mut_ptr();
+
+            loop {
+                stream.avail_out = chunk as _;
+                match deflate(stream, DeflateFlush::Finish) {
+                    ReturnCode::StreamEnd => break,
+                    err => assert_eq!(err, ReturnCode::Ok),
+                }
+            }
+
+            output.truncate(stream.total_out as usize);
+            assert!(end(stream).is_ok());
+
+            let mut decompressed = vec![0; input.len()];
+            let (decompressed, err) =
+                uncompress_slice(&mut decompressed, &output, InflateConfig::default());
+            assert_eq!(err, ReturnCode::Ok);
+            assert!(decompressed == input);
+
+            output
+        };
+
+        let level_config = LevelConfig {
+            algorithm: Algorithm::QuickDynamic,
+            ..LevelConfig::for_level(1).unwrap()
+        };
+
+        let quick = compress(None, 1 << 20);
+        let dynamic = compress(Some(level_config), 1 << 20);
+        assert!(dynamic.len() < quick.len());
+
+        // level 2 still searches harder for matches
+        let fast = compress(LevelConfig::for_level(2), 1 << 20);
+        assert!(dynamic.len() > fast.len());
+
+        assert_eq!(compress(Some(level_config), 7), dynamic);
+    }
+
+    #[test]
+    fn window_bits_8() {
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+
+        let strict = InflateConfig {
+            window_bits: 8,
+            strict: true,
+            ..Default::default()
+        };
+
+        for level in 0..=9 {
+            let config = DeflateConfig {
+                window_bits: 8,
+                ..DeflateConfig::new(level)
+            };
+
+            let mut output = vec![0; compress_bound(input.len())];
+            let (output, err) = compress_slice(&mut output, input, config);
+            assert_eq!(err, ReturnCode::Ok);
+
+            // CINFO is 0: a 256-byte window
+            assert_eq!(output[0], 0x08);
+            assert_eq!(u16::from_be_bytes([output[0], output[1]]) % 31, 0);
+
+            // a 256-byte window is enough to decode it, even when distances beyond the window
+            // are rejected
+            let mut decompressed = vec![0; input.len()];
+            let (decompressed, err) = uncompress_slice(&mut decompressed, output, strict);
+            assert_eq!(err, ReturnCode::Ok, "level {level}");
+            assert!(decompressed == input);
+        }
+
+        // a stream with distances beyond 256 bytes that claims a 256-byte window is rejected
+        let config = DeflateConfig {
+            window_bits: 10,
+            ..DeflateConfig::new(6)
+        };
+
+        let mut output = vec![0; compress_bound(input.len())];
+        let (output, err) = compress_slice(&mut output, input, config);
+        assert_eq!(err, ReturnCode::Ok);
+
+        assert_eq!(output[0], 0x28);
+        output[0] = 0x08;
+        let flevel = output[1] & 0b1110_0000;
+        output[1] = flevel + ((31 - u16::from_be_bytes([0x08, flevel]) % 31) % 31) as u8;
+        assert_eq!(u16::from_be_bytes([output[0], output[1]]) % 31, 0);
+
+        let mut decompressed = vec![0; input.len()];
+        let (_, err) = uncompress_slice(&mut decompressed, output, strict);
+        assert_eq!(err, ReturnCode::DataError);
+    }
+
+    #[test]
+    fn deflate64() {
+        // repeats at distances of more than 32K, and a run of zeros that is longer than 64K
+        let pdf = include_bytes!("deflate/test-data/paper-100k.pdf");
+        let mut input = pdf[..40_000].to_vec();
+        input.extend_from_slice(&pdf[..40_000]);
+        input.extend(core::iter::repeat(0).take(100_000));
+        input.extend_from_slice(&pdf[20_000..60_000]);
+
+        let compress = |config, chunk: usize| {
+            let mut stream = z_stream::default();
+            assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+            let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+            let mut output = vec![0; compress_bound(input.len())];
+            stream.next_in = input.as_ptr() as *mut u8;
+            stream.avail_in = input.len() as _;
+            stream.next_out = output.as_mut_ptr();
+
+            loop {
+                stream.avail_out = chunk as _;
+                match deflate(stream, DeflateFlush::Finish) {
+                    ReturnCode::StreamEnd => break,
+                    err => assert_eq!(err, ReturnCode::Ok),
+                }
+            }
+
+            output.truncate(stream.total_out as usize);
+            assert!(end(stream).is_ok());
+
+            output
+        };
+
+        let inflate_config = InflateConfig {
+            window_bits: -15,
+            deflate64: true,
+            ..Default::default()
+        };
+
+        for level in 0..=9 {
+            for strategy in [
+                Strategy::Default,
+                Strategy::Filtered,
+                Strategy::HuffmanOnly,
+                Strategy::Rle,
+                Strategy::Fixed,
+            ] {
+                let config = DeflateConfig {
+                    window_bits: -15,
+                    strategy,
+                    deflate64: true,
+                    ..DeflateConfig::new(level)
+                };
+
+                // small output chunks interrupt long matches
+                for chunk in [1 << 20, 7] {
+                    let output = compress(config, chunk);
+
+                    let mut decompressed = vec![0; input.len()];
+                    let (decompressed, err) =
+                        uncompress_slice(&mut decompressed, &output, inflate_config);
+                    assert_eq!(err, ReturnCode::Ok, "level {level} {strategy:?}");
+                    assert!(decompressed == input, "level {level} {strategy:?}");
+                }
+            }
+        }
+
+        // deflate cannot reach the repeats 40K back
+        let config = DeflateConfig {
+            window_bits: -15,
+            ..DeflateConfig::new(6)
+        };
+        let deflate = compress(config, 1 << 20);
+        let deflate64 = compress(
+            DeflateConfig {
+                deflate64: true,
+                ..config
+            },
+            1 << 20,
+        );
+        assert!(deflate64.len() + 20_000 < deflate.len());
+
+        // a run of zeros is sent as a few matches of up to 64K bytes
+        let zeros = [0; 200_000];
+        let mut output = [0; 64];
+        let config = DeflateConfig {
+            deflate64: true,
+            ..config
+        };
+        let (output, err) = compress_slice(&mut output, &zeros, config);
+        assert_eq!(err, ReturnCode::Ok);
+        assert!(output.len() < 20);
+
+        let mut decompressed = vec![1; zeros.len()];
+        let (decompressed, err) = uncompress_slice(&mut decompressed, output, inflate_config);
+        assert_eq!(err, ReturnCode::Ok);
+        assert!(decompressed == zeros);
+
+        // there is no zlib or gzip wrapper for Deflate64
+        for config in [
+            DeflateConfig::new(6),
+            DeflateConfig {
+                window_bits: 31,
+                ..DeflateConfig::new(6)
+            },
+            DeflateConfig {
+                zlib_identical: true,
+                ..config
+            },
+        ] {
+            let config = DeflateConfig {
+                deflate64: true,
+                ..config
+            };
+            let mut stream = z_stream::default();
+            assert_eq!(init(&mut stream, config), ReturnCode::StreamError);
+        }
+    }
+
+    #[test]
+    fn compress_fit() {
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+
+        let configs = [
+            DeflateConfig::new(1),
+            DeflateConfig::new(6),
+            DeflateConfig::new(9),
+            DeflateConfig {
+                window_bits: -15,
+                ..DeflateConfig::new(6)
+            },
+            DeflateConfig {
+                window_bits: 31,
+                strategy: Strategy::HuffmanOnly,
+                ..DeflateConfig::new(6)
+            },
+        ];
+
+        for config in configs {
+            for budget in [64, 1000, 4096, 30_000] {
+                let mut output = vec![0; budget];
+                let (output, consumed, err) = super::compress_fit(&mut output, input, config);
+                assert_eq!(err, ReturnCode::Ok);
+                assert!(output.len() <= budget);
+                assert!(consumed > 0);
+
+                // the budget is (almost) used up
+                assert!(output.len() + 16 >= budget, "{} of {budget}", output.len());
+
+                let inflate_config = InflateConfig {
+                    window_bits: config.window_bits,
+                    ..Default::default()
+                };
+
+                let mut decompressed = vec![0; consumed];
+                let (decompressed, err) =
+                    uncompress_slice(&mut decompressed, output, inflate_config);
+                assert_eq!(err, ReturnCode::Ok);
+                assert_eq!(decompressed, &input[..consumed]);
+            }
+        }
+
+        // everything fits
+        let mut output = vec![0; compress_bound(input.len())];
+        let (output, consumed, err) =
+            super::compress_fit(&mut output, input, DeflateConfig::new(6));
+        assert_eq!(err, ReturnCode::Ok);
+        assert_eq!(consumed, input.len());
+
+        let mut expected = vec![0; compress_bound(input.len())];
+        let (expected, _) = compress_slice(&mut expected, input, DeflateConfig::new(6));
+        assert_eq!(output, expected);
+
+        // not even an empty zlib stream fits
+        let mut output = [0; 7];
+        let (output, consumed, err) =
+            super::compress_fit(&mut output, input, DeflateConfig::new(6));
+        assert_eq!(err, ReturnCode::DataError);
+        assert_eq!((output.len(), consumed), (0, 0));
+
+        // but an empty raw stream does
+        let config = DeflateConfig {
+            window_bits: -15,
+            ..DeflateConfig::new(6)
+        };
+        let mut output = [0; 2];
+        let (output, consumed, err) = super::compress_fit(&mut output, input, config);
+        assert_eq!(err, ReturnCode::Ok);
+        assert_eq!((output.len(), consumed), (2, 0));
+    }
+
+    #[test]
+    fn copy_is_faithful() {
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+        let (first, second) = input.split_at(input.len() / 2);
+
+        let mut original = vec![0; compress_bound(input.len())];
+        let mut copied_output = vec![0; compress_bound(input.len())];
+
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, DeflateConfig::new(6)), ReturnCode::Ok);
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        stream.next_in = first.as_ptr() as *mut u8;
+        stream.avail_in = first.len() as _;
+        stream.next_out = original.as_mut_ptr();
+        stream.avail_out = original.len() as _;
+        assert_eq!(deflate(stream, DeflateFlush::NoFlush), ReturnCode::Ok);
+
+        let mut copied = MaybeUninit::uninit();
+        assert_eq!(copy(&mut copied, stream), ReturnCode::Ok);
+        let copied = unsafe { copied.assume_init_mut() };
+
+        let written = stream.total_out as usize;
+        copied_output[..written].copy_from_slice(&original[..written]);
+        copied.next_out = copied_output[written..].as_mut_ptr();
+
+        // the copy produces the same output as the original, including matches into the first half
+        for stream in [stream, copied] {
+            stream.next_in = second.as_ptr() as *mut u8;
+            stream.avail_in = second.len() as _;
+            assert_eq!(deflate(stream, DeflateFlush::Finish), ReturnCode::StreamEnd);
+            assert!(end(stream).is_ok());
+        }
+
+        assert_eq!(original, copied_output);
+    }
+
+    #[test]
+    fn skip_incompressible() {
+        let mut seed = 0x2545f4914f6cdd1du64;
+        let random: Vec<u8> = core::iter::repeat_with(|| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed >> 24) as u8
+        })
+        .take(200_000)
+        .collect();
+
+        let text = b"The quick brown fox jumps over the lazy dog. ".repeat(1000);
+
+        let mut input = text.clone();
+        input.extend_from_slice(&random);
+        input.extend_from_slice(&include_bytes!("deflate/test-data/paper-100k.pdf")[..64 * 1024]);
+        input.extend_from_slice(&random[..50_000]);
+        input.extend_from_slice(&text);
+
+        for level in 1..=9 {
+            for strategy in [Strategy::Default, Strategy::Filtered, Strategy::Fixed] {
+                let mut sizes = [0; 2];
+
+                for (size, skip_incompressible) in sizes.iter_mut().zip([false, true]) {
+                    let config = DeflateConfig {
+                        level,
+                        strategy,
+                        skip_incompressible,
+                        ..DeflateConfig::default()
+                    };
+
+                    let mut output = vec![0; compress_bound(input.len())];
+                    let (output, err) = compress_slice(&mut output, &input, config);
+                    assert_eq!(err, ReturnCode::Ok);
+
+                    let mut decompressed = vec![0; input.len()];
+                    let (decompressed, err) =
+                        uncompress_slice(&mut decompressed, output, InflateConfig::default());
+                    assert_eq!(err, ReturnCode::Ok);
+                    assert!(decompressed == input);
+
+                    *size = output.len();
+                }
+
+                // the random data is stored either way, everything else is still compressed
+                let [compressed, skipped] = sizes;
+                assert!(
+                    skipped < compressed + compressed / 500,
+                    "level {level}, {strategy:?}: {skipped} vs. {compressed}"
+                );
+            }
+        }
+
+        // the bound holds for incompressible data
+        let config = DeflateConfig {
+            skip_incompressible: true,
+            ..DeflateConfig::default()
+        };
+        let mut output = vec![0; compress_bound(random.len())];
+        let (_, err) = compress_slice(&mut output, &random, config);
+        assert_eq!(err, ReturnCode::Ok);
+    }
+
+    #[test]
+    fn skip_incompressible_streaming() {
+        let mut seed = 0x9e3779b97f4a7c15u64;
+        let random: Vec<u8> = core::iter::repeat_with(|| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed >> 24) as u8
+        })
+        .take(100_000)
+        .collect();
+
+        let mut input = b"The quick brown fox jumps over the lazy dog. ".repeat(1000);
+        let text_len = input.len();
+        input.extend_from_slice(&random);
+        input.extend_from_within(..text_len);
+
+        let config = DeflateConfig {
+            skip_incompressible: true,
+            ..DeflateConfig::new(6)
+        };
+
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut output = vec![0; compress_bound(input.len()) + 1024];
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+
+        let mut bypassed = false;
+        for (i, chunk) in input.chunks(5000).enumerate() {
+            stream.next_in = chunk.as_ptr() as *mut u8;
+            stream.avail_in = chunk.len() as _;
+
+            let flush = match i % 7 {
+                6 => DeflateFlush::SyncFlush,
+                _ => DeflateFlush::NoFlush,
+            };
+            assert_eq!(deflate(stream, flush), ReturnCode::Ok);
+            assert_eq!(stream.avail_in, 0);
+
+            bypassed |= stream.state.bypassing;
+        }
+
+        // the text at the end is compressed again
+        assert!(bypassed);
+        assert!(!stream.state.bypassing);
+
+        assert_eq!(deflate(stream, DeflateFlush::Finish), ReturnCode::StreamEnd);
+        output.truncate(stream.total_out as usize);
+        assert!(output.len() < random.len() + 2000);
+        assert!(end(stream).is_ok());
+
+        let mut decompressed = vec![0; input.len()];
+        let (decompressed, err) =
+            uncompress_slice(&mut decompressed, &output, InflateConfig::default());
+        assert_eq!(err, ReturnCode::Ok);
+        assert!(decompressed == input);
+    }
+
+    #[test]
+    fn optimal_huffman() {
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, DeflateConfig::new(9)), ReturnCode::Ok);
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+        let state = &mut stream.state;
+
+        // the cost in bits of the literal/length tree for these frequencies
+        let mut tree_cost = |freqs: &[u16], optimal_huffman: bool| {
+            state.optimal_huffman = optimal_huffman;
+
+            let mut desc = TreeDesc::<HEAP_SIZE> {
+                stat_desc: &StaticTreeDesc::L,
+                ..TreeDesc::EMPTY
+            };
+            for (value, &freq) in desc.dyn_tree.iter_mut().zip(freqs) {
+                *value.freq_mut() = freq;
+            }
+
+            build_tree(state, &mut desc);
+
+            let lengths = desc.dyn_tree[..L_CODES].iter().map(|value| value.len());
+            assert!(lengths.clone().all(|len| len <= MAX_BITS as u16));
+            let kraft: usize = lengths
+                .clone()
+                .filter(|&len| len > 0)
+                .map(|len| 1 << (MAX_BITS - len as usize))
+                .sum();
+            assert_eq!(kraft, 1 << MAX_BITS);
+
+            lengths
+                .zip(freqs)
+                .map(|(len, &freq)| len as usize * freq as usize)
+                .sum::<usize>()
+        };
+
+        let mut seed = 0x2545f4914f6cdd1du64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        let mut saved = 0;
+        for _ in 0..100 {
+            // skewed frequencies, whose huffman code is longer than 15 bits
+            let mut freqs = [0u16; L_CODES];
+            for (i, freq) in freqs.iter_mut().enumerate() {
+                *freq = 1 + ((4096 >> (i / 12)) / (1 + random() % 4)) as u16;
+            }
+
+            let heuristic = tree_cost(&freqs, false);
+            let optimal = tree_cost(&freqs, true);
+            assert!(optimal <= heuristic);
+            saved += heuristic - optimal;
+        }
+        assert!(saved > 0);
+
+        assert!(end(stream).is_ok());
+
+        // round trip of data with a skewed distribution
+        let skewed: Vec<u8> = (0..200_000)
+            .map(|_| {
+                let mut k = 0u8;
+                while random() % 1000 < 618 {
+                    k += 1;
+                }
+                k
+            })
+            .collect();
+
+        for strategy in [Strategy::Default, Strategy::HuffmanOnly] {
+            let config = DeflateConfig {
+                level: 9,
+                mem_level: 9,
+                strategy,
+                optimal_huffman: true,
+                ..DeflateConfig::default()
+            };
+
+            let mut output = vec![0; compress_bound(skewed.len())];
+            let (output, err) = compress_slice(&mut output, &skewed, config);
+            assert_eq!(err, ReturnCode::Ok);
+
+            let mut decompressed = vec![0; skewed.len()];
+            let (decompressed, err) =
+                uncompress_slice(&mut decompressed, output, InflateConfig::default());
+            assert_eq!(err, ReturnCode::Ok);
+            assert!(decompressed == skewed);
+        }
+    }
+
+    /// A simple greedy LZ77 parse, with matches that reach at most `max_dist` bytes back.
+    fn greedy_tokens(input: &[u8], max_dist: usize) -> Vec<Token> {
+        let mut last_seen = vec![usize::MAX; 1 << 16];
+        let hash = |i: usize| {
+            (input[i] as usize) << 8 ^ (input[i + 1] as usize) << 4 ^ input[i + 2] as usize
+        };
+
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < input.len() {
+            if i + STD_MIN_MATCH <= input.len() {
+                let candidate = core::mem::replace(&mut last_seen[hash(i)], i);
+                if candidate != usize::MAX && i - candidate <= max_dist {
+                    let length = input[i..]
+                        .iter()
+                        .zip(&input[candidate..])
+                        .take(STD_MAX_MATCH)
+                        .take_while(|(a, b)| a == b)
+                        .count();
+
+                    if length >= STD_MIN_MATCH {
+                        let distance = (i - candidate) as u16;
+                        tokens.push(Token::Match {
+                            length: length as u16,
+                            distance,
+                        });
+                        i += length;
+                        continue;
+                    }
+                }
+            }
+
+            tokens.push(Token::Literal(input[i]));
+            i += 1;
+        }
+
+        tokens
+    }
+
+    #[test]
+    fn compress_tokens_round_trip() {
+        let mut input = b"The quick brown fox jumps over the lazy dog. ".repeat(2000);
+        input.extend_from_slice(include_bytes!("deflate/test-data/paper-100k.pdf"));
+
+        for window_bits in [9i32, 15, -15, 31] {
+            let max_dist = (1 << (window_bits.abs() & 15)) - MIN_LOOKAHEAD;
+            let tokens = greedy_tokens(&input, max_dist);
+
+            for level in [0, 1, 6, 9] {
+                for strategy in [Strategy::Default, Strategy::Fixed] {
+                    let config = DeflateConfig {
+                        level,
+                        window_bits,
+                        strategy,
+                        ..DeflateConfig::default()
+                    };
+
+                    let mut output = vec![0; compress_bound(input.len())];
+                    let (output, err) = compress_tokens(&mut output, &tokens, config);
+                    assert_eq!(err, ReturnCode::Ok);
+
+                    // also checks the checksum
+                    let mut decompressed = vec![0; input.len()];
+                    let inflate_config = InflateConfig {
+                        window_bits,
+                        ..Default::default()
+                    };
+                    let (decompressed, err) =
+                        uncompress_slice(&mut decompressed, output, inflate_config);
+                    assert_eq!(err, ReturnCode::Ok);
+                    assert!(decompressed == input, "{config:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compress_long_match_tokens_stored() {
+        // at level 0 the matches are stored, which is much more input than there are symbols
+        let mut tokens = vec![Token::Literal(b'a')];
+        tokens.extend(
+            [Token::Match {
+                length: 258,
+                distance: 1,
+            }; 2000],
+        );
+        let expected = vec![b'a'; 1 + 2000 * 258];
+
+        for mem_level in [1, 2, 8] {
+            let config = DeflateConfig {
+                level: 0,
+                mem_level,
+                ..DeflateConfig::default()
+            };
+
+            let mut output = vec![0; compress_bound(expected.len())];
+            let (output, err) = compress_tokens(&mut output, &tokens, config);
+            assert_eq!(err, ReturnCode::Ok);
+
+            let mut decompressed = vec![0; expected.len()];
+            let (decompressed, err) =
+                uncompress_slice(&mut decompressed, output, InflateConfig::default());
+            assert_eq!(err, ReturnCode::Ok);
+            assert!(decompressed == expected, "{mem_level}");
+        }
+    }
+
+    #[test]
+    fn compress_literal_tokens() {
+        // only literals gives the same symbols, and so the same blocks, as huffman-only
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+        let tokens: Vec<_> = input.iter().map(|&b| Token::Literal(b)).collect();
+
+        let config = DeflateConfig {
+            strategy: Strategy::HuffmanOnly,
+            ..DeflateConfig::default()
+        };
+
+        let mut expected = vec![0; compress_bound(input.len())];
+        let (expected, err) = compress_slice(&mut expected, input, config);
+        assert_eq!(err, ReturnCode::Ok);
+
+        let mut output = vec![0; compress_bound(input.len())];
+        let (output, err) = compress_tokens(&mut output, &tokens, config);
+        assert_eq!(err, ReturnCode::Ok);
+
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn mix_bytes_and_tokens() {
+        let text = b"The quick brown fox jumps over the lazy dog, many times. ";
+        let tokens = [
+            Token::Literal(b'X'),
+            Token::Literal(b'Y'),
+            Token::Match {
+                length: 5,
+                distance: 2,
+            },
+        ];
+
+        for flush in [DeflateFlush::NoFlush, DeflateFlush::SyncFlush] {
+            let mut stream = z_stream::default();
+            assert_eq!(init(&mut stream, DeflateConfig::new(6)), ReturnCode::Ok);
+            let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+            let mut output = [0; 1024];
+            stream.next_out = output.as_mut_ptr();
+            stream.avail_out = output.len() as _;
+
+            stream.next_in = text.as_ptr() as *mut u8;
+            stream.avail_in = text.len() as _;
+            assert_eq!(deflate(stream, DeflateFlush::NoFlush), ReturnCode::Ok);
+            assert_eq!(stream.avail_in, 0);
+
+            // the text is still in the lookahead
+            if flush == DeflateFlush::NoFlush {
+                let mut remaining = &tokens[..];
+                let err = deflate_tokens(stream, &mut remaining, DeflateFlush::Finish);
+                assert_eq!(err, ReturnCode::StreamError);
+                assert_eq!(remaining.len(), tokens.len());
+                let _ = end(stream);
+                continue;
+            }
+
+            assert_eq!(deflate(stream, flush), ReturnCode::Ok);
+
+            let mut remaining = &tokens[..];
+            let err = deflate_tokens(stream, &mut remaining, DeflateFlush::Finish);
+            assert_eq!(err, ReturnCode::StreamEnd);
+            assert!(remaining.is_empty());
+
+            let len = stream.total_out as usize;
+            let _ = end(stream);
+
+            let mut decompressed = [0; 1024];
+            let (decompressed, err) =
+                uncompress_slice(&mut decompressed, &output[..len], InflateConfig::default());
+            assert_eq!(err, ReturnCode::Ok);
+            assert_eq!(decompressed, [&text[..], b"XYXYXYX"].concat());
+        }
+    }
+
+    #[test]
+    fn compress_invalid_tokens() {
+        let config = DeflateConfig::new(6);
+        let mut output = [0; 1024];
+
+        let invalid: [&[Token]; 5] = [
+            // reaches back before the start of the input
+            &[
+                Token::Literal(b'a'),
+                Token::Match {
+                    length: 3,
+                    distance: 2,
+                },
+            ],
+            &[Token::Match {
+                length: 3,
+                distance: 1,
+            }],
+            // distance 0
+            &[
+                Token::Literal(b'a'),
+                Token::Match {
+                    length: 3,
+                    distance: 0,
+                },
+            ],
+            // invalid length
+            &[
+                Token::Literal(b'a'),
+                Token::Match {
+                    length: 2,
+                    distance: 1,
+                },
+            ],
+            &[
+                Token::Literal(b'a'),
+                Token::Match {
+                    length: 259,
+                    distance: 1,
+                },
+            ],
+        ];
+
+        for tokens in invalid {
+            let (_, err) = compress_tokens(&mut output, tokens, config);
+            assert_eq!(err, ReturnCode::DataError, "{tokens:?}");
+        }
+
+        // reaches further back than the window allows
+        let config = DeflateConfig {
+            window_bits: 9,
+            ..DeflateConfig::default()
+        };
+        let mut tokens = vec![Token::Literal(b'a'); 1000];
+        tokens.push(Token::Match {
+            length: 3,
+            distance: 512 - MIN_LOOKAHEAD as u16 + 1,
+        });
+        let (_, err) = compress_tokens(&mut output, &tokens, config);
+        assert_eq!(err, ReturnCode::DataError);
+
+        tokens.pop();
+        tokens.push(Token::Match {
+            length: 3,
+            distance: 512 - MIN_LOOKAHEAD as u16,
+        });
+        let (output, err) = compress_tokens(&mut output, &tokens, config);
+        assert_eq!(err, ReturnCode::Ok);
+
+        let mut decompressed = [0; 1003];
+        let (decompressed, err) = uncompress_slice(
+            &mut decompressed,
+            output,
+            InflateConfig {
+                window_bits: 9,
+                ..Default::default()
+            },
+        );
+        assert_eq!(err, ReturnCode::Ok);
+        assert_eq!(decompressed, [b'a'; 1003]);
+    }
+
+    #[test]
+    fn trace_blocks() {
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+
+        for (level, strategy) in [
+            (6, Strategy::Default),
+            (9, Strategy::Fixed),
+            (1, Strategy::Rle),
+        ] {
+            let config = DeflateConfig {
+                level,
+                strategy,
+                mem_level: 4,
+                ..DeflateConfig::default()
+            };
+
+            let mut stream = z_stream::default();
+            assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+            let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+            let mut output = vec![0; compress_bound(input.len())];
+            stream.next_in = input.as_ptr() as *mut u8;
+            stream.avail_in = input.len() as _;
+            stream.next_out = output.as_mut_ptr();
+            stream.avail_out = output.len() as _;
+
+            let mut blocks = 0;
+            let mut block_bits = 0;
+            let mut last = false;
+            let mut reconstructed = Vec::new();
+
+            let mut observer = |block: &BlockTrace| {
+                assert!(!last, "a block after the last block");
+
+                blocks += 1;
+                block_bits += block.size;
+                last = block.last;
+
+                let start = reconstructed.len();
+                for token in block.tokens() {
+                    match token {
+                        Token::Literal(byte) => reconstructed.push(byte),
+                        Token::Match { length, distance } => {
+                            for _ in 0..length {
+                                reconstructed
+                                    .push(reconstructed[reconstructed.len() - distance as usize]);
+                            }
+                        }
+                    }
+                }
+                assert_eq!(reconstructed.len() - start, block.input_len);
+
+                // the block header is 3 bits, and the estimates are exact
+                match block.block_type {
+                    BlockType::StoredBlock => assert!(block.size >= 3 + 32 + 8 * block.input_len),
+                    BlockType::StaticTrees => assert_eq!(block.size, block.static_len + 3),
+                    BlockType::DynamicTrees => assert_eq!(block.size, block.opt_len + 3),
+                }
+            };
+
+            let err = deflate_with_trace(stream, DeflateFlush::Finish, &mut observer);
+            assert_eq!(err, ReturnCode::StreamEnd);
+            assert!(stream.state.observer.is_none());
+
+            let total_out = stream.total_out as usize;
+            assert!(end(stream).is_ok());
+
+            assert!(last);
+            assert!(blocks > 1, "{blocks}");
+            assert_eq!(reconstructed, input);
+
+            // 2 bytes of zlib header, 4 bytes of trailer, and at most 7 bits of padding
+            let padding = 8 * (total_out - 6) - block_bits;
+            assert!(padding < 8, "{padding}");
+        }
+    }
+
+    #[test]
+    fn trace_blocks_without_tokens() {
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+
+        // level 0 stores, level 1 uses `deflate_quick`
+        for (level, block_type) in [(0, BlockType::StoredBlock), (1, BlockType::StaticTrees)] {
+            let mut stream = z_stream::default();
+            assert_eq!(init(&mut stream, DeflateConfig::new(level)), ReturnCode::Ok);
+            let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+            let mut output = vec![0; compress_bound(input.len()) + 1024];
+            stream.next_out = output.as_mut_ptr();
+            stream.avail_out = output.len() as _;
+
+            let mut blocks = Vec::new();
+            let mut observer = |block: &BlockTrace| {
+                assert_eq!(block.tokens().count(), 0);
+                blocks.push((block.block_type, block.last, block.input_len, block.size));
+            };
+
+            for part in input.chunks(16 * 1024) {
+                stream.next_in = part.as_ptr() as *mut u8;
+                stream.avail_in = part.len() as _;
+
+                let err = deflate_with_trace(stream, DeflateFlush::SyncFlush, &mut observer);
+                assert_eq!(err, ReturnCode::Ok);
+            }
+
+            let err = deflate_with_trace(stream, DeflateFlush::Finish, &mut observer);
+            assert_eq!(err, ReturnCode::StreamEnd);
+
+            let total_out = stream.total_out as usize;
+            assert!(end(stream).is_ok());
+
+            // every sync flush is marked by an empty stored block. At level 0, the final block can
+            // also be empty
+            let markers = blocks.iter().filter(|b| b.2 == 0 && !b.1).count();
+            assert_eq!(markers, input.len().div_ceil(16 * 1024));
+            assert!(blocks
+                .iter()
+                .all(|b| b.2 == 0 && b.0 == BlockType::StoredBlock || b.0 == block_type));
+
+            assert_eq!(blocks.iter().filter(|b| b.1).count(), 1);
+            assert!(blocks.last().unwrap().1);
+            assert_eq!(blocks.iter().map(|b| b.2).sum::<usize>(), input.len());
+
+            // 2 bytes of zlib header, 4 bytes of trailer, and at most 7 bits of padding
+            let padding = 8 * (total_out - 6) - blocks.iter().map(|b| b.3).sum::<usize>();
+            assert!(padding < 8, "{padding}");
+        }
+    }
+
+    #[test]
+    fn prepared_dictionary() {
+        let paper = include_bytes!("deflate/test-data/paper-100k.pdf");
+        let input = &paper[60_000..70_000];
+
+        fn compress_with(
+            input: &[u8],
+            config: DeflateConfig,
+            set: impl FnOnce(&mut DeflateStream) -> ReturnCode,
+        ) -> Vec<u8> {
+            let mut stream = z_stream::default();
+            assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+            let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+            assert_eq!(set(stream), ReturnCode::Ok);
+
+            let mut output = vec![0; compress_bound(input.len())];
+            stream.next_in = input.as_ptr() as *mut u8;
+            stream.avail_in = input.len() as _;
+            stream.next_out = output.as_mut_ptr();
+            stream.avail_out = output.len() as _;
+
+            assert_eq!(deflate(stream, DeflateFlush::Finish), ReturnCode::StreamEnd);
+
+            output.truncate(stream.total_out as usize);
+            assert!(end(stream).is_ok());
+
+            output
+        }
+
+        for dictionary_len in [2, 1000, 20_000, 40_000, 70_000] {
+            let dictionary = &paper[..dictionary_len];
+
+            for (level, window_bits, zlib_identical) in [
+                (1, 15, false),
+                (6, 15, false),
+                (9, 15, false),
+                (6, -15, false),
+                (4, 9, false),
+                (6, 15, true),
+            ] {
+                let config = DeflateConfig {
+                    level,
+                    window_bits,
+                    zlib_identical,
+                    ..DeflateConfig::default()
+                };
+
+                let prepared = PreparedDictionary::new(dictionary, config).unwrap();
+                assert_eq!(prepared.id(), adler32(1, dictionary));
+
+                let expected = compress_with(input, config, |s| set_dictionary(s, dictionary));
+                let actual =
+                    compress_with(input, config, |s| set_prepared_dictionary(s, &prepared));
+                assert_eq!(expected, actual, "{dictionary_len} {config:?}");
+            }
+        }
+
+        // prepared for a stream that hashes differently: the dictionary is inserted the slow way
+        let dictionary = &paper[..20_000];
+        let prepared = PreparedDictionary::new(dictionary, DeflateConfig::new(9)).unwrap();
+
+        let config = DeflateConfig::new(1);
+        let expected = compress_with(input, config, |s| set_dictionary(s, dictionary));
+        let actual = compress_with(input, config, |s| set_prepared_dictionary(s, &prepared));
+        assert_eq!(expected, actual);
+
+        // gzip streams don't support dictionaries
+        let config = DeflateConfig {
+            window_bits: 31,
+            ..DeflateConfig::default()
+        };
+        let prepared = PreparedDictionary::new(dictionary, config).unwrap();
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+        assert_eq!(
+            set_prepared_dictionary(stream, &prepared),
+            ReturnCode::StreamError
+        );
+        assert!(end(stream).is_ok());
+    }
+
+    /// Compress `input` in chunks of `chunk` bytes, with a [`DeflateFlush::Block`] after every
+    /// chunk, and pass every block to `callback`.
+    fn compress_with_progress(
+        input: &[u8],
+        config: DeflateConfig,
+        chunk: usize,
+        callback: ProgressCallback,
+    ) -> (Vec<u8>, ReturnCode) {
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut output = vec![0; compress_bound(input.len()) + 1024];
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+
+        let mut err = ReturnCode::Ok;
+        for part in input.chunks(chunk) {
+            stream.next_in = part.as_ptr() as *mut u8;
+            stream.avail_in = part.len() as _;
+
+            let last = part.as_ptr_range().end == input.as_ptr_range().end;
+            let flush = match last {
+                true => DeflateFlush::Finish,
+                false => DeflateFlush::Block,
+            };
+
+            err = deflate_with_progress(stream, flush, &mut *callback);
+            if err != ReturnCode::Ok {
+                break;
+            }
+        }
+
+        output.truncate(stream.total_out as usize);
+        assert!(stream.state.progress.is_none());
+        let _ = end(stream);
+
+        (output, err)
+    }
+
+    #[test]
+    fn progress_callback() {
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+
+        // level 0 stores, level 1 uses `deflate_quick`
+        for level in [0, 1, 6] {
+            for chunk in [input.len(), 16 * 1024] {
+                let mut reports = Vec::new();
+                let mut callback = |progress: &Progress| {
+                    reports.push(*progress);
+                    ControlFlow::Continue(())
+                };
+
+                let config = DeflateConfig::new(level);
+                let (output, err) = compress_with_progress(input, config, chunk, &mut callback);
+                assert_eq!(err, ReturnCode::StreamEnd);
+
+                let mut decompressed = vec![0; input.len()];
+                let (decompressed, err) =
+                    uncompress_slice(&mut decompressed, &output, InflateConfig::default());
+                assert_eq!(err, ReturnCode::Ok);
+                assert!(decompressed == input);
+
+                assert!(!reports.is_empty(), "level {level}, chunk {chunk}");
+                assert!(reports.windows(2).all(|w| w[0].total_out <= w[1].total_out));
+
+                // the input that was compressed so far is exactly the input of the blocks so far
+                let mut block_input = 0;
+                for progress in &reports {
+                    block_input += progress.block_input_len;
+                    assert_eq!(progress.total_in, block_input);
+                }
+
+                let last = reports.last().unwrap();
+                assert_eq!(last.total_in, input.len());
+                assert!(last.total_out <= output.len());
+
+                match level {
+                    0 => assert!(reports
+                        .iter()
+                        .all(|p| p.block_type == BlockType::StoredBlock && p.matches == 0)),
+                    1 => assert!(reports
+                        .iter()
+                        .all(|p| p.block_type == BlockType::StaticTrees && p.matches > 0)),
+                    _ => {
+                        assert!(reports.iter().all(|p| p.matches > 0 && p.ratio() < 1.0));
+
+                        // the last byte of the final block, and the adler32 checksum
+                        assert_eq!(last.total_out + 1 + 4, output.len());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn progress_callback_abort() {
+        let mut seed = 42u32;
+        let input: Vec<u8> = (0..1 << 20)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+
+        for level in [0, 1, 6] {
+            let mut blocks = 0;
+            let mut total_in = 0;
+            let mut callback = |progress: &Progress| {
+                blocks += 1;
+                total_in = progress.total_in;
+                match progress.ratio() > 0.9 {
+                    true => ControlFlow::Break(()),
+                    false => ControlFlow::Continue(()),
+                }
+            };
+
+            let config = DeflateConfig::new(level);
+            let (_, err) = compress_with_progress(&input, config, 64 * 1024, &mut callback);
+            assert_eq!(err, ReturnCode::StreamError, "level {level}");
+
+            // aborted after the first block
+            assert_eq!(blocks, 1);
+            assert!(total_in < input.len());
+        }
+
+        // the stream stays aborted until it is reset
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, DeflateConfig::new(6)), ReturnCode::Ok);
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut callback = |_: &Progress| ControlFlow::Break(());
+
+        let mut output = vec![0; compress_bound(input.len())];
+        stream.next_in = input.as_ptr() as *mut u8;
+        stream.avail_in = input.len() as _;
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+
+        assert_eq!(
+            deflate_with_progress(stream, DeflateFlush::Finish, &mut callback),
+            ReturnCode::StreamError
+        );
+        assert_eq!(
+            deflate(stream, DeflateFlush::Finish),
+            ReturnCode::StreamError
+        );
+        assert!(stream.total_in < input.len() as _);
+
+        assert_eq!(reset(stream), ReturnCode::Ok);
+
+        stream.next_in = input.as_ptr() as *mut u8;
+        stream.avail_in = input.len() as _;
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+        assert_eq!(deflate(stream, DeflateFlush::Finish), ReturnCode::StreamEnd);
+
+        let _ = end(stream);
+    }
+
+    #[test]
+    fn params_after_quick_progress() {
+        let input = include_bytes!("deflate/test-data/paper-100k.pdf");
+        let (head, tail) = input.split_at(input.len() / 2);
+
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, DeflateConfig::new(1)), ReturnCode::Ok);
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut output = vec![0; compress_bound(input.len())];
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+
+        let mut matches = 0;
+        let mut callback = |progress: &Progress| {
+            matches += progress.matches;
+            ControlFlow::Continue(())
+        };
+
+        stream.next_in = head.as_ptr() as *mut u8;
+        stream.avail_in = head.len() as _;
+        let err = deflate_with_progress(stream, DeflateFlush::Block, &mut callback);
+        assert_eq!(err, ReturnCode::Ok);
+        assert!(matches > 0);
+
+        // at level 0, `matches` counts the hash table slides that deflate_stored skipped
+        assert_eq!(stream.state.matches, 0);
+        assert_eq!(params(stream, 0, Strategy::Default), ReturnCode::Ok);
+
+        stream.next_in = tail.as_ptr() as *mut u8;
+        stream.avail_in = tail.len() as _;
+        assert_eq!(deflate(stream, DeflateFlush::Finish), ReturnCode::StreamEnd);
+
+        output.truncate(stream.total_out as usize);
+        assert!(end(stream).is_ok());
+
+        let mut decompressed = vec![0; input.len()];
+        let (decompressed, err) =
+            uncompress_slice(&mut decompressed, &output, InflateConfig::default());
+        assert_eq!(err, ReturnCode::Ok);
+        assert!(decompressed == input);
+    }
 }
@@ -0,0 +1,286 @@
+//! Adapt the compression level to a throughput target.
+//!
+//! The input is compressed in chunks. After every chunk, the throughput of the current level is
+//! measured, and the level is lowered when it cannot keep up with the target, or raised when the
+//! next level is expected to keep up. Levels are switched with [`params`], which ends the current
+//! block when the new level uses a different algorithm.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    deflate::{deflate, params, DeflateStream},
+    DeflateFlush, ReturnCode,
+};
+
+/// The number of input bytes that is compressed before the level is reconsidered.
+const CHUNK: usize = 1 << 16;
+
+/// The number of chunks after which the estimate for the next level is forgotten, so that it is
+/// tried again. The input may have become easier to compress.
+const REPROBE: u32 = 16;
+
+/// What [`deflate_adaptive`] aims for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// Compress at least this many input bytes per second.
+    Throughput(f64),
+    /// Compress the input of every [`deflate_adaptive`] call within this time.
+    Latency(Duration),
+}
+
+/// The state of the level selection of [`deflate_adaptive`]. Use one per stream.
+#[derive(Debug, Clone)]
+pub struct AdaptiveLevel {
+    target: Target,
+    min_level: i8,
+    max_level: i8,
+    /// The estimated throughput (in bytes per second) of every level.
+    speed: [Option<f64>; 10],
+    /// The number of chunks compressed since the level last changed.
+    stable: u32,
+}
+
+impl AdaptiveLevel {
+    /// Select a level between 1 and 9 that meets `target`.
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            min_level: 1,
+            max_level: 9,
+            speed: [None; 10],
+            stable: 0,
+        }
+    }
+
+    /// Only select levels in `min_level..=max_level`. Levels are clamped to `0..=9`.
+    pub fn with_levels(self, min_level: i32, max_level: i32) -> Self {
+        let min_level = min_level.clamp(0, 9) as i8;
+        let max_level = max_level.clamp(min_level as i32, 9) as i8;
+
+        Self {
+            min_level,
+            max_level,
+            ..self
+        }
+    }
+
+    /// The estimated throughput (in bytes per second) of `level`, if it has been measured.
+    pub fn estimate(&self, level: i32) -> Option<f64> {
+        self.speed
+            .get(usize::try_from(level).ok()?)
+            .copied()
+            .flatten()
+    }
+
+    /// The throughput in bytes per second that is needed to compress `len` bytes.
+    fn required_throughput(&self, len: usize) -> f64 {
+        match self.target {
+            Target::Throughput(bytes_per_second) => bytes_per_second,
+            Target::Latency(budget) => len as f64 / budget.as_secs_f64(),
+        }
+    }
+
+    /// Record that `level` compressed `bytes` in `elapsed` time, and return the level to use for
+    /// the next chunk.
+    fn update(&mut self, level: i8, bytes: usize, elapsed: Duration, target: f64) -> i8 {
+        // the clock is too coarse to measure this chunk
+        if elapsed.is_zero() {
+            return level.clamp(self.min_level, self.max_level);
+        }
+
+        let measured = bytes as f64 / elapsed.as_secs_f64();
+        let speed = match self.speed[level as usize] {
+            None => measured,
+            Some(speed) => (speed + measured) / 2.0,
+        };
+        self.speed[level as usize] = Some(speed);
+
+        let next = if level > self.max_level {
+            level - 1
+        } else if level < self.min_level {
+            level + 1
+        } else if speed < target {
+            Ord::max(level - 1, self.min_level)
+        } else if level < self.max_level {
+            let higher = &mut self.speed[level as usize + 1];
+
+            self.stable += 1;
+            if self.stable >= REPROBE {
+                *higher = None;
+            }
+
+            match *higher {
+                Some(speed) if speed < target => level,
+                _ => level + 1,
+            }
+        } else {
+            level
+        };
+
+        if next != level {
+            self.stable = 0;
+        }
+
+        next
+    }
+}
+
+/// Like [`deflate`], but the compression level follows the throughput (or latency) target of
+/// `adaptive` instead of staying fixed.
+///
+/// The input is compressed in chunks of 64K, and the level is reconsidered after every chunk.
+/// Changing to a level that uses a different algorithm ends the current block, like [`params`]
+/// does. When there is not enough output space to end the block, the level is not changed.
+pub fn deflate_adaptive(
+    stream: &mut DeflateStream,
+    flush: DeflateFlush,
+    adaptive: &mut AdaptiveLevel,
+) -> ReturnCode {
+    let target = adaptive.required_throughput(stream.avail_in as usize);
+
+    loop {
+        let avail_in = stream.avail_in as usize;
+        let chunk = Ord::min(avail_in, CHUNK);
+        let rest = avail_in - chunk;
+
+        // only the final chunk is flushed
+        let chunk_flush = match rest {
+            0 => flush,
+            _ => DeflateFlush::NoFlush,
+        };
+
+        stream.avail_in = chunk as _;
+        let total_in = stream.total_in;
+
+        let start = Instant::now();
+        let err = deflate(stream, chunk_flush);
+        let elapsed = start.elapsed();
+
+        stream.avail_in += rest as crate::c_api::uInt;
+
+        if err != ReturnCode::Ok || rest == 0 || stream.avail_out == 0 {
+            return err;
+        }
+
+        let level = stream.state.level;
+        let consumed = (stream.total_in - total_in) as usize;
+        let next = adaptive.update(level, consumed, elapsed, target);
+
+        if next != level {
+            // end the block with the input so far, but keep the rest of the input for later
+            let avail_in = stream.avail_in;
+            stream.avail_in = 0;
+            let _ = params(stream, next as i32, stream.state.strategy);
+            stream.avail_in = avail_in;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        c_api::z_stream,
+        deflate::{compress_bound, end, init, DeflateConfig},
+        inflate::{uncompress_slice, InflateConfig},
+    };
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn level_follows_target() {
+        let mut adaptive = AdaptiveLevel::new(Target::Throughput(100.0));
+
+        let fast =
+            |adaptive: &mut AdaptiveLevel, level| adaptive.update(level, 150, 1000 * MS, 100.0);
+        let slow =
+            |adaptive: &mut AdaptiveLevel, level| adaptive.update(level, 50, 1000 * MS, 100.0);
+
+        // every level is fast enough
+        let mut level = 1;
+        for _ in 0..8 {
+            level = fast(&mut adaptive, level);
+        }
+        assert_eq!(level, 9);
+        assert_eq!(fast(&mut adaptive, level), 9);
+
+        // the data changes and the high levels become too slow
+        while level > 5 {
+            let next = slow(&mut adaptive, level);
+            assert!(next == level || next == level - 1);
+            level = next;
+        }
+
+        // level 5 is fast enough again, but level 6 is known to be too slow
+        let mut chunks = 0;
+        while level == 5 {
+            level = fast(&mut adaptive, level);
+            chunks += 1;
+        }
+
+        // until that estimate is forgotten
+        assert_eq!(chunks, REPROBE);
+        assert_eq!(level, 6);
+    }
+
+    #[test]
+    fn level_stays_within_bounds() {
+        let mut adaptive = AdaptiveLevel::new(Target::Throughput(100.0)).with_levels(3, 5);
+
+        // start below the minimum
+        assert_eq!(adaptive.update(1, 1, 1000 * MS, 100.0), 2);
+        assert_eq!(adaptive.update(3, 1, 1000 * MS, 100.0), 3);
+        assert_eq!(adaptive.update(5, 1000, MS, 100.0), 5);
+        assert_eq!(adaptive.update(7, 1000, MS, 100.0), 6);
+
+        // a zero duration is not measured
+        assert_eq!(adaptive.update(4, 1000, Duration::ZERO, 100.0), 4);
+        assert_eq!(adaptive.estimate(4), None);
+    }
+
+    fn compress(input: &[u8], adaptive: &mut AdaptiveLevel) -> (Vec<u8>, i8) {
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, DeflateConfig::new(6)), ReturnCode::Ok);
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut output = vec![0; compress_bound(input.len())];
+        stream.next_in = input.as_ptr() as *mut u8;
+        stream.avail_in = input.len() as _;
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+
+        let err = deflate_adaptive(stream, DeflateFlush::Finish, adaptive);
+        assert_eq!(err, ReturnCode::StreamEnd);
+
+        let level = stream.state.level;
+        output.truncate(stream.total_out as usize);
+        assert!(end(stream).is_ok());
+
+        (output, level)
+    }
+
+    #[test]
+    fn deflate_adaptive_round_trip() {
+        let input = include_bytes!("test-data/paper-100k.pdf").repeat(4);
+
+        // no level is this fast
+        let mut adaptive = AdaptiveLevel::new(Target::Throughput(1e15)).with_levels(2, 9);
+        let (fast, level) = compress(&input, &mut adaptive);
+        assert_eq!(level, 2);
+
+        // every level is this fast
+        let mut adaptive = AdaptiveLevel::new(Target::Latency(Duration::from_secs(3600)));
+        let (slow, level) = compress(&input, &mut adaptive);
+        assert_eq!(level, 9);
+        assert!(slow.len() < fast.len());
+
+        for compressed in [fast, slow] {
+            let mut output = vec![0; input.len()];
+            let (output, err) =
+                uncompress_slice(&mut output, &compressed, InflateConfig::default());
+            assert_eq!(err, ReturnCode::Ok);
+            assert!(output == input);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{
+    deflate::{slide_hash, BlockState, DeflateStream, State},
+    DeflateFlush,
+};
+
+use super::{stored::deflate_stored, CompressFunc};
+
+/// The input is compressed in regions of at most this many bytes. Every region is checked before
+/// it is compressed.
+const REGION: usize = 32 * 1024;
+
+/// Smaller samples are too noisy to judge.
+const MIN_SAMPLE: usize = 4 * 1024;
+
+/// Like `compress`, but regions of input that look incompressible are copied into stored blocks
+/// with `deflate_stored`, skipping the match finding that would not pay off.
+///
+/// Switching between the two ends the current block, so after a switch the mode is kept for at
+/// least one full region. That limits the overhead of the extra blocks to a few bytes per region,
+/// well within [`bound`](crate::deflate::bound).
+pub fn deflate_bypass(
+    stream: &mut DeflateStream,
+    flush: DeflateFlush,
+    compress: CompressFunc,
+) -> BlockState {
+    loop {
+        let avail_in = stream.avail_in as usize;
+        let region = Ord::min(avail_in, REGION);
+
+        if region >= MIN_SAMPLE && stream.state.bypass_region >= REGION {
+            let incompressible = is_incompressible(&histogram(stream, region), region);
+
+            if incompressible != stream.state.bypassing {
+                // end the current block, but do not consume any input yet
+                stream.avail_in = 0;
+                let bstate = run(stream, DeflateFlush::Block, compress);
+                stream.avail_in = avail_in as _;
+
+                if !matches!(bstate, BlockState::BlockDone) {
+                    return bstate;
+                }
+
+                switch(stream.state, incompressible);
+            }
+        }
+
+        let rest = avail_in - region;
+        let region_flush = match rest {
+            0 => flush,
+            _ => DeflateFlush::NoFlush,
+        };
+
+        stream.avail_in = region as _;
+        let bstate = run(stream, region_flush, compress);
+        let consumed = region - stream.avail_in as usize;
+        stream.avail_in += rest as crate::c_api::uInt;
+
+        let state = &mut stream.state;
+        state.bypass_region = state.bypass_region.saturating_add(consumed);
+
+        if rest == 0 || stream.avail_out == 0 || !matches!(bstate, BlockState::NeedMore) {
+            return bstate;
+        }
+    }
+}
+
+fn run(stream: &mut DeflateStream, flush: DeflateFlush, compress: CompressFunc) -> BlockState {
+    match stream.state.bypassing {
+        true => deflate_stored(stream, flush),
+        false => compress(stream, flush),
+    }
+}
+
+fn switch(state: &mut State, bypassing: bool) {
+    if state.bypassing && state.matches != 0 {
+        // deflate_stored does not maintain the hash table, see `params`
+        if state.matches == 1 {
+            slide_hash::slide_hash(state);
+        } else {
+            state.head.as_mut_slice().fill(0);
+        }
+        state.matches = 0;
+    }
+
+    state.bypassing = bypassing;
+    state.bypass_region = 0;
+}
+
+fn histogram(stream: &DeflateStream, len: usize) -> [u32; 256] {
+    let mut histogram = [0u32; 256];
+
+    for i in 0..len {
+        // SAFETY: there are at least `len` bytes of input. The input is read byte by byte (like
+        // `read_buf_direct_copy` does with its copy) because it may be modified concurrently.
+        let byte = unsafe { stream.next_in.add(i).read() };
+        histogram[byte as usize] += 1;
+    }
+
+    histogram
+}
+
+/// Whether `len` bytes with this histogram are unlikely to compress.
+///
+/// Uses the chi-squared distance to a uniform distribution, `X² = 256 * Σ c² / n - n`. For small
+/// distances it approximates the entropy: `8 - H ≈ X² / (2 n ln 2)` bits per byte. Random data
+/// has `X² ≈ 255` on average. Data that entropy coding can shrink by more than about 1% (0.1 bits
+/// per byte) has a larger distance. Repetitions of random data are not detected.
+fn is_incompressible(histogram: &[u32; 256], len: usize) -> bool {
+    let n = len as u64;
+    let sum_of_squares: u64 = histogram.iter().map(|&c| u64::from(c) * u64::from(c)).sum();
+
+    // 256 * Σ c² / n - n < 255 + 0.1386 n, with the division moved to the right-hand side
+    256 * sum_of_squares < n * (n + 255 + n * 1386 / 10_000)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(input: &[u8]) -> bool {
+        let mut histogram = [0u32; 256];
+        input.iter().for_each(|&b| histogram[b as usize] += 1);
+        is_incompressible(&histogram, input.len())
+    }
+
+    fn random(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                (seed >> 32) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_data_is_incompressible() {
+        for len in [MIN_SAMPLE, 10_000, REGION] {
+            for seed in 1..20 {
+                assert!(check(&random(len, seed)), "{len} {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn other_data_is_compressible() {
+        let text = include_bytes!("../test-data/paper-100k.pdf");
+        assert!(!check(&text[..REGION]));
+
+        assert!(!check(&[0; MIN_SAMPLE]));
+        assert!(!check(&b"abcdefghijklmnopqrstuvwxyz".repeat(200)));
+
+        // only 7 bits per byte
+        let mut sevens = random(REGION, 7);
+        sevens.iter_mut().for_each(|b| *b &= 0x7f);
+        assert!(!check(&sevens));
+    }
+}
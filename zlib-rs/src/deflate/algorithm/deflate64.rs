@@ -0,0 +1,238 @@
+#![forbid(unsafe_code)]
+
+//! Lazy matching for Deflate64, see [`DeflateConfig::deflate64`].
+//!
+//! The window is 64K, so positions in the (double-sized) window buffer do not fit the `u16`
+//! entries of the hash chains. Instead, the chains store positions modulo 64K, and are not moved
+//! when the window slides. The distance to a candidate is reconstructed from `strstart`, which is
+//! unambiguous because distances are shorter than 64K. Outdated entries are recognized because
+//! the distances along a chain stop increasing, and all candidates are verified anyway.
+//!
+//! For the same reason `prev_match` holds the distance of the previous match, not its position.
+//!
+//! [`DeflateConfig::deflate64`]: crate::deflate::DeflateConfig::deflate64
+
+use crate::{
+    deflate::{
+        compare256::compare256_slice, fill_window, flush_block_only, BlockState, DeflateStream,
+        State, Strategy, MIN_LOOKAHEAD, STD_MAX_MATCH, STD_MIN_MATCH, WANT_MIN_MATCH,
+    },
+    flush_block, DeflateFlush,
+};
+
+/// Positions in the hash chains are stored modulo 64K.
+const POSITION_MASK: usize = u16::MAX as usize;
+
+pub fn deflate64(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockState {
+    loop {
+        // Make sure that we always have enough lookahead, except
+        // at the end of the input file. We need STD_MAX_MATCH bytes
+        // for the next match, plus WANT_MIN_MATCH bytes to insert the
+        // string following the next match.
+        if stream.state.lookahead < MIN_LOOKAHEAD {
+            fill_window(stream);
+            if stream.state.lookahead < MIN_LOOKAHEAD && flush == DeflateFlush::NoFlush {
+                return BlockState::NeedMore;
+            }
+
+            if stream.state.lookahead == 0 {
+                break; /* flush the current block */
+            }
+        }
+
+        let state = &mut stream.state;
+
+        let hash_head = if state.lookahead >= WANT_MIN_MATCH {
+            state.quick_insert_string(state.strstart)
+        } else {
+            0
+        };
+
+        // Find the longest match, discarding those <= prev_length.
+        let mut match_len = STD_MIN_MATCH - 1;
+        let mut match_dist = 0;
+
+        if hash_head != 0 && state.prev_length < state.max_lazy_match {
+            (match_len, match_dist) = longest_match(state, hash_head);
+
+            if match_len <= 5 && state.strategy == Strategy::Filtered {
+                match_len = STD_MIN_MATCH - 1;
+            }
+        }
+
+        // If there was a match at the previous step and the current
+        // match is not better, output the previous match:
+        if state.prev_length >= STD_MIN_MATCH && match_len <= state.prev_length {
+            let dist = state.prev_match as usize;
+            let len = state.prev_length;
+
+            // Do not insert strings in hash table beyond this.
+            let max_insert = state.strstart + state.lookahead - STD_MIN_MATCH;
+
+            let bflush = state.tally_dist(dist, len - STD_MIN_MATCH);
+
+            // Insert in hash table all strings up to the end of the match.
+            // strstart-1 and strstart are already inserted.
+            state.lookahead -= len - 1;
+
+            let mov_fwd = len - 2;
+            if max_insert > state.strstart {
+                let insert_cnt = Ord::min(mov_fwd, max_insert - state.strstart);
+                state.insert_string(state.strstart + 1, insert_cnt);
+            }
+            state.prev_length = 0;
+            state.match_available = false;
+            state.strstart += mov_fwd + 1;
+
+            if bflush {
+                flush_block!(stream, false);
+            }
+
+            // A match of the maximum length is continued at the same distance for as long as the
+            // input repeats. The parts are combined into one long match when the block is written.
+            let mut len = len;
+            while len == STD_MAX_MATCH {
+                if stream.state.lookahead < MIN_LOOKAHEAD {
+                    fill_window(stream);
+                }
+
+                let state = &mut stream.state;
+
+                len = match_length(state, dist);
+                if len < STD_MIN_MATCH {
+                    break;
+                }
+
+                let bflush = state.tally_dist(dist, len - STD_MIN_MATCH);
+
+                let insert_cnt = Ord::min(len, (state.lookahead + 1).saturating_sub(STD_MIN_MATCH));
+                state.insert_string(state.strstart, insert_cnt);
+                state.lookahead -= len;
+                state.strstart += len;
+
+                if bflush {
+                    flush_block!(stream, false);
+                }
+            }
+        } else if state.match_available {
+            // If there was no match at the previous position, output a
+            // single literal. If there was a match but the current match
+            // is longer, truncate the previous match to a single literal.
+            let lc = state.window.filled()[state.strstart - 1];
+            let bflush = state.tally_lit(lc);
+            if bflush {
+                flush_block_only(stream, false);
+            }
+
+            let state = &mut stream.state;
+            state.prev_length = match_len;
+            state.prev_match = match_dist as u16;
+            state.strstart += 1;
+            state.lookahead -= 1;
+            if stream.avail_out == 0 {
+                return BlockState::NeedMore;
+            }
+        } else {
+            // There is no previous match to compare with, wait for
+            // the next step to decide.
+            state.prev_length = match_len;
+            state.prev_match = match_dist as u16;
+            state.match_available = true;
+            state.strstart += 1;
+            state.lookahead -= 1;
+        }
+    }
+
+    assert_ne!(flush, DeflateFlush::NoFlush, "no flush?");
+
+    let state = &mut stream.state;
+
+    if state.match_available {
+        let lc = state.window.filled()[state.strstart - 1];
+        let _ = state.tally_lit(lc);
+        state.match_available = false;
+    }
+
+    state.insert = Ord::min(state.strstart, STD_MIN_MATCH - 1);
+
+    if flush == DeflateFlush::Finish {
+        flush_block!(stream, true);
+        return BlockState::FinishDone;
+    }
+
+    if !stream.state.sym_buf.is_empty() {
+        flush_block!(stream, false);
+    }
+
+    BlockState::BlockDone
+}
+
+/// The length of the match at `strstart` with the string `dist` bytes back, at most
+/// [`STD_MAX_MATCH`] and the lookahead. Lengths below [`STD_MIN_MATCH`] are not exact.
+fn match_length(state: &State, dist: usize) -> usize {
+    let window = state.window.filled();
+    let scan = &window[state.strstart..];
+    let candidate = &window[state.strstart - dist..];
+
+    let len = match scan[..2] == candidate[..2] {
+        true => compare256_slice(&scan[2..], &candidate[2..]) + 2,
+        false => 0,
+    };
+
+    Ord::min(len, state.lookahead)
+}
+
+/// Find the longest match at `strstart` on the hash chain that starts at `cur_match`, a position
+/// modulo 64K. Returns the length and the distance of the match.
+fn longest_match(state: &State, cur_match: u16) -> (usize, usize) {
+    let strstart = state.strstart;
+    let window = state.window.filled();
+
+    let max_dist = Ord::min(state.max_dist(), strstart);
+    let max_len = Ord::min(STD_MAX_MATCH, state.lookahead);
+    let nice_match = Ord::min(state.nice_match, max_len);
+
+    // Do not waste too much time if we already have a good match
+    let mut chain_length = state.max_chain_length;
+    if state.prev_length >= state.good_match {
+        chain_length >>= 2;
+    }
+
+    let mut best_len = Ord::max(state.prev_length, STD_MIN_MATCH - 1);
+    let mut best_dist = 0;
+
+    let mut prev_dist = 0;
+    let mut dist = strstart.wrapping_sub(cur_match as usize) & POSITION_MASK;
+
+    while prev_dist < dist && dist <= max_dist && best_len < max_len {
+        let candidate = strstart - dist;
+
+        // the match must be longer than best_len, so check that byte first
+        if window[candidate + best_len] == window[strstart + best_len] {
+            let len = match_length(state, dist);
+            if len > best_len {
+                best_len = len;
+                best_dist = dist;
+
+                if len >= nice_match {
+                    break;
+                }
+            }
+        }
+
+        chain_length -= 1;
+        if chain_length == 0 {
+            break;
+        }
+
+        let next = state.prev.as_slice()[candidate & state.w_mask];
+        if next == 0 {
+            break;
+        }
+
+        prev_dist = dist;
+        dist = strstart.wrapping_sub(next as usize) & POSITION_MASK;
+    }
+
+    (Ord::min(best_len, max_len), best_dist)
+}
@@ -1,10 +1,12 @@
 use crate::{
-    deflate::{BlockState, DeflateStream, Strategy},
+    deflate::{Algorithm, BlockState, DeflateStream, Strategy, Token},
     DeflateFlush,
 };
 
-use self::{huff::deflate_huff, rle::deflate_rle, stored::deflate_stored};
+use self::{bypass::deflate_bypass, huff::deflate_huff, rle::deflate_rle, stored::deflate_stored};
 
+mod bypass;
+mod deflate64;
 mod fast;
 mod huff;
 mod medium;
@@ -12,13 +14,15 @@ mod quick;
 mod rle;
 mod slow;
 mod stored;
+mod tokens;
+mod zlib;
 
 #[macro_export]
 macro_rules! flush_block {
     ($stream:expr, $is_last_block:expr) => {
         $crate::deflate::flush_block_only($stream, $is_last_block);
 
-        if $stream.avail_out == 0 {
+        if $stream.avail_out == 0 || $stream.state.aborted {
             return match $is_last_block {
                 true => BlockState::FinishStarted,
                 false => BlockState::NeedMore,
@@ -31,14 +35,38 @@ pub fn run(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockState {
     match stream.state.strategy {
         _ if stream.state.level == 0 => deflate_stored(stream, flush),
         Strategy::HuffmanOnly => deflate_huff(stream, flush),
+        Strategy::Rle if stream.state.zlib_identical => zlib::deflate_rle(stream, flush),
         Strategy::Rle => deflate_rle(stream, flush),
+        _ if stream.state.deflate64 => deflate64::deflate64(stream, flush),
         Strategy::Default | Strategy::Filtered | Strategy::Fixed => {
-            (CONFIGURATION_TABLE[stream.state.level as usize].func)(stream, flush)
+            let compress = stream.state.compress_func();
+            match stream.state.skip_incompressible {
+                true => deflate_bypass(stream, flush, compress),
+                false => compress(stream, flush),
+            }
         }
     }
 }
 
-type CompressFunc = fn(&mut DeflateStream, flush: DeflateFlush) -> BlockState;
+pub fn run_tokens(
+    stream: &mut DeflateStream,
+    tokens: &mut &[Token],
+    flush: DeflateFlush,
+) -> BlockState {
+    tokens::deflate_tokens(stream, tokens, flush)
+}
+
+pub type CompressFunc = fn(&mut DeflateStream, flush: DeflateFlush) -> BlockState;
+
+pub fn compress_func(algorithm: Algorithm) -> CompressFunc {
+    match algorithm {
+        Algorithm::Quick => quick::deflate_quick,
+        Algorithm::QuickDynamic => quick::deflate_quick_dynamic,
+        Algorithm::Fast => fast::deflate_fast,
+        Algorithm::Medium => medium::deflate_medium,
+        Algorithm::Slow => slow::deflate_slow,
+    }
+}
 
 pub struct Config {
     pub good_length: u16, /* reduce lazy search above this match length */
@@ -80,3 +108,19 @@ pub const CONFIGURATION_TABLE: [Config; 10] = {
         Config::new(32, 258, 258, 4096, slow::deflate_slow), // 9 /* max compression */
     ]
 };
+
+/// The level table of stock zlib, used to produce zlib-identical output.
+pub const ZLIB_CONFIGURATION_TABLE: [Config; 10] = {
+    [
+        Config::new(0, 0, 0, 0, stored::deflate_stored), // 0 /* store only */
+        Config::new(4, 4, 8, 4, zlib::deflate_fast),     // 1 /* max speed, no lazy matches */
+        Config::new(4, 5, 16, 8, zlib::deflate_fast),    // 2
+        Config::new(4, 6, 32, 32, zlib::deflate_fast),   // 3
+        Config::new(4, 4, 16, 16, zlib::deflate_slow),   // 4 /* lazy matches */
+        Config::new(8, 16, 32, 32, zlib::deflate_slow),  // 5
+        Config::new(8, 16, 128, 128, zlib::deflate_slow), // 6
+        Config::new(8, 32, 128, 256, zlib::deflate_slow), // 7
+        Config::new(32, 128, 258, 1024, zlib::deflate_slow), // 8
+        Config::new(32, 258, 258, 4096, zlib::deflate_slow), // 9 /* max compression */
+    ]
+};
@@ -2,10 +2,11 @@
 
 use crate::{
     deflate::{
-        fill_window, flush_pending, BlockState, BlockType, DeflateStream, State, StaticTreeDesc,
-        MIN_LOOKAHEAD, STD_MAX_MATCH, STD_MIN_MATCH, WANT_MIN_MATCH,
+        bits_written, fill_window, flush_pending, report_progress, trace_block, BlockState,
+        BlockType, DeflateStream, State, StaticTreeDesc, MIN_LOOKAHEAD, STD_MAX_MATCH,
+        STD_MIN_MATCH, WANT_MIN_MATCH,
     },
-    DeflateFlush,
+    flush_block, DeflateFlush,
 };
 
 pub fn deflate_quick(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockState {
@@ -18,13 +19,25 @@ pub fn deflate_quick(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockSt
                     .bit_writer
                     .emit_end_block_and_align(&StaticTreeDesc::L.static_tree, $last);
                 state.block_open = 0;
+                let block_len = (state.strstart as isize - state.block_start) as usize;
                 state.block_start = state.strstart as isize;
+                let size = bits_written(stream).wrapping_sub(stream.state.quick_block_start);
+                state = &mut stream.state;
+                trace_block(state, BlockType::StaticTrees, $last, block_len, size);
+                let (unflushed, matches) = (state.lookahead, state.quick_matches);
+                report_progress(
+                    stream,
+                    BlockType::StaticTrees,
+                    block_len,
+                    unflushed,
+                    matches,
+                );
                 flush_pending(stream);
                 #[allow(unused_assignments)]
                 {
                     state = &mut stream.state;
                 }
-                if stream.avail_out == 0 {
+                if stream.avail_out == 0 || state.aborted {
                     return match $last {
                         true => BlockState::FinishStarted,
                         false => BlockState::NeedMore,
@@ -36,9 +49,13 @@ pub fn deflate_quick(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockSt
 
     macro_rules! quick_start_block {
         ($last:expr) => {
+            let block_start = bits_written(stream);
+            state = &mut stream.state;
+            state.quick_block_start = block_start;
             state.bit_writer.emit_tree(BlockType::StaticTrees, $last);
             state.block_open = 1 + $last as u8;
             state.block_start = state.strstart as isize;
+            state.quick_matches = 0;
         };
     }
 
@@ -92,46 +109,14 @@ pub fn deflate_quick(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockSt
             }
         }
 
-        if state.lookahead >= WANT_MIN_MATCH {
-            let hash_head = state.quick_insert_string(state.strstart);
-            let dist = state.strstart as isize - hash_head as isize;
-
-            if dist <= state.max_dist() as isize && dist > 0 {
-                let str_start = &state.window.filled()[state.strstart..];
-                let match_start = &state.window.filled()[hash_head as usize..];
-
-                macro_rules! first_two_bytes {
-                    ($slice:expr, $offset:expr) => {
-                        u16::from_le_bytes($slice[$offset..$offset + 2].try_into().unwrap())
-                    };
-                }
-
-                if first_two_bytes!(str_start, 0) == first_two_bytes!(match_start, 0) {
-                    let mut match_len = crate::deflate::compare256::compare256_slice(
-                        &str_start[2..],
-                        &match_start[2..],
-                    ) + 2;
-
-                    if match_len >= WANT_MIN_MATCH {
-                        match_len = Ord::min(match_len, state.lookahead);
-                        match_len = Ord::min(match_len, STD_MAX_MATCH);
-
-                        // TODO do this with a debug_assert?
-                        // check_match(s, state.strstart, hash_head, match_len);
-
-                        // The `dist` value is a distance within the window,
-                        // and MAX_WBITS == 15 (32k), hence a u16 can always represent this value.
-                        let dist = u16::try_from(dist).unwrap();
-
-                        state
-                            .bit_writer
-                            .emit_dist_static((match_len - STD_MIN_MATCH) as u8, dist);
-                        state.lookahead -= match_len;
-                        state.strstart += match_len;
-                        continue;
-                    }
-                }
-            }
+        if let Some((match_len, dist)) = find_match(state) {
+            state
+                .bit_writer
+                .emit_dist_static((match_len - STD_MIN_MATCH) as u8, dist);
+            state.quick_matches += 1;
+            state.lookahead -= match_len;
+            state.strstart += match_len;
+            continue;
         }
 
         let lc = state.window.filled()[state.strstart];
@@ -150,3 +135,102 @@ pub fn deflate_quick(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockSt
         BlockState::BlockDone
     }
 }
+
+/// Like [`deflate_quick`], but the symbols are buffered, so that every block is emitted with the
+/// cheapest of dynamic trees, static trees or a stored block. The matches are the same: a single
+/// hash lookup per position.
+pub fn deflate_quick_dynamic(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockState {
+    loop {
+        if stream.state.lookahead < MIN_LOOKAHEAD {
+            fill_window(stream);
+            if stream.state.lookahead < MIN_LOOKAHEAD && flush == DeflateFlush::NoFlush {
+                return BlockState::NeedMore;
+            }
+            if stream.state.lookahead == 0 {
+                break; /* flush the current block */
+            }
+        }
+
+        let state = &mut stream.state;
+
+        let bflush = match find_match(state) {
+            Some((match_len, dist)) => {
+                let bflush = state.tally_dist(usize::from(dist), match_len - STD_MIN_MATCH);
+                state.lookahead -= match_len;
+                state.strstart += match_len;
+                bflush
+            }
+            None => {
+                let lc = state.window.filled()[state.strstart];
+                let bflush = state.tally_lit(lc);
+                state.strstart += 1;
+                state.lookahead -= 1;
+                bflush
+            }
+        };
+
+        if bflush {
+            flush_block!(stream, false);
+        }
+    }
+
+    stream.state.insert = Ord::min(stream.state.strstart, STD_MIN_MATCH - 1);
+
+    if flush == DeflateFlush::Finish {
+        flush_block!(stream, true);
+        return BlockState::FinishDone;
+    }
+
+    if !stream.state.sym_buf.is_empty() {
+        flush_block!(stream, false);
+    }
+
+    BlockState::BlockDone
+}
+
+/// Look up the string at `strstart` in the hash table, and return the length and distance of the
+/// match with the candidate, if it is at least `WANT_MIN_MATCH` long. Only the string at
+/// `strstart` is inserted.
+#[inline(always)]
+fn find_match(state: &mut State) -> Option<(usize, u16)> {
+    if state.lookahead < WANT_MIN_MATCH {
+        return None;
+    }
+
+    let hash_head = state.quick_insert_string(state.strstart);
+    let dist = state.strstart as isize - hash_head as isize;
+
+    if dist > state.max_dist() as isize || dist <= 0 {
+        return None;
+    }
+
+    let str_start = &state.window.filled()[state.strstart..];
+    let match_start = &state.window.filled()[hash_head as usize..];
+
+    macro_rules! first_two_bytes {
+        ($slice:expr, $offset:expr) => {
+            u16::from_le_bytes($slice[$offset..$offset + 2].try_into().unwrap())
+        };
+    }
+
+    if first_two_bytes!(str_start, 0) != first_two_bytes!(match_start, 0) {
+        return None;
+    }
+
+    let mut match_len =
+        crate::deflate::compare256::compare256_slice(&str_start[2..], &match_start[2..]) + 2;
+
+    if match_len < WANT_MIN_MATCH {
+        return None;
+    }
+
+    match_len = Ord::min(match_len, state.lookahead);
+    match_len = Ord::min(match_len, STD_MAX_MATCH);
+
+    // TODO do this with a debug_assert?
+    // check_match(s, state.strstart, hash_head, match_len);
+
+    // The `dist` value is a distance within the window,
+    // and MAX_WBITS == 15 (32k), hence a u16 can always represent this value.
+    Some((match_len, u16::try_from(dist).unwrap()))
+}
@@ -2,8 +2,8 @@
 
 use crate::{
     deflate::{
-        fill_window, flush_block_only, BlockState, DeflateStream, Strategy, MIN_LOOKAHEAD,
-        STD_MIN_MATCH, WANT_MIN_MATCH,
+        fill_window, flush_block_only, hash_calc::HashCalcVariant, BlockState, DeflateStream,
+        Strategy, MIN_LOOKAHEAD, STD_MIN_MATCH, WANT_MIN_MATCH,
     },
     flush_block, DeflateFlush,
 };
@@ -14,7 +14,8 @@ pub fn deflate_slow(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockSta
     let mut dist;
     let mut match_len;
 
-    let use_longest_match_slow = stream.state.max_chain_length > 1024;
+    // the slow match finder rehashes with the rolling hash
+    let use_longest_match_slow = stream.state.hash_calc_variant == HashCalcVariant::Roll;
     let valid_distance_range = 1..=stream.state.max_dist() as isize;
 
     let mut match_available = stream.state.match_available;
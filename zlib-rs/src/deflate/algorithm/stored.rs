@@ -1,6 +1,7 @@
 use crate::{
     deflate::{
-        flush_pending, read_buf_window, zng_tr_stored_block, BlockState, DeflateStream, MAX_STORED,
+        bits_written, flush_pending, read_buf_window, report_progress, trace_block,
+        zng_tr_stored_block, BlockState, BlockType, DeflateStream, MAX_STORED,
     },
     DeflateFlush,
 };
@@ -61,6 +62,8 @@ pub fn deflate_stored(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockS
         // Make a dummy stored block in pending to get the header bytes,
         // including any pending bits. This also updates the debugging counts.
         last = flush == DeflateFlush::Finish && len == left + stream.avail_in as usize;
+        let block_len = len;
+        let bits_before = bits_written(stream);
         zng_tr_stored_block(stream.state, 0..0, last);
 
         /* Replace the lengths in the dummy stored block with len. */
@@ -103,7 +106,19 @@ pub fn deflate_stored(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockS
             read_buf_direct_copy(stream, len);
         }
 
-        if last {
+        let size = bits_written(stream).wrapping_sub(bits_before);
+        trace_block(stream.state, BlockType::StoredBlock, last, block_len, size);
+
+        let unflushed = (stream.state.strstart as isize - stream.state.block_start).max(0);
+        report_progress(
+            stream,
+            BlockType::StoredBlock,
+            block_len,
+            unflushed as usize,
+            0,
+        );
+
+        if last || stream.state.aborted {
             break;
         }
     }
@@ -165,6 +180,10 @@ pub fn deflate_stored(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockS
         return BlockState::FinishDone;
     }
 
+    if stream.state.aborted {
+        return BlockState::NeedMore;
+    }
+
     // If flushing and all input has been consumed, then done.
     if flush != DeflateFlush::NoFlush
         && flush != DeflateFlush::Finish
@@ -233,9 +252,16 @@ pub fn deflate_stored(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockS
         last = flush == DeflateFlush::Finish && stream.avail_in == 0 && len == (left as usize);
 
         let range = state.block_start as usize..state.block_start as usize + len;
+        let bits_before = state.bit_writer.pending_bits();
         zng_tr_stored_block(state, range, last);
 
+        let size = state.bit_writer.pending_bits() - bits_before;
+        trace_block(state, BlockType::StoredBlock, last, len, size);
+
         state.block_start += len as isize;
+        let unflushed = (left as usize) - len;
+        report_progress(stream, BlockType::StoredBlock, len, unflushed, 0);
+
         flush_pending(stream);
     }
 
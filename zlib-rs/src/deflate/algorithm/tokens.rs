@@ -0,0 +1,103 @@
+#![forbid(unsafe_code)]
+
+use crate::{
+    adler32,
+    deflate::{slide_window, BlockState, DeflateStream, Token, MAX_STORED, STD_MIN_MATCH},
+    flush_block, DeflateFlush, CRC32_INITIAL_VALUE,
+};
+
+/// Encode LZ77 tokens that were produced by the caller. The tokens have already been validated by
+/// [`crate::deflate::deflate_tokens`].
+///
+/// The input is reconstructed in the window, so that the checksum can be computed, and so that
+/// blocks can be stored if that is smaller. No strings are inserted into the hash table.
+pub fn deflate_tokens(
+    stream: &mut DeflateStream,
+    tokens: &mut &[Token],
+    flush: DeflateFlush,
+) -> BlockState {
+    while let Some((&token, rest)) = tokens.split_first() {
+        // make room for the longest match, like fill_window does
+        if stream.state.strstart >= stream.state.w_size + stream.state.max_dist() {
+            // at level 0 the block is always stored, so its input must stay in the window
+            if stream.state.level == 0 && stream.state.block_start < stream.state.w_size as isize {
+                flush_block!(stream, false);
+            }
+
+            slide_window(stream.state);
+        }
+
+        // at level 0 the block is stored, so the input that it covers must fit in a single stored
+        // block in the pending buffer
+        if stream.state.level == 0 {
+            let len = match token {
+                Token::Literal(_) => 1,
+                Token::Match { length, .. } => length as usize,
+            };
+            let max = Ord::min(MAX_STORED, stream.state.bit_writer.pending.capacity() - 5);
+            let block_len = (stream.state.strstart + len) as isize - stream.state.block_start;
+            if block_len > max as isize {
+                flush_block!(stream, false);
+            }
+        }
+
+        let state = &mut stream.state;
+
+        let start = state.strstart;
+
+        let bflush = match token {
+            Token::Literal(byte) => {
+                state.window.initialize_at_least(start + 1);
+                state.window.filled_mut()[start] = byte;
+
+                state.strstart += 1;
+                state.tally_lit(byte)
+            }
+            Token::Match { length, distance } => {
+                let length = length as usize;
+                let distance = distance as usize;
+
+                // the source and destination may overlap, so copy byte by byte
+                state.window.initialize_at_least(start + length);
+                let window = state.window.filled_mut();
+                for i in start..start + length {
+                    window[i] = window[i - distance];
+                }
+
+                state.strstart += length;
+                state.tally_dist(distance, length - STD_MIN_MATCH)
+            }
+        };
+
+        let data = &state.window.filled()[start..state.strstart];
+        match state.wrap {
+            2 => state.crc_fold.fold(data, CRC32_INITIAL_VALUE),
+            1 => stream.adler = adler32(stream.adler as u32, data) as _,
+            _ => {}
+        }
+        stream.total_in += data.len() as crate::c_api::z_size;
+
+        *tokens = rest;
+
+        if bflush {
+            flush_block!(stream, false);
+        }
+    }
+
+    if flush == DeflateFlush::NoFlush {
+        return BlockState::NeedMore;
+    }
+
+    stream.state.insert = 0;
+
+    if flush == DeflateFlush::Finish {
+        flush_block!(stream, true);
+        return BlockState::FinishDone;
+    }
+
+    if !stream.state.sym_buf.is_empty() {
+        flush_block!(stream, false);
+    }
+
+    BlockState::BlockDone
+}
@@ -0,0 +1,311 @@
+#![forbid(unsafe_code)]
+
+//! The algorithms of stock zlib, used to produce zlib-identical output.
+//!
+//! zlib-ng (and this crate) changed `deflate_fast`, `deflate_slow` and `deflate_rle` in ways that
+//! are faster, but that change which matches are found. These are faithful ports of the zlib
+//! versions: the same hash insertions, the same lazy evaluation and the same block boundaries.
+
+use crate::{
+    deflate::{
+        fill_window, flush_block_only, longest_match::longest_match_zlib, BlockState,
+        DeflateStream, Strategy, MIN_LOOKAHEAD, STD_MAX_MATCH, STD_MIN_MATCH,
+    },
+    flush_block, DeflateFlush,
+};
+
+/// Matches of length 3 are discarded if their distance exceeds TOO_FAR
+const TOO_FAR: usize = 4096;
+
+/// Compress as much as possible from the input stream, return the current block state.
+///
+/// This function does not perform lazy evaluation of matches and inserts new strings in the
+/// dictionary only for unmatched strings or for short matches.
+pub fn deflate_fast(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockState {
+    loop {
+        // Make sure that we always have enough lookahead, except
+        // at the end of the input file. We need STD_MAX_MATCH bytes
+        // for the next match, plus STD_MIN_MATCH bytes to insert the
+        // string following the next match.
+        if stream.state.lookahead < MIN_LOOKAHEAD {
+            fill_window(stream);
+            if stream.state.lookahead < MIN_LOOKAHEAD && flush == DeflateFlush::NoFlush {
+                return BlockState::NeedMore;
+            }
+            if stream.state.lookahead == 0 {
+                break; /* flush the current block */
+            }
+        }
+
+        let state = &mut stream.state;
+
+        // Insert the string window[strstart .. strstart+2] in the
+        // dictionary, and set hash_head to the head of the hash chain:
+        let mut hash_head = 0;
+        if state.lookahead >= STD_MIN_MATCH {
+            hash_head = state.quick_insert_string(state.strstart);
+        }
+
+        // Find the longest match, discarding those <= prev_length.
+        // At this point we have always match_length < STD_MIN_MATCH
+        if hash_head != 0 && state.strstart - hash_head as usize <= state.max_dist() {
+            // To simplify the code, we prevent matches with the string
+            // of window index 0 (in particular we have to avoid a match
+            // of the string with itself at the start of the input file).
+            (state.match_length, state.match_start) = longest_match_zlib(state, hash_head);
+        }
+
+        let bflush;
+        if state.match_length >= STD_MIN_MATCH {
+            bflush = state.tally_dist(
+                state.strstart - state.match_start,
+                state.match_length - STD_MIN_MATCH,
+            );
+
+            state.lookahead -= state.match_length;
+
+            // Insert new strings in the hash table only if the match length
+            // is not too large. This saves time but degrades compression.
+            if state.match_length <= state.max_insert_length() && state.lookahead >= STD_MIN_MATCH {
+                // string at strstart already in table
+                state.match_length -= 1;
+                while state.match_length != 0 {
+                    state.strstart += 1;
+                    state.quick_insert_string(state.strstart);
+                    state.match_length -= 1;
+                }
+                state.strstart += 1;
+            } else {
+                state.strstart += state.match_length;
+                state.match_length = 0;
+
+                // If lookahead < STD_MIN_MATCH, ins_h is garbage, but it does not
+                // matter since it will be recomputed at next deflate call.
+                let v0 = state.window.filled()[state.strstart] as u32;
+                let v1 = state.window.filled()[state.strstart + 1] as u32;
+                state.ins_h = state.update_hash(v0, v1) as usize;
+            }
+        } else {
+            // No match, output a literal byte
+            let lc = state.window.filled()[state.strstart];
+            bflush = state.tally_lit(lc);
+            state.lookahead -= 1;
+            state.strstart += 1;
+        }
+
+        if bflush {
+            flush_block!(stream, false);
+        }
+    }
+
+    stream.state.insert = Ord::min(stream.state.strstart, STD_MIN_MATCH - 1);
+
+    if flush == DeflateFlush::Finish {
+        flush_block!(stream, true);
+        return BlockState::FinishDone;
+    }
+
+    if !stream.state.sym_buf.is_empty() {
+        flush_block!(stream, false);
+    }
+
+    BlockState::BlockDone
+}
+
+/// Same as [`deflate_fast`], but achieves better compression. We use a lazy evaluation for
+/// matches: a match is finally adopted only if there is no better match at the next window
+/// position.
+pub fn deflate_slow(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockState {
+    loop {
+        // Make sure that we always have enough lookahead, except
+        // at the end of the input file. We need STD_MAX_MATCH bytes
+        // for the next match, plus STD_MIN_MATCH bytes to insert the
+        // string following the next match.
+        if stream.state.lookahead < MIN_LOOKAHEAD {
+            fill_window(stream);
+            if stream.state.lookahead < MIN_LOOKAHEAD && flush == DeflateFlush::NoFlush {
+                return BlockState::NeedMore;
+            }
+            if stream.state.lookahead == 0 {
+                break; /* flush the current block */
+            }
+        }
+
+        let state = &mut stream.state;
+
+        // Insert the string window[strstart .. strstart+2] in the
+        // dictionary, and set hash_head to the head of the hash chain:
+        let mut hash_head = 0;
+        if state.lookahead >= STD_MIN_MATCH {
+            hash_head = state.quick_insert_string(state.strstart);
+        }
+
+        // Find the longest match, discarding those <= prev_length.
+        state.prev_length = state.match_length;
+        state.prev_match = state.match_start as u16;
+        state.match_length = STD_MIN_MATCH - 1;
+
+        if hash_head != 0
+            && state.prev_length < state.max_lazy_match
+            && state.strstart - hash_head as usize <= state.max_dist()
+        {
+            // To simplify the code, we prevent matches with the string
+            // of window index 0 (in particular we have to avoid a match
+            // of the string with itself at the start of the input file).
+            (state.match_length, state.match_start) = longest_match_zlib(state, hash_head);
+
+            if state.match_length <= 5
+                && (state.strategy == Strategy::Filtered
+                    || (state.match_length == STD_MIN_MATCH
+                        && state.strstart - state.match_start > TOO_FAR))
+            {
+                // If prev_match is also STD_MIN_MATCH, match_start is garbage
+                // but we will ignore the current match anyway.
+                state.match_length = STD_MIN_MATCH - 1;
+            }
+        }
+
+        // If there was a match at the previous step and the current
+        // match is not better, output the previous match:
+        if state.prev_length >= STD_MIN_MATCH && state.match_length <= state.prev_length {
+            // Do not insert strings in hash table beyond this.
+            let max_insert = state.strstart + state.lookahead - STD_MIN_MATCH;
+
+            let bflush = state.tally_dist(
+                state.strstart - 1 - state.prev_match as usize,
+                state.prev_length - STD_MIN_MATCH,
+            );
+
+            // Insert in hash table all strings up to the end of the match.
+            // strstart - 1 and strstart are already inserted. If there is not
+            // enough lookahead, the last two strings are not inserted in
+            // the hash table.
+            state.lookahead -= state.prev_length - 1;
+            state.prev_length -= 2;
+            while state.prev_length != 0 {
+                state.strstart += 1;
+                if state.strstart <= max_insert {
+                    state.quick_insert_string(state.strstart);
+                }
+                state.prev_length -= 1;
+            }
+            state.match_available = false;
+            state.match_length = STD_MIN_MATCH - 1;
+            state.strstart += 1;
+
+            if bflush {
+                flush_block!(stream, false);
+            }
+        } else if state.match_available {
+            // If there was no match at the previous position, output a
+            // single literal. If there was a match but the current match
+            // is longer, truncate the previous match to a single literal.
+            let lc = state.window.filled()[state.strstart - 1];
+            if state.tally_lit(lc) {
+                flush_block_only(stream, false);
+            }
+            stream.state.strstart += 1;
+            stream.state.lookahead -= 1;
+            if stream.avail_out == 0 {
+                return BlockState::NeedMore;
+            }
+        } else {
+            // There is no previous match to compare with, wait for
+            // the next step to decide.
+            state.match_available = true;
+            state.strstart += 1;
+            state.lookahead -= 1;
+        }
+    }
+
+    assert_ne!(flush, DeflateFlush::NoFlush, "no flush?");
+
+    let state = &mut stream.state;
+
+    if state.match_available {
+        let lc = state.window.filled()[state.strstart - 1];
+        let _ = state.tally_lit(lc);
+        state.match_available = false;
+    }
+
+    state.insert = Ord::min(state.strstart, STD_MIN_MATCH - 1);
+
+    if flush == DeflateFlush::Finish {
+        flush_block!(stream, true);
+        return BlockState::FinishDone;
+    }
+
+    if !stream.state.sym_buf.is_empty() {
+        flush_block!(stream, false);
+    }
+
+    BlockState::BlockDone
+}
+
+/// For Strategy::Rle, simply look for runs of bytes, generate matches only of distance one.
+/// Do not maintain a hash table. (It will be regenerated if this run of deflate switches away
+/// from Strategy::Rle.)
+pub fn deflate_rle(stream: &mut DeflateStream, flush: DeflateFlush) -> BlockState {
+    loop {
+        // Make sure that we always have enough lookahead, except
+        // at the end of the input file. We need STD_MAX_MATCH bytes
+        // for the longest run, plus one for the unrolled loop.
+        if stream.state.lookahead <= STD_MAX_MATCH {
+            fill_window(stream);
+            if stream.state.lookahead <= STD_MAX_MATCH && flush == DeflateFlush::NoFlush {
+                return BlockState::NeedMore;
+            }
+            if stream.state.lookahead == 0 {
+                break; /* flush the current block */
+            }
+        }
+
+        // See how many times the previous byte repeats
+        let state = &mut stream.state;
+        state.match_length = 0;
+        if state.lookahead >= STD_MIN_MATCH && state.strstart > 0 {
+            let scan = &state.window.filled()[state.strstart - 1..][..=STD_MAX_MATCH];
+            let prev = scan[0];
+            if prev == scan[1] && prev == scan[2] && prev == scan[3] {
+                let run = scan[STD_MIN_MATCH + 1..]
+                    .iter()
+                    .take_while(|&&b| b == prev)
+                    .count();
+                state.match_length = Ord::min(STD_MIN_MATCH + run, state.lookahead);
+            }
+        }
+
+        // Emit match if have run of STD_MIN_MATCH or longer, else emit literal
+        let bflush;
+        if state.match_length >= STD_MIN_MATCH {
+            bflush = state.tally_dist(1, state.match_length - STD_MIN_MATCH);
+
+            state.lookahead -= state.match_length;
+            state.strstart += state.match_length;
+            state.match_length = 0;
+        } else {
+            // No match, output a literal byte
+            let lc = state.window.filled()[state.strstart];
+            bflush = state.tally_lit(lc);
+            state.lookahead -= 1;
+            state.strstart += 1;
+        }
+
+        if bflush {
+            flush_block!(stream, false);
+        }
+    }
+
+    stream.state.insert = 0;
+
+    if flush == DeflateFlush::Finish {
+        flush_block!(stream, true);
+        return BlockState::FinishDone;
+    }
+
+    if !stream.state.sym_buf.is_empty() {
+        flush_block!(stream, false);
+    }
+
+    BlockState::BlockDone
+}
@@ -0,0 +1,307 @@
+#![forbid(unsafe_code)]
+
+//! Split a block where the statistics of its symbols change.
+//!
+//! Normally a block is flushed when the symbol buffer is full. When the character of the data
+//! changes within a block, e.g. text followed by binary data in a tarball, the huffman trees of
+//! that block fit neither part well. Before such a block is flushed, we estimate the cost of
+//! encoding it as a whole and as two parts, at a number of candidate split points. If splitting is
+//! cheaper, the parts are considered for further splitting in the same way.
+
+use crate::deflate::{trees_tbl, State, D_CODES, LITERALS, L_CODES};
+
+/// The maximum number of blocks that one block is split into.
+pub(crate) const MAX_BLOCKS: usize = 16;
+
+/// The minimum number of symbols in a block produced by splitting. Smaller blocks rarely make up
+/// for the cost of sending their trees.
+const MIN_BLOCK_SYMBOLS: usize = 1024;
+
+/// The number of candidate split points considered when splitting a block in two.
+const CANDIDATES: usize = 16;
+
+/// Estimated cost (in bits) of the header of a dynamic block, excluding the code lengths.
+const DYNAMIC_HEADER_BITS: u64 = 3 + 5 + 5 + 4 + 3 * 19;
+
+/// Estimated cost (in bits) of sending the code length of a used symbol in a dynamic block.
+const CODE_LENGTH_BITS: u64 = 4;
+
+/// The boundaries of the blocks that a block is split into, as offsets into the symbol buffer.
+pub(crate) struct Blocks {
+    boundaries: [usize; MAX_BLOCKS + 1],
+    len: usize,
+}
+
+impl Blocks {
+    /// The ranges of the symbol buffer that should each be flushed as a separate block.
+    pub(crate) fn ranges(&self) -> impl Iterator<Item = core::ops::Range<usize>> + '_ {
+        self.boundaries[..self.len].windows(2).map(|w| w[0]..w[1])
+    }
+
+    /// The number of blocks.
+    pub(crate) fn count(&self) -> usize {
+        self.len - 1
+    }
+
+    fn insert(&mut self, index: usize, boundary: usize) {
+        self.boundaries.copy_within(index..self.len, index + 1);
+        self.boundaries[index] = boundary;
+        self.len += 1;
+    }
+}
+
+/// Determine where the block in `sym_buf` should be split.
+pub(crate) fn split(sym_buf: &[u8]) -> Blocks {
+    let mut blocks = Blocks {
+        boundaries: [0; MAX_BLOCKS + 1],
+        len: 2,
+    };
+    blocks.boundaries[1] = sym_buf.len();
+
+    // bit i is set when block i cannot be split any further
+    let mut done = 0u32;
+
+    while blocks.count() < MAX_BLOCKS {
+        let mut changed = false;
+
+        let mut i = 0;
+        while i < blocks.count() && blocks.count() < MAX_BLOCKS {
+            if done & (1 << i) == 0 {
+                let start = blocks.boundaries[i];
+                let end = blocks.boundaries[i + 1];
+
+                match best_split(&sym_buf[start..end]) {
+                    None => done |= 1 << i,
+                    Some(at) => {
+                        blocks.insert(i + 1, start + at);
+
+                        // shift the flags of the blocks after this one
+                        let low = done & ((1 << i) - 1);
+                        done = low | ((done >> (i + 1)) << (i + 2));
+
+                        changed = true;
+                        i += 1;
+                    }
+                }
+            }
+
+            i += 1;
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    blocks
+}
+
+/// Find the split point (an offset into `symbols`) that minimizes the estimated cost of the two
+/// resulting blocks, if that is cheaper than not splitting at all.
+fn best_split(symbols: &[u8]) -> Option<usize> {
+    let n = symbols.len() / 3;
+    if n < 2 * MIN_BLOCK_SYMBOLS {
+        return None;
+    }
+
+    let step = Ord::max(MIN_BLOCK_SYMBOLS, n / CANDIDATES);
+
+    let mut left = Histogram::EMPTY;
+    let mut right = Histogram::EMPTY;
+    right.add(symbols);
+
+    let whole = right.cost();
+    let mut best = None;
+    let mut best_cost = whole;
+
+    let mut previous = 0;
+    for at in (step..=n - step).step_by(step) {
+        let moved = &symbols[3 * previous..3 * at];
+        left.add(moved);
+        right.remove(moved);
+        previous = at;
+
+        let cost = left.cost() + right.cost();
+        if cost < best_cost {
+            best_cost = cost;
+            best = Some(3 * at);
+        }
+    }
+
+    best
+}
+
+struct Histogram {
+    lit: [u32; L_CODES],
+    dist: [u32; D_CODES],
+}
+
+impl Histogram {
+    const EMPTY: Self = Self {
+        lit: [0; L_CODES],
+        dist: [0; D_CODES],
+    };
+
+    fn add(&mut self, symbols: &[u8]) {
+        for chunk in symbols.chunks_exact(3) {
+            let [dist_low, dist_high, lc] = *chunk else {
+                unreachable!("out of bound access on the symbol buffer");
+            };
+
+            match u16::from_le_bytes([dist_low, dist_high]) {
+                0 => self.lit[lc as usize] += 1,
+                dist => {
+                    self.lit[trees_tbl::LENGTH_CODE[lc as usize] as usize + LITERALS + 1] += 1;
+                    self.dist[State::d_code(dist as usize - 1) as usize] += 1;
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, symbols: &[u8]) {
+        for chunk in symbols.chunks_exact(3) {
+            let [dist_low, dist_high, lc] = *chunk else {
+                unreachable!("out of bound access on the symbol buffer");
+            };
+
+            match u16::from_le_bytes([dist_low, dist_high]) {
+                0 => self.lit[lc as usize] -= 1,
+                dist => {
+                    self.lit[trees_tbl::LENGTH_CODE[lc as usize] as usize + LITERALS + 1] -= 1;
+                    self.dist[State::d_code(dist as usize - 1) as usize] -= 1;
+                }
+            }
+        }
+    }
+
+    /// Estimated cost in bits of a block with these symbols, excluding the extra bits of lengths
+    /// and distances (they are the same however the symbols are split).
+    fn cost(&self) -> u64 {
+        // the end of block symbol is not in the histogram
+        const END_BLOCK: usize = 256;
+
+        let dynamic = DYNAMIC_HEADER_BITS
+            + tree_cost(&self.lit, END_BLOCK)
+            + tree_cost(&self.dist, usize::MAX);
+
+        let mut fixed = 3 + 7;
+        for (code, freq) in self.lit.iter().enumerate() {
+            let len = match code {
+                0..=143 => 8,
+                144..=255 => 9,
+                256..=279 => 7,
+                _ => 8,
+            };
+            fixed += len * *freq as u64;
+        }
+        fixed += 5 * self.dist.iter().map(|&f| f as u64).sum::<u64>();
+
+        Ord::min(dynamic, fixed)
+    }
+}
+
+/// Estimate the cost in bits of the symbols with these frequencies, and of sending their code
+/// lengths, when encoded with a huffman tree. `extra` is a symbol that occurs once more.
+fn tree_cost(freqs: &[u32], extra: usize) -> u64 {
+    let freq = |code: usize| freqs[code] as u64 + (code == extra) as u64;
+
+    let total: u64 = (0..freqs.len()).map(freq).sum();
+    if total == 0 {
+        return 0;
+    }
+
+    let log2_total = log2_fixed(total);
+
+    let mut bits = 0;
+    for code in 0..freqs.len() {
+        let f = freq(code);
+        if f != 0 {
+            // codes are between 1 and 15 bits long
+            let len = log2_total
+                .saturating_sub(log2_fixed(f))
+                .clamp(1 << FRACTION_BITS, 15 << FRACTION_BITS);
+            bits += f * len + (CODE_LENGTH_BITS << FRACTION_BITS);
+        }
+    }
+
+    bits >> FRACTION_BITS
+}
+
+const FRACTION_BITS: u32 = 16;
+
+/// An approximation of `log2(x)`, in fixed point with `FRACTION_BITS` fractional bits.
+fn log2_fixed(x: u64) -> u64 {
+    debug_assert!(x > 0);
+
+    const ONE: u64 = 1 << FRACTION_BITS;
+
+    let integer = x.ilog2();
+
+    // the fractional part of x / 2^integer, which is in [1, 2)
+    let fraction = if integer >= FRACTION_BITS {
+        (x >> (integer - FRACTION_BITS)) - ONE
+    } else {
+        (x << (FRACTION_BITS - integer)) - ONE
+    };
+
+    // log2(1 + f) ~= f + c * f * (1 - f), with c = 0.3466 (an error of at most 0.01)
+    let correction = (fraction * (ONE - fraction) * 22713) >> (2 * FRACTION_BITS);
+
+    ((integer as u64) << FRACTION_BITS) + fraction + correction
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn literals(bytes: &[u8]) -> impl Iterator<Item = u8> + '_ {
+        bytes.iter().flat_map(|&b| [0, 0, b])
+    }
+
+    #[test]
+    fn log2_approximation() {
+        for x in 1..100_000u64 {
+            let expected = (x as f64).log2();
+            let actual = log2_fixed(x) as f64 / (1u64 << FRACTION_BITS) as f64;
+            assert!(
+                (expected - actual).abs() < 0.01,
+                "{x}: {expected} vs {actual}"
+            );
+        }
+    }
+
+    #[test]
+    fn homogeneous_block_is_not_split() {
+        // bytes from a fixed (skewed) distribution
+        let mut seed = 42u32;
+        let bytes: Vec<u8> = (0..8 * MIN_BLOCK_SYMBOLS)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                b"eeeeeeetttttaaaooinnsrhld"[(seed >> 16) as usize % 25]
+            })
+            .collect();
+        let symbols: Vec<u8> = literals(&bytes).collect();
+
+        let blocks = split(&symbols);
+        assert_eq!(blocks.count(), 1);
+        assert_eq!(blocks.ranges().next(), Some(0..symbols.len()));
+    }
+
+    #[test]
+    fn split_where_statistics_change() {
+        let mut symbols = Vec::new();
+        symbols.extend(literals(
+            &[b'a', b'b', b'c', b'd'].repeat(MIN_BLOCK_SYMBOLS),
+        ));
+        symbols.extend(literals(
+            &[0xf0, 0xf1, 0xf2, 0xf3].repeat(MIN_BLOCK_SYMBOLS),
+        ));
+
+        let blocks = split(&symbols);
+        let ranges: Vec<_> = blocks.ranges().collect();
+        assert_eq!(
+            ranges,
+            [0..symbols.len() / 2, symbols.len() / 2..symbols.len()]
+        );
+    }
+}
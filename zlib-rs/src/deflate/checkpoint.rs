@@ -0,0 +1,618 @@
+//! Suspend and resume compression.
+//!
+//! [`save`] serializes the state of a deflate stream into a versioned byte blob, and [`restore`]
+//! loads it into another stream, possibly in another process. The restored stream continues where
+//! the original stopped and produces an identical stream. Unlike [`copy`], which makes an in-memory
+//! deep copy, the checkpoint can be persisted.
+//!
+//! A checkpoint can only be made between blocks, e.g. after a call to [`deflate`] with
+//! [`DeflateFlush::SyncFlush`] or [`DeflateFlush::Block`]: the symbols of a block that is in
+//! progress are not part of the checkpoint. The checkpoint contains the window, the hash table and
+//! chains, the pending output and bits, the running checksum and the progress of the gzip header.
+//! With the default settings, that is about 256K.
+//!
+//! The gzip header itself (see [`set_header`]) is not part of the checkpoint, and must be set
+//! again.
+//!
+//! [`copy`]: crate::deflate::copy
+//! [`deflate`]: crate::deflate::deflate
+//! [`DeflateFlush::SyncFlush`]: crate::DeflateFlush::SyncFlush
+//! [`DeflateFlush::Block`]: crate::DeflateFlush::Block
+//! [`set_header`]: crate::deflate::set_header
+
+use alloc::vec::Vec;
+
+use crate::{crc32::Crc32Fold, ReturnCode};
+
+use super::{
+    hash_calc::{Crc32HashCalc, HashCalcVariant},
+    DeflateStream, State, Status, Strategy, HASH_SIZE, STD_MAX_MATCH,
+};
+
+/// Identifies the serialized format.
+const MAGIC: [u8; 4] = *b"zdck";
+const VERSION: u8 = 1;
+
+/// Serialize the state of `stream`, which can be resumed with [`restore`]. Returns
+/// [`ReturnCode::StreamError`] when a block is in progress.
+pub fn save(stream: &DeflateStream) -> Result<Vec<u8>, ReturnCode> {
+    let state = &stream.state;
+
+    if !state.sym_buf.is_empty() || state.block_open != 0 {
+        return Err(ReturnCode::StreamError);
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(VERSION);
+
+    // the configuration that the restored stream must have been initialized with
+    bytes.extend_from_slice(&compatibility(state));
+    bytes.push(state.level_config.is_some() as u8);
+
+    bytes.push(state.status as u8);
+    bytes.push(state.last_flush as u8);
+    bytes.push(state.wrap as u8);
+    bytes.push(state.strategy as u8);
+    bytes.push(state.level as u8);
+    bytes.push(state.hash_calc_variant as u8);
+    bytes.push(state.match_available as u8);
+    bytes.push(state.bypassing as u8);
+    bytes.push(state.aborted as u8);
+    bytes.extend_from_slice(&state.prev_match.to_le_bytes());
+
+    for field in [
+        state.good_match,
+        state.nice_match,
+        state.max_chain_length,
+        state.max_lazy_match,
+        state.match_length,
+        state.strstart,
+        state.match_start,
+        state.prev_length,
+        state.matches,
+        state.opt_len,
+        state.static_len,
+        state.insert,
+        state.lookahead,
+        state.ins_h,
+        // only the progress through the extra field is tracked
+        match state.status {
+            Status::Extra => state.gzindex,
+            _ => 0,
+        },
+    ] {
+        bytes.extend_from_slice(&(field as u64).to_le_bytes());
+    }
+
+    // both can be negative
+    bytes.extend_from_slice(&(state.block_start as i64).to_le_bytes());
+    bytes.extend_from_slice(&(state.bypass_region as i64).to_le_bytes());
+
+    bytes.extend_from_slice(&stream.total_in.to_le_bytes());
+    bytes.extend_from_slice(&stream.total_out.to_le_bytes());
+    bytes.extend_from_slice(&(stream.adler as u32).to_le_bytes());
+    bytes.extend_from_slice(&stream.data_type.to_le_bytes());
+    bytes.extend_from_slice(&state.crc_fold.finish().to_le_bytes());
+
+    let bit_writer = &state.bit_writer;
+    bytes.push(bit_writer.bits_used);
+    bytes.extend_from_slice(&bit_writer.bit_buffer.to_le_bytes());
+
+    let pending = bit_writer.pending.pending();
+    bytes.extend_from_slice(&(pending.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(pending);
+
+    let window = state.window.initialized();
+    bytes.extend_from_slice(&(state.window.filled().len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(window.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(window);
+
+    for &pos in state.prev.as_slice().iter().chain(state.head.as_slice()) {
+        bytes.extend_from_slice(&pos.to_le_bytes());
+    }
+
+    Ok(bytes)
+}
+
+/// Restore a checkpoint that was created with [`save`] into `stream`, which must have been
+/// initialized with the same configuration as the stream of the checkpoint. Returns
+/// [`ReturnCode::StreamError`] when the configurations differ, and [`ReturnCode::DataError`] when
+/// the bytes are not a valid checkpoint.
+pub fn restore(stream: &mut DeflateStream, bytes: &[u8]) -> ReturnCode {
+    let err = super::reset(stream);
+    if err != ReturnCode::Ok {
+        return err;
+    }
+
+    match restore_help(stream, bytes) {
+        Ok(()) => ReturnCode::Ok,
+        Err(err) => {
+            // don't leave a partially restored stream behind
+            let _ = super::reset(stream);
+            err
+        }
+    }
+}
+
+fn restore_help(stream: &mut DeflateStream, mut bytes: &[u8]) -> Result<(), ReturnCode> {
+    fn take<'a, const N: usize>(bytes: &mut &'a [u8]) -> Result<&'a [u8; N], ReturnCode> {
+        take_slice(bytes, N).map(|slice| slice.try_into().unwrap())
+    }
+
+    fn take_slice<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], ReturnCode> {
+        if bytes.len() < n {
+            return Err(ReturnCode::DataError);
+        }
+
+        let (head, tail) = bytes.split_at(n);
+        *bytes = tail;
+        Ok(head)
+    }
+
+    fn take_u32(bytes: &mut &[u8]) -> Result<u32, ReturnCode> {
+        Ok(u32::from_le_bytes(*take(bytes)?))
+    }
+
+    fn take_u64(bytes: &mut &[u8]) -> Result<u64, ReturnCode> {
+        Ok(u64::from_le_bytes(*take(bytes)?))
+    }
+
+    fn check(valid: bool) -> Result<(), ReturnCode> {
+        match valid {
+            true => Ok(()),
+            false => Err(ReturnCode::DataError),
+        }
+    }
+
+    let state = &mut *stream.state;
+
+    check(*take(&mut bytes)? == MAGIC && *take(&mut bytes)? == [VERSION])?;
+
+    let compatible = *take(&mut bytes)? == compatibility(state);
+    let &[level_config] = take(&mut bytes)?;
+    if !compatible || (level_config != 0 && state.level_config.is_none()) {
+        return Err(ReturnCode::StreamError);
+    }
+
+    if level_config == 0 {
+        state.level_config = None;
+    }
+
+    let &[status, last_flush, wrap, strategy, level, hash_calc_variant, match_available, bypassing, aborted] =
+        take(&mut bytes)?;
+
+    state.status = match status {
+        1 => Status::Init,
+        2 => Status::Busy,
+        3 => Status::Finish,
+        4 => Status::GZip,
+        5 => Status::Extra,
+        6 => Status::Name,
+        7 => Status::Comment,
+        8 => Status::Hcrc,
+        _ => return Err(ReturnCode::DataError),
+    };
+
+    state.hash_calc_variant = match hash_calc_variant {
+        0 => HashCalcVariant::Standard,
+        // the hash function determines the matches that are found, so it cannot be replaced
+        1 if !Crc32HashCalc::is_supported() => return Err(ReturnCode::StreamError),
+        1 => HashCalcVariant::Crc32,
+        2 => HashCalcVariant::Roll,
+        3 => HashCalcVariant::Zlib,
+        _ => return Err(ReturnCode::DataError),
+    };
+
+    let Ok(strategy) = Strategy::try_from(strategy as i32) else {
+        return Err(ReturnCode::DataError);
+    };
+
+    check(level <= 9 && (wrap as i8).unsigned_abs() == state.wrap.unsigned_abs())?;
+
+    state.last_flush = last_flush as i8;
+    state.wrap = wrap as i8;
+    state.strategy = strategy;
+    state.level = level as i8;
+    state.match_available = match_available != 0;
+    state.bypassing = bypassing != 0;
+    state.aborted = aborted != 0;
+    state.prev_match = u16::from_le_bytes(*take(&mut bytes)?);
+
+    let mut fields = [0; 15];
+    for field in &mut fields {
+        *field = usize::try_from(take_u64(&mut bytes)?).map_err(|_| ReturnCode::DataError)?;
+    }
+
+    [
+        state.good_match,
+        state.nice_match,
+        state.max_chain_length,
+        state.max_lazy_match,
+        state.match_length,
+        state.strstart,
+        state.match_start,
+        state.prev_length,
+        state.matches,
+        state.opt_len,
+        state.static_len,
+        state.insert,
+        state.lookahead,
+        state.ins_h,
+        state.gzindex,
+    ] = fields;
+
+    state.block_start = take_u64(&mut bytes)? as i64 as isize;
+    state.bypass_region = take_u64(&mut bytes)? as i64 as usize;
+
+    stream.total_in = take_u64(&mut bytes)? as _;
+    stream.total_out = take_u64(&mut bytes)? as _;
+    stream.adler = take_u32(&mut bytes)? as _;
+    stream.data_type = take_u32(&mut bytes)? as i32;
+    state.crc_fold = Crc32Fold::resume(take_u32(&mut bytes)?);
+
+    let &[bits_used] = take(&mut bytes)?;
+    let bit_buffer = take_u64(&mut bytes)?;
+    check(bits_used <= State::BIT_BUF_SIZE)?;
+    state.bit_writer.bits_used = bits_used;
+    state.bit_writer.bit_buffer = bit_buffer;
+
+    let len = take_u32(&mut bytes)? as usize;
+    let pending = take_slice(&mut bytes, len)?;
+    check(pending.len() <= state.bit_writer.pending.capacity())?;
+    state.bit_writer.pending.extend(pending);
+
+    let filled = take_u32(&mut bytes)? as usize;
+    let len = take_u32(&mut bytes)? as usize;
+    let window = take_slice(&mut bytes, len)?;
+    check(filled <= window.len() && window.len() <= state.window.capacity())?;
+    state.window.restore(window, filled);
+
+    let prev = state.prev.as_mut_slice();
+    for pos in prev.iter_mut() {
+        *pos = u16::from_le_bytes(*take(&mut bytes)?);
+    }
+
+    for pos in state.head.as_mut_slice().iter_mut() {
+        *pos = u16::from_le_bytes(*take(&mut bytes)?);
+    }
+
+    check(bytes.is_empty())?;
+
+    // the match finders read the window without bounds checks, so the positions must be in bounds
+    let window_size = state.window_size;
+    let positions_valid = state.deflate64
+        || (state.prev.as_slice().iter())
+            .chain(state.head.as_slice())
+            .all(|&pos| (pos as usize) < window_size);
+
+    check(
+        positions_valid
+            && state.strstart + state.lookahead <= filled
+            && state.strstart <= window_size
+            && state.match_start < window_size
+            && state.insert <= state.strstart
+            && state.match_length <= STD_MAX_MATCH
+            && state.prev_length <= STD_MAX_MATCH
+            && state.ins_h < HASH_SIZE
+            && (state.gzindex == 0 || state.status == Status::Extra)
+            && state.block_start <= state.strstart as isize
+            && state.block_start >= -(window_size as isize),
+    )
+}
+
+/// The configuration that determines the sizes of the buffers and the output of the stream.
+fn compatibility(state: &State) -> [u8; 8] {
+    let lit_bufsize = (state.lit_bufsize as u32).to_le_bytes();
+    let options = [
+        state.zlib_identical,
+        state.block_splitting,
+        state.skip_incompressible,
+        state.optimal_huffman,
+        state.deflate64,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |options, (i, &option)| options | (option as u8) << i);
+
+    [
+        state.w_bits as u8,
+        state.hash_bits as u8,
+        state.wrap.unsigned_abs(),
+        options,
+        lit_bufsize[0],
+        lit_bufsize[1],
+        lit_bufsize[2],
+        lit_bufsize[3],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        c_api::{gz_header, z_stream},
+        deflate::{self, DeflateConfig},
+        inflate::{self, InflateConfig},
+        DeflateFlush,
+    };
+
+    const INPUT: &[u8] = include_bytes!("test-data/paper-100k.pdf");
+
+    fn with_stream<T>(config: DeflateConfig, f: impl FnOnce(&mut DeflateStream) -> T) -> T {
+        let mut stream = z_stream::default();
+        assert_eq!(deflate::init(&mut stream, config), ReturnCode::Ok);
+
+        // SAFETY: the stream was successfully initialized
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let result = f(stream);
+
+        let _ = deflate::end(stream);
+
+        result
+    }
+
+    /// Compress `INPUT` in chunks, with a sync flush after every chunk. When `checkpoints` is
+    /// true, every call to deflate is made by a new stream restored from a checkpoint of the
+    /// previous one. The output buffer is small, so that checkpoints also contain pending output.
+    fn compress(config: DeflateConfig, checkpoints: bool) -> Vec<u8> {
+        fn step(
+            stream: &mut DeflateStream,
+            input: &[u8],
+            output: &mut [u8],
+            flush: DeflateFlush,
+        ) -> (ReturnCode, bool) {
+            let len = Ord::min(output.len(), 5_000);
+            let output = &mut output[..len];
+
+            stream.next_in = input.as_ptr() as *mut u8;
+            stream.avail_in = input.len() as _;
+            stream.next_out = output.as_mut_ptr();
+            stream.avail_out = output.len() as _;
+
+            let err = deflate::deflate(stream, flush);
+
+            (err, stream.avail_out != 0)
+        }
+
+        let mut output = vec![0; deflate::compress_bound(INPUT.len()) + 10_000];
+        let (mut total_in, mut total_out) = (0, 0);
+        let mut chunk_end = Ord::min(INPUT.len(), 7_000);
+
+        with_stream(config, |stream| {
+            let mut checkpoint = save(stream).unwrap();
+
+            loop {
+                let flush = match chunk_end == INPUT.len() {
+                    true => DeflateFlush::Finish,
+                    false => DeflateFlush::SyncFlush,
+                };
+
+                let input = &INPUT[total_in..chunk_end];
+                let out = &mut output[total_out..];
+
+                let (err, flushed) = match checkpoints {
+                    false => {
+                        let result = step(stream, input, out, flush);
+                        total_in = stream.total_in as usize;
+                        total_out = stream.total_out as usize;
+                        result
+                    }
+                    true => with_stream(config, |stream| {
+                        assert_eq!(restore(stream, &checkpoint), ReturnCode::Ok);
+                        assert_eq!(stream.total_in as usize, total_in);
+
+                        let result = step(stream, input, out, flush);
+                        total_in = stream.total_in as usize;
+                        total_out = stream.total_out as usize;
+                        checkpoint = save(stream).unwrap();
+                        result
+                    }),
+                };
+
+                match err {
+                    ReturnCode::StreamEnd => break,
+                    err => assert_eq!(err, ReturnCode::Ok),
+                }
+
+                if flushed {
+                    assert_eq!(total_in, chunk_end);
+                    chunk_end = Ord::min(INPUT.len(), chunk_end + 7_000);
+                }
+            }
+        });
+
+        output.truncate(total_out);
+        output
+    }
+
+    #[test]
+    fn identical_output() {
+        let mut configs = vec![];
+        for level in 0..=9 {
+            for window_bits in [15, 31, -9] {
+                configs.push(DeflateConfig {
+                    window_bits,
+                    ..DeflateConfig::new(level)
+                });
+            }
+        }
+
+        configs.push(DeflateConfig {
+            zlib_identical: true,
+            ..DeflateConfig::new(6)
+        });
+        configs.push(DeflateConfig {
+            window_bits: -15,
+            deflate64: true,
+            ..DeflateConfig::new(9)
+        });
+
+        for config in configs {
+            let expected = compress(config, false);
+            let output = compress(config, true);
+            assert!(output == expected, "{config:?}");
+
+            let inflate_config = InflateConfig {
+                window_bits: config.window_bits,
+                deflate64: config.deflate64,
+                ..Default::default()
+            };
+            let mut decompressed = vec![0; INPUT.len()];
+            let (decompressed, err) =
+                inflate::uncompress_slice(&mut decompressed, &output, inflate_config);
+            assert_eq!(err, ReturnCode::Ok);
+            assert!(decompressed == INPUT);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let config = DeflateConfig::new(6);
+
+        let checkpoint = with_stream(config, |stream| {
+            let mut output = vec![0; 100_000];
+
+            // a block is in progress
+            stream.next_in = INPUT.as_ptr() as *mut u8;
+            stream.avail_in = 20_000;
+            stream.next_out = output.as_mut_ptr();
+            stream.avail_out = output.len() as _;
+            assert_eq!(
+                deflate::deflate(stream, DeflateFlush::NoFlush),
+                ReturnCode::Ok
+            );
+            assert_eq!(save(stream), Err(ReturnCode::StreamError));
+
+            loop {
+                stream.next_out = output.as_mut_ptr();
+                stream.avail_out = output.len() as _;
+                assert_eq!(
+                    deflate::deflate(stream, DeflateFlush::Block),
+                    ReturnCode::Ok
+                );
+
+                if stream.avail_out != 0 {
+                    break save(stream).unwrap();
+                }
+            }
+        });
+
+        with_stream(config, |stream| {
+            assert_eq!(restore(stream, &checkpoint), ReturnCode::Ok);
+
+            assert_eq!(
+                restore(stream, &checkpoint[..checkpoint.len() - 1]),
+                ReturnCode::DataError
+            );
+
+            let mut bytes = checkpoint.clone();
+            bytes[4] = VERSION + 1;
+            assert_eq!(restore(stream, &bytes), ReturnCode::DataError);
+        });
+
+        let config = DeflateConfig {
+            window_bits: 14,
+            ..config
+        };
+        with_stream(config, |stream| {
+            assert_eq!(restore(stream, &checkpoint), ReturnCode::StreamError);
+        });
+    }
+
+    #[test]
+    fn pending_gzip_header() {
+        fn with_header<T>(
+            config: DeflateConfig,
+            header: &mut gz_header,
+            f: impl FnOnce(&mut DeflateStream) -> T,
+        ) -> T {
+            let mut stream = z_stream::default();
+            assert_eq!(deflate::init(&mut stream, config), ReturnCode::Ok);
+
+            // SAFETY: the stream was successfully initialized
+            let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+            // SAFETY: the extra field is valid for `extra_len` bytes
+            let err = unsafe { deflate::set_header(stream, Some(header)) };
+            assert_eq!(err, ReturnCode::Ok);
+
+            let result = f(stream);
+
+            let _ = deflate::end(stream);
+
+            result
+        }
+
+        fn compress(stream: &mut DeflateStream, output: &mut [u8]) -> ReturnCode {
+            stream.next_in = INPUT.as_ptr() as *mut u8;
+            stream.avail_in = INPUT.len() as _;
+            stream.next_out = output.as_mut_ptr();
+            stream.avail_out = output.len() as _;
+
+            deflate::deflate(stream, DeflateFlush::Finish)
+        }
+
+        let extra = vec![b'x'; 2_000];
+        let mut header = gz_header {
+            extra: extra.as_ptr() as *mut _,
+            extra_len: extra.len() as _,
+            hcrc: 1,
+            ..gz_header::default()
+        };
+
+        // the pending buffer is 512 bytes, so the extra field takes several calls
+        let config = DeflateConfig {
+            window_bits: 31,
+            mem_level: 1,
+            ..DeflateConfig::new(6)
+        };
+
+        let mut expected = vec![0; deflate::compress_bound(INPUT.len()) + extra.len()];
+        let expected_len = with_header(config, &mut header, |stream| {
+            assert_eq!(compress(stream, &mut expected), ReturnCode::StreamEnd);
+            stream.total_out as usize
+        });
+
+        let mut output = vec![0; expected.len()];
+        let checkpoint = with_header(config, &mut header, |stream| {
+            assert_eq!(compress(stream, &mut output[..100]), ReturnCode::Ok);
+            assert_eq!(stream.state.status, Status::Extra);
+            assert_ne!(stream.state.gzindex, 0);
+
+            save(stream).unwrap()
+        });
+
+        with_header(config, &mut header, |stream| {
+            assert_eq!(restore(stream, &checkpoint), ReturnCode::Ok);
+
+            // none of the input was read before the checkpoint
+            assert_eq!(stream.total_in, 0);
+            assert_eq!(compress(stream, &mut output[100..]), ReturnCode::StreamEnd);
+            assert_eq!(stream.total_out as usize, expected_len);
+        });
+        assert!(output[..expected_len] == expected[..expected_len]);
+
+        // a header with a shorter extra field than what was already written
+        let mut short = gz_header {
+            extra_len: 10,
+            ..header
+        };
+        with_header(config, &mut short, |stream| {
+            assert_eq!(restore(stream, &checkpoint), ReturnCode::Ok);
+            assert_eq!(compress(stream, &mut output), ReturnCode::StreamError);
+        });
+
+        // the position in the extra field is only valid while it is being written
+        let mut bytes = checkpoint.clone();
+        let status = MAGIC.len() + 1 + 8 + 1;
+        assert_eq!(bytes[status], Status::Extra as u8);
+        bytes[status] = Status::Name as u8;
+        with_header(config, &mut header, |stream| {
+            assert_eq!(restore(stream, &bytes), ReturnCode::DataError);
+        });
+    }
+}
@@ -1,6 +1,6 @@
 use crate::deflate::{State, HASH_SIZE, STD_MIN_MATCH};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HashCalcVariant {
     Standard,
     /// # Safety
@@ -9,6 +9,9 @@ pub enum HashCalcVariant {
     /// [`Crc32HashCalc`].
     Crc32,
     Roll,
+    /// The rolling hash of stock zlib, see [`ZlibHashCalc`]. Only used when producing
+    /// zlib-identical output.
+    Zlib,
 }
 
 impl HashCalcVariant {
@@ -70,7 +73,7 @@ impl StandardHashCalc {
         let slice = &slice[..Ord::min(slice.len(), count + 3)];
 
         for (i, w) in slice.windows(4).enumerate() {
-            let idx = string as u16 + i as u16;
+            let idx = (string as u16).wrapping_add(i as u16);
 
             let val = u32::from_le_bytes(w.try_into().unwrap());
 
@@ -122,7 +125,7 @@ impl RollHashCalc {
         let slice = &state.window.filled()[string + Self::HASH_CALC_OFFSET..][..count];
 
         for (i, val) in slice.iter().copied().enumerate() {
-            let idx = string as u16 + i as u16;
+            let idx = (string as u16).wrapping_add(i as u16);
 
             state.ins_h = Self::hash_calc(state.ins_h as u32, val as u32) as usize;
             state.ins_h &= Self::HASH_CALC_MASK as usize;
@@ -137,6 +140,45 @@ impl RollHashCalc {
     }
 }
 
+/// The hash function of stock zlib.
+///
+/// It is the same rolling hash as [`RollHashCalc`], but the number of hash bits depends on the
+/// memory level (`mem_level + 7`). Strings are also always inserted, even if that turns the head
+/// of a chain into a self-reference, because that is what zlib's `INSERT_STRING` does.
+pub struct ZlibHashCalc;
+
+impl ZlibHashCalc {
+    const HASH_CALC_OFFSET: usize = STD_MIN_MATCH - 1;
+
+    fn hash_calc(hash_bits: usize, h: u32, val: u32) -> u32 {
+        let hash_shift = hash_bits.div_ceil(STD_MIN_MATCH);
+        (h << hash_shift) ^ val
+    }
+
+    pub fn update_hash(hash_bits: usize, h: u32, val: u32) -> u32 {
+        Self::hash_calc(hash_bits, h, val) & ((1 << hash_bits) - 1)
+    }
+
+    pub fn quick_insert_string(state: &mut State, string: usize) -> u16 {
+        let val = state.window.filled()[string + Self::HASH_CALC_OFFSET] as u32;
+
+        state.ins_h = Self::update_hash(state.hash_bits, state.ins_h as u32, val) as usize;
+        let hm = state.ins_h;
+
+        let head = state.head.as_slice()[hm];
+        state.prev.as_mut_slice()[string & state.w_mask] = head;
+        state.head.as_mut_slice()[hm] = string as u16;
+
+        head
+    }
+
+    pub fn insert_string(state: &mut State, string: usize, count: usize) {
+        for idx in string..string + count {
+            Self::quick_insert_string(state, idx);
+        }
+    }
+}
+
 /// # Safety
 ///
 /// The methods of this struct can only be executed if the system has platform support, otherwise
@@ -145,7 +187,7 @@ impl RollHashCalc {
 pub struct Crc32HashCalc;
 
 impl Crc32HashCalc {
-    fn is_supported() -> bool {
+    pub(crate) fn is_supported() -> bool {
         #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
         return crate::cpu_features::is_enabled_sse42();
 
@@ -221,7 +263,7 @@ impl Crc32HashCalc {
         let slice = &slice[..Ord::min(slice.len(), count + 3)];
 
         for (i, w) in slice.windows(4).enumerate() {
-            let idx = string as u16 + i as u16;
+            let idx = (string as u16).wrapping_add(i as u16);
 
             let val = u32::from_le_bytes(w.try_into().unwrap());
 
@@ -298,6 +340,22 @@ mod tests {
         assert_eq!(RollHashCalc::hash_calc(24117, 101), 771781);
     }
 
+    #[test]
+    fn zlib_hash_calc() {
+        // with the default memory level, zlib's hash is the rolling hash
+        for (h, val) in [(2565, 93), (16637, 10), (8106, 100), (29988, 101)] {
+            assert_eq!(
+                ZlibHashCalc::update_hash(15, h, val),
+                RollHashCalc::update_hash(h, val)
+            );
+        }
+
+        // mem_level 1 gives 8 hash bits and a shift of 3
+        assert_eq!(ZlibHashCalc::update_hash(8, 0xab, 0x12), 0x4a);
+        // mem_level 9 gives 16 hash bits and a shift of 6
+        assert_eq!(ZlibHashCalc::update_hash(16, 0xab, 0x12), 0x2ad2);
+    }
+
     #[test]
     fn standard_hash_calc() {
         assert_eq!(StandardHashCalc::hash_calc(0, 807411760), 65468);
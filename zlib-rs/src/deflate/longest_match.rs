@@ -368,3 +368,77 @@ fn longest_match_help<const SLOW: bool>(
 fn break_matching(state: &State, best_len: usize, match_start: usize) -> (usize, usize) {
     (Ord::min(best_len, state.lookahead), match_start)
 }
+
+/// Stock zlib's `longest_match`.
+///
+/// This is a plain byte-by-byte search, but it visits the chain in the same order and with the
+/// same cutoffs as zlib, and therefore picks the same match among several equally long ones.
+pub fn longest_match_zlib(state: &crate::deflate::State, mut cur_match: u16) -> (usize, usize) {
+    let mut match_start = state.match_start;
+
+    let strstart = state.strstart;
+    let wmask = state.w_mask;
+    let window = state.window.filled();
+
+    let mut chain_length = state.max_chain_length;
+    let mut best_len = state.prev_length;
+    let nice_match = Ord::min(state.nice_match, state.lookahead);
+
+    // Stop when cur_match becomes <= limit. To simplify the code,
+    // we prevent matches with the string of window index 0
+    let limit = strstart.saturating_sub(state.max_dist());
+
+    // Do not waste too much time if we already have a good match
+    if state.prev_length >= state.good_match {
+        chain_length >>= 2;
+    }
+
+    assert!(
+        strstart <= state.window_size.saturating_sub(MIN_LOOKAHEAD),
+        "need lookahead"
+    );
+
+    let scan = &window[strstart..][..STD_MAX_MATCH];
+
+    loop {
+        let m = cur_match as usize;
+
+        // Skip to the next match if the match length cannot increase or if the match length is
+        // less than 2. Note that best_len can be 0 (e.g. after a switch from deflate_slow), in which
+        // case zlib compares the bytes just before the strings. Those exist because cur_match > 0.
+        //
+        // zlib does not compare the third byte: it is implied by the hash (which has at least 8
+        // bits) when the first two bytes match.
+        if window[m + best_len] == window[strstart + best_len]
+            && window[m + best_len - 1] == window[strstart + best_len - 1]
+            && window[m] == scan[0]
+            && window[m + 1] == scan[1]
+        {
+            let candidate = &window[m..][..STD_MAX_MATCH];
+            let len = STD_MIN_MATCH
+                + scan[STD_MIN_MATCH..]
+                    .iter()
+                    .zip(&candidate[STD_MIN_MATCH..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+            if len > best_len {
+                match_start = cur_match as usize;
+                best_len = len;
+                if len >= nice_match {
+                    break;
+                }
+            }
+        }
+
+        cur_match = state.prev.as_slice()[cur_match as usize & wmask];
+
+        // NOTE: like in zlib, a chain length of 0 means unlimited
+        chain_length = chain_length.wrapping_sub(1);
+        if cur_match as usize <= limit || chain_length == 0 {
+            break;
+        }
+    }
+
+    (Ord::min(best_len, state.lookahead), match_start)
+}
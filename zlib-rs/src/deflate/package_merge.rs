@@ -0,0 +1,186 @@
+#![forbid(unsafe_code)]
+
+//! Optimal length-limited huffman code lengths, using the package-merge algorithm of Larmore and
+//! Hirschberg.
+//!
+//! Every symbol is a coin with a face value of `2^-max_length` (its numismatic value is its
+//! frequency). Coins of the same face value are packaged in pairs into coins of the next face
+//! value, and merged with the symbols themselves. The `2n - 2` cheapest items of the largest face
+//! value form an optimal set: the code length of a symbol is the number of lists in which it is
+//! picked, directly or as part of a package.
+
+use crate::deflate::{L_CODES, MAX_BITS};
+
+/// The maximum number of items in a list: the symbols, and at most as many packages.
+const MAX_ITEMS: usize = 2 * L_CODES;
+
+const WORDS: usize = MAX_ITEMS.div_ceil(64);
+
+/// Compute the optimal code lengths, each at most `max_length`, for symbols with these
+/// frequencies. `freqs` must be sorted in increasing order, and have between 2 and
+/// `2^max_length` elements. The code length of `freqs[i]` is written to `lengths[i]`.
+pub(crate) fn code_lengths(freqs: &[u16], max_length: usize, lengths: &mut [u16]) {
+    let n = freqs.len();
+
+    assert!((2..=L_CODES).contains(&n));
+    assert!((1..=MAX_BITS).contains(&max_length) && n <= 1 << max_length);
+    assert!(freqs.windows(2).all(|w| w[0] <= w[1]));
+
+    // for every list, which of its items are packages (the others are symbols)
+    let mut is_package = [[0u64; WORDS]; MAX_BITS + 1];
+
+    // the weights of the items of the current list, and of the list being built
+    let mut list = [0u32; MAX_ITEMS];
+    let mut next = [0u32; MAX_ITEMS];
+
+    // the list of the smallest face value only contains symbols
+    for (weight, &freq) in list.iter_mut().zip(freqs) {
+        *weight = u32::from(freq);
+    }
+    let mut len = n;
+
+    for level in (1..max_length).rev() {
+        let packages = len / 2;
+        let (mut symbol, mut package, mut k) = (0, 0, 0);
+
+        while symbol < n || package < packages {
+            let package_weight = match package < packages {
+                true => list[2 * package] + list[2 * package + 1],
+                false => u32::MAX,
+            };
+
+            // on ties, prefer symbols, which keeps the code shallow
+            if symbol < n && u32::from(freqs[symbol]) <= package_weight {
+                next[k] = u32::from(freqs[symbol]);
+                symbol += 1;
+            } else {
+                next[k] = package_weight;
+                is_package[level][k / 64] |= 1 << (k % 64);
+                package += 1;
+            }
+
+            k += 1;
+        }
+
+        core::mem::swap(&mut list, &mut next);
+        len = k;
+    }
+
+    // pick the 2n - 2 cheapest items, and unpack the packages among them level by level
+    lengths[..n].fill(0);
+
+    let mut picked = 2 * n - 2;
+    for bits in &is_package[1..=max_length] {
+        let packages = (0..picked)
+            .filter(|k| bits[k / 64] & (1 << (k % 64)) != 0)
+            .count();
+
+        // the symbols in a list are sorted, so the cheapest ones are picked
+        for length in &mut lengths[..picked - packages] {
+            *length += 1;
+        }
+
+        picked = 2 * packages;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cost(freqs: &[u16], lengths: &[u16]) -> u64 {
+        freqs
+            .iter()
+            .zip(lengths)
+            .map(|(&f, &l)| u64::from(f) * u64::from(l))
+            .sum()
+    }
+
+    fn is_complete(lengths: &[u16], max_length: usize) -> bool {
+        let kraft: u64 = lengths
+            .iter()
+            .map(|&l| 1 << (max_length - l as usize))
+            .sum();
+        kraft == 1 << max_length
+    }
+
+    /// The cheapest complete code with lengths of at most `max_length`, by trying all of them.
+    fn brute_force(freqs: &[u16], max_length: usize) -> u64 {
+        fn go(freqs: &[u16], lengths: &mut Vec<u16>, max_length: usize, best: &mut u64) {
+            if lengths.len() == freqs.len() {
+                if is_complete(lengths, max_length) {
+                    *best = Ord::min(*best, cost(freqs, lengths));
+                }
+                return;
+            }
+
+            // less frequent symbols never get shorter codes
+            let longest = lengths.last().copied().unwrap_or(max_length as u16);
+            for length in 1..=longest {
+                lengths.push(length);
+                go(freqs, lengths, max_length, best);
+                lengths.pop();
+            }
+        }
+
+        let mut best = u64::MAX;
+        go(freqs, &mut Vec::new(), max_length, &mut best);
+        best
+    }
+
+    #[test]
+    fn optimal_for_small_alphabets() {
+        let mut seed = 0x1234_5678_9abc_def0u64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for _ in 0..200 {
+            let n = 2 + (random() % 7) as usize;
+            let mut freqs: Vec<u16> = (0..n).map(|_| 1 + (random() % 1000) as u16).collect();
+            freqs.sort();
+
+            let min_length = (usize::BITS - (n - 1).leading_zeros()) as usize;
+            for max_length in min_length..=min_length + 3 {
+                let mut lengths = vec![0; n];
+                code_lengths(&freqs, max_length, &mut lengths);
+
+                assert!(lengths
+                    .iter()
+                    .all(|&l| (1..=max_length as u16).contains(&l)));
+                assert!(is_complete(&lengths, max_length));
+                assert_eq!(cost(&freqs, &lengths), brute_force(&freqs, max_length));
+            }
+        }
+    }
+
+    #[test]
+    fn limits_skewed_distributions() {
+        // fibonacci frequencies give the deepest possible huffman tree
+        let mut freqs = vec![1u16, 1];
+        while freqs.len() < 20 {
+            freqs.push(freqs[freqs.len() - 1] + freqs[freqs.len() - 2]);
+        }
+
+        let mut lengths = vec![0; freqs.len()];
+        code_lengths(&freqs, 7, &mut lengths);
+        assert!(is_complete(&lengths, 7));
+        assert_eq!(lengths.iter().max(), Some(&7));
+
+        // when the limit is not binding, this is a huffman code: 15, 15, 14, ..., 2, 1
+        let freqs = &freqs[..16];
+        let mut lengths = vec![0; freqs.len()];
+        code_lengths(freqs, 15, &mut lengths);
+        let huffman: Vec<u16> = (0..16).map(|i: u16| 15 - i.saturating_sub(1)).collect();
+        assert_eq!(lengths, huffman);
+
+        // all symbols of the largest alphabet
+        let freqs: Vec<u16> = (1..=L_CODES as u16).collect();
+        let mut lengths = vec![0; L_CODES];
+        code_lengths(&freqs, MAX_BITS, &mut lengths);
+        assert!(is_complete(&lengths, MAX_BITS));
+    }
+}
@@ -16,6 +16,7 @@ pub struct Pending<'a> {
 impl<'a> Pending<'a> {
     pub fn reset_keep(&mut self) {
         // keep the buffer as it is
+        self.out = 0;
         self.pending = 0;
     }
 
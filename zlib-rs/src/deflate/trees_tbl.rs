@@ -1,7 +1,7 @@
 #![forbid(unsafe_code)]
 
 use crate::deflate::{
-    Value, DIST_CODE_LEN, D_CODES, LENGTH_CODES, L_CODES, STD_MAX_MATCH, STD_MIN_MATCH,
+    Value, DIST_CODE_LEN, D_CODES_64, LENGTH_CODES, L_CODES, STD_MAX_MATCH, STD_MIN_MATCH,
 };
 
 const fn h(freq: u16, code: u16) -> Value {
@@ -92,13 +92,15 @@ pub const STATIC_LTREE_ENCODINGS: [Value; 256] = {
 };
 
 #[rustfmt::skip]
-pub const STATIC_DTREE: [Value; D_CODES] = [
+pub const STATIC_DTREE: [Value; D_CODES_64] = [
     h( 0,5), h(16,5), h( 8,5), h(24,5), h( 4,5),
     h(20,5), h(12,5), h(28,5), h( 2,5), h(18,5),
     h(10,5), h(26,5), h( 6,5), h(22,5), h(14,5),
     h(30,5), h( 1,5), h(17,5), h( 9,5), h(25,5),
     h( 5,5), h(21,5), h(13,5), h(29,5), h( 3,5),
-    h(19,5), h(11,5), h(27,5), h( 7,5), h(23,5)
+    h(19,5), h(11,5), h(27,5), h( 7,5), h(23,5),
+    // Deflate64 only
+    h(15,5), h(31,5)
 ];
 
 #[rustfmt::skip]
@@ -128,7 +130,21 @@ pub const DIST_CODE: [u8; DIST_CODE_LEN] = [
     28, 28, 28, 28, 28, 28, 28, 28, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29,
     29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29,
     29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29,
-    29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29
+    29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29,
+    // Deflate64 only: distances of 32K and more
+    30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    30, 30, 30, 30, 30, 30, 30, 30, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31,
+    31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31,
+    31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31,
+    31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31,
+    31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31,
+    31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31,
+    31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31
 ];
 
 #[rustfmt::skip]
@@ -154,8 +170,9 @@ pub const BASE_LENGTH: [u8; LENGTH_CODES] = [
 ];
 
 #[rustfmt::skip]
-pub const BASE_DIST: [u16; D_CODES] = [
+pub const BASE_DIST: [u16; D_CODES_64] = [
     0,     1,     2,     3,     4,     6,     8,    12,    16,    24,
    32,    48,    64,    96,   128,   192,   256,   384,   512,   768,
- 1024,  1536,  2048,  3072,  4096,  6144,  8192, 12288, 16384, 24576
+ 1024,  1536,  2048,  3072,  4096,  6144,  8192, 12288, 16384, 24576,
+32768, 49152
 ];
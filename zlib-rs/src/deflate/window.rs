@@ -71,6 +71,27 @@ impl<'a> Window<'a> {
         unsafe { core::slice::from_raw_parts_mut(self.buf.as_mut_ptr().cast(), self.filled) }
     }
 
+    /// Returns the initialized portion of the buffer, which can extend beyond [`Self::filled`].
+    #[cfg(feature = "rust-allocator")]
+    pub fn initialized(&self) -> &[u8] {
+        // SAFETY: `self.buf` has been initialized for at least `high_water` elements
+        unsafe { core::slice::from_raw_parts(self.buf.as_ptr().cast(), self.high_water) }
+    }
+
+    /// Replace the contents of the buffer with `initialized`, of which the first `filled` bytes are
+    /// filled, see [`Self::initialized`].
+    #[cfg(feature = "rust-allocator")]
+    pub fn restore(&mut self, initialized: &[u8], filled: usize) {
+        assert!(filled <= initialized.len() && initialized.len() <= self.capacity());
+
+        // SAFETY: [u8] is valid [MaybeUninit<u8>]
+        let initialized = unsafe { &*(initialized as *const [u8] as *const [MaybeUninit<u8>]) };
+        self.buf.as_mut_slice()[..initialized.len()].copy_from_slice(initialized);
+
+        self.filled = filled;
+        self.high_water = initialized.len();
+    }
+
     /// # Safety
     ///
     /// `src` must point to `range.end - range.start` valid (initialized!) bytes
@@ -0,0 +1,359 @@
+//! Build preset dictionaries from sample data.
+//!
+//! Small inputs compress poorly, because there is little history to find matches in. When many
+//! small inputs are similar (e.g. JSON messages with the same keys), a preset dictionary that
+//! contains their common substrings provides that history. The dictionary is passed to
+//! [`crate::deflate::set_dictionary`] and [`crate::inflate::set_dictionary`].
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{adler32, MAX_WBITS};
+
+/// The length of the substrings that are counted. Shorter substrings are too common to be useful,
+/// longer ones miss variations.
+const DMER_LEN: usize = 8;
+
+/// The length of the segments that the dictionary is built from.
+const SEGMENT_LEN: usize = 64;
+
+/// log2 of the number of substring counters. Different substrings can share a counter.
+const HASH_BITS: u32 = 20;
+
+/// A trained preset dictionary.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Dictionary {
+    /// The dictionary. The most useful bytes are at the end, where matches are cheapest.
+    pub bytes: Vec<u8>,
+    /// The Adler-32 checksum of the dictionary. It is the dictionary id (DICTID) in the header of
+    /// zlib streams that use this dictionary.
+    pub id: u32,
+}
+
+impl Dictionary {
+    fn new(bytes: Vec<u8>) -> Self {
+        let id = adler32(1, &bytes);
+        Self { bytes, id }
+    }
+}
+
+/// Build a dictionary of at most `max_size` bytes from the substrings that are repeated most
+/// often across `samples`.
+///
+/// Every sample should be a typical input. A substring scores by the number of samples that
+/// contain it, so a substring that is repeated within only one sample does not contribute.
+/// Because a deflate window is at most 32K, `max_size` is capped at 32K.
+///
+/// This is a simplified version of the COVER algorithm: the samples are divided into epochs, and
+/// from each epoch the segment with the highest score is picked. The substrings of a picked
+/// segment no longer count towards the score of other segments. This repeats until the
+/// dictionary is full, or no segment has a positive score. The segments that were picked first
+/// are placed at the end of the dictionary.
+pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Dictionary {
+    let max_size = Ord::min(max_size, 1 << MAX_WBITS);
+
+    if max_size == 0 || samples.is_empty() {
+        return Dictionary::new(Vec::new());
+    }
+
+    let mut frequencies = count_dmers(samples);
+
+    // the segments, from most to least useful
+    let mut segments: Vec<&[u8]> = Vec::new();
+    let mut size = 0;
+
+    // try to spread the segments over the samples
+    let epochs = Ord::clamp(max_size / SEGMENT_LEN / 4, 1, samples.len());
+    let epoch_len = samples.len() / epochs;
+
+    let mut in_segment = vec![0u8; 1 << HASH_BITS];
+    let mut fruitless_epochs = 0;
+
+    for epoch in (0..epochs).cycle() {
+        if size >= max_size || fruitless_epochs == epochs {
+            break;
+        }
+
+        let start = epoch * epoch_len;
+        let end = if epoch + 1 == epochs {
+            samples.len()
+        } else {
+            start + epoch_len
+        };
+
+        let Some(segment) = best_segment(&samples[start..end], &frequencies, &mut in_segment)
+        else {
+            fruitless_epochs += 1;
+            continue;
+        };
+
+        fruitless_epochs = 0;
+
+        // the substrings of this segment are now covered by the dictionary
+        for dmer in segment.windows(DMER_LEN) {
+            frequencies[hash(dmer)] = 0;
+        }
+
+        size += segment.len();
+        segments.push(segment);
+    }
+
+    let mut bytes = Vec::with_capacity(size);
+    for segment in segments.iter().rev() {
+        bytes.extend_from_slice(segment);
+    }
+
+    // drop the least useful bytes, which are at the start
+    let excess = bytes.len().saturating_sub(max_size);
+    bytes.drain(..excess);
+
+    Dictionary::new(bytes)
+}
+
+fn hash(dmer: &[u8]) -> usize {
+    let dmer: [u8; DMER_LEN] = dmer.try_into().unwrap();
+    let h = u64::from_le_bytes(dmer).wrapping_mul(0x9E3779B185EBCA87);
+    (h >> (64 - HASH_BITS)) as usize
+}
+
+/// For every substring, the number of samples that contain it.
+fn count_dmers<S: AsRef<[u8]>>(samples: &[S]) -> Vec<u32> {
+    let mut frequencies = vec![0u32; 1 << HASH_BITS];
+
+    // the (1-based) index of the last sample that was counted for this substring
+    let mut last_sample = vec![0u32; 1 << HASH_BITS];
+
+    for (i, sample) in samples.iter().enumerate() {
+        let sample_id = i as u32 + 1;
+
+        for dmer in sample.as_ref().windows(DMER_LEN) {
+            let h = hash(dmer);
+            if last_sample[h] != sample_id {
+                last_sample[h] = sample_id;
+                frequencies[h] += 1;
+            }
+        }
+    }
+
+    frequencies
+}
+
+/// The segment of the samples with the highest score. A substring contributes to the score of a
+/// segment only once, and only when it occurs in more than one sample.
+fn best_segment<'a, S: AsRef<[u8]>>(
+    samples: &'a [S],
+    frequencies: &[u32],
+    in_segment: &mut [u8],
+) -> Option<&'a [u8]> {
+    let score = |h: usize| frequencies[h].saturating_sub(1) as u64;
+
+    let mut best: Option<(u64, &[u8])> = None;
+
+    for sample in samples {
+        let sample = sample.as_ref();
+        if sample.len() < DMER_LEN {
+            continue;
+        }
+
+        let dmers: Vec<usize> = sample.windows(DMER_LEN).map(hash).collect();
+        let window = Ord::min(SEGMENT_LEN, sample.len()) - DMER_LEN + 1;
+
+        // the score of the dmers[i - window..i]
+        let mut current = 0;
+
+        for i in 0..dmers.len() {
+            let h = dmers[i];
+            in_segment[h] += 1;
+            if in_segment[h] == 1 {
+                current += score(h);
+            }
+
+            if i >= window {
+                let h = dmers[i - window];
+                in_segment[h] -= 1;
+                if in_segment[h] == 0 {
+                    current -= score(h);
+                }
+            }
+
+            if i + 1 >= window && current > best.map_or(0, |(score, _)| score) {
+                let start = i + 1 - window;
+                best = Some((current, &sample[start..start + window + DMER_LEN - 1]));
+            }
+        }
+
+        // reset the counts for the next sample
+        for &h in &dmers[dmers.len().saturating_sub(window)..] {
+            in_segment[h] = 0;
+        }
+    }
+
+    best.map(|(_, segment)| segment)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        c_api::z_stream,
+        deflate::{self, DeflateConfig, DeflateStream},
+        inflate::{self, InflateConfig, InflateStream},
+        DeflateFlush, InflateFlush, ReturnCode,
+    };
+
+    /// Generate JSON messages that share their structure, but not their values.
+    fn messages(count: usize, mut seed: u32) -> Vec<Vec<u8>> {
+        let mut random = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            seed >> 8
+        };
+
+        let levels = ["debug", "info", "warning", "error"];
+        let services = ["payments", "search", "checkout", "accounts", "inventory"];
+
+        (0..count)
+            .map(|_| {
+                format!(
+                    r#"{{"timestamp":"2024-03-{:02}T{:02}:{:02}:{:02}.{:03}Z","level":"{}","service":"{}","request_id":"{:08x}-{:04x}","user":{{"id":{},"country":"NL","premium":{}}},"latency_ms":{},"message":"request completed successfully"}}"#,
+                    random() % 28 + 1,
+                    random() % 24,
+                    random() % 60,
+                    random() % 60,
+                    random() % 1000,
+                    levels[random() as usize % levels.len()],
+                    services[random() as usize % services.len()],
+                    random(),
+                    random() % 0x10000,
+                    random() % 100_000,
+                    random() % 2 == 0,
+                    random() % 500,
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    fn compress(input: &[u8], dictionary: &[u8]) -> Vec<u8> {
+        let mut stream = z_stream::default();
+        assert_eq!(
+            deflate::init(&mut stream, DeflateConfig::new(9)),
+            ReturnCode::Ok
+        );
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        if !dictionary.is_empty() {
+            assert_eq!(deflate::set_dictionary(stream, dictionary), ReturnCode::Ok);
+        }
+
+        let mut output = vec![0; 1024];
+        stream.next_in = input.as_ptr() as *mut u8;
+        stream.avail_in = input.len() as _;
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+
+        let err = deflate::deflate(stream, DeflateFlush::Finish);
+        assert_eq!(err, ReturnCode::StreamEnd);
+
+        output.truncate(stream.total_out as usize);
+        assert!(deflate::end(stream).is_ok());
+
+        output
+    }
+
+    fn decompress(input: &[u8], dictionary: &Dictionary, len: usize) -> Vec<u8> {
+        let mut stream = z_stream::default();
+        assert_eq!(
+            inflate::init(&mut stream, InflateConfig::default()),
+            ReturnCode::Ok
+        );
+        let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut output = vec![0; len];
+        stream.next_in = input.as_ptr() as *mut u8;
+        stream.avail_in = input.len() as _;
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+
+        let err = unsafe { inflate::inflate(stream, InflateFlush::Finish) };
+        assert_eq!(err, ReturnCode::NeedDict);
+        assert_eq!(stream.adler as u32, dictionary.id);
+
+        let err = inflate::set_dictionary(stream, &dictionary.bytes);
+        assert_eq!(err, ReturnCode::Ok);
+
+        let err = unsafe { inflate::inflate(stream, InflateFlush::Finish) };
+        assert_eq!(err, ReturnCode::StreamEnd);
+
+        output.truncate(stream.total_out as usize);
+        inflate::end(stream);
+
+        output
+    }
+
+    #[test]
+    fn trained_dictionary_compresses_similar_messages() {
+        let dictionary = train(&messages(2000, 1), 4096);
+        assert!(dictionary.bytes.len() <= 4096);
+        assert_eq!(dictionary.id, adler32(1, &dictionary.bytes));
+
+        let mut without = 0;
+        let mut with = 0;
+
+        for message in messages(100, 2) {
+            without += compress(&message, &[]).len();
+
+            let compressed = compress(&message, &dictionary.bytes);
+            with += compressed.len();
+
+            assert_eq!(decompress(&compressed, &dictionary, message.len()), message);
+        }
+
+        assert!(2 * with < without, "{with} vs {without}");
+    }
+
+    #[test]
+    fn most_useful_bytes_are_at_the_end() {
+        let mut seed = 0u64;
+
+        // every sample contains the common part, some contain the less common part. They are
+        // separated by random bytes, so that one segment cannot contain both.
+        let samples: Vec<Vec<u8>> = (0..100)
+            .map(|i| {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+
+                let mut sample = b"[this part is in every single sample]".to_vec();
+                sample
+                    .extend_from_slice(format!("{seed:016x}{:016x}", seed.swap_bytes()).as_bytes());
+                if i % 4 == 0 {
+                    sample.extend_from_slice(b"{this is the less common part}");
+                }
+                sample
+            })
+            .collect();
+
+        let dictionary = train(&samples, 1024);
+        let bytes = &dictionary.bytes[..];
+
+        let rfind = |needle: &[u8]| bytes.windows(needle.len()).rposition(|w| w == needle);
+        let common = rfind(b"[this part is in every single sample]").unwrap();
+        let less_common = rfind(b"{this is the less common part}").unwrap();
+        assert!(less_common < common);
+    }
+
+    #[test]
+    fn degenerate_input() {
+        let empty: [&[u8]; 0] = [];
+        assert_eq!(train(&empty, 1024).bytes, b"");
+        assert_eq!(train(&[b"short"], 1024).bytes, b"");
+        assert_eq!(train(&[b"some sample data"], 0).bytes, b"");
+
+        // a substring that occurs in only one sample is not useful
+        assert_eq!(train(&[b"aaaaaaaaaaaaaaaaaaaaaaaaa"], 1024).bytes, b"");
+
+        let dictionary = train(&[b"ab"; 2], 1024);
+        assert_eq!(dictionary.id, 1);
+    }
+}
@@ -7,8 +7,16 @@ use core::mem::MaybeUninit;
 use core::ops::ControlFlow;
 
 mod bitreader;
+#[cfg(feature = "rust-allocator")]
+pub mod blocks;
+#[cfg(feature = "rust-allocator")]
+pub mod checkpoint;
+#[cfg(feature = "rust-allocator")]
+pub mod index;
 mod inffixed_tbl;
 mod inftrees;
+#[cfg(feature = "rust-allocator")]
+pub mod recover;
 mod window;
 mod writer;
 
@@ -29,8 +37,6 @@ use self::{
     window::Window,
 };
 
-const INFLATE_STRICT: bool = false;
-
 // SAFETY: This struct must have the same layout as [`z_stream`], so that casts and transmutations
 // between the two can work without UB.
 #[repr(C)]
@@ -125,11 +131,34 @@ impl<'a> InflateStream<'a> {
         // safety: a valid &mut InflateStream is also a valid &mut z_stream
         unsafe { &mut *(self as *mut _ as *mut z_stream) }
     }
+
+    /// Where and why decoding stopped, after inflate returned [`ReturnCode::DataError`] for
+    /// invalid data. This is `None` while the data is valid, and when a limit of
+    /// [`InflateConfig`] was exceeded (see [`limit_exceeded`]).
+    pub fn corruption(&self) -> Option<Corruption> {
+        self.state.corruption
+    }
 }
 
 const MAX_BITS: u8 = 15; // maximum number of bits in a code
 const MAX_DIST_EXTRA_BITS: u8 = 13; // maximum number of extra distance bits
-                                    //
+
+/// Set in the `op` of Deflate64 length code 285, which has 16 extra bits. That does not fit in the
+/// 4 bits of `op` that normally hold the number of extra bits.
+const OP_EXTRA_16: u8 = 0b1000_0000;
+
+/// The number of extra bits of a length or distance code.
+#[inline(always)]
+const fn extra_bits(op: u8) -> usize {
+    (op & MAX_BITS) as usize + ((op & OP_EXTRA_16) >> 3) as usize
+}
+
+/// The longest match of Deflate64: length code 285 with all 16 extra bits set.
+const MAX_MATCH_64: usize = 3 + 0xFFFF;
+
+/// The number of symbols of the code length code.
+const CODE_LENS: usize = 19;
+
 pub fn uncompress_slice<'a>(
     output: &'a mut [u8],
     input: &[u8],
@@ -144,6 +173,10 @@ pub fn uncompress_slice<'a>(
 }
 
 /// Inflates `source` into `dest`, and writes the final inflated size into `dest_len`.
+///
+/// When the input and output fit in a single call to [`inflate`], raw streams (negative
+/// `window_bits`) are decoded without a window. A zlib or gzip stream that ends in a single call
+/// does not use its window anyway, so for those the single-shot decode makes no difference.
 pub fn uncompress<'a>(
     output: &'a mut [MaybeUninit<u8>],
     input: &[u8],
@@ -202,6 +235,13 @@ pub fn uncompress<'a>(
             len -= stream.avail_in as u64;
         }
 
+        // When all of the input and output are available, the output holds everything that
+        // back-references can reach, so the window is not maintained. It is only needed when the
+        // output is so large that it is decoded in parts. Only raw streams benefit: a zlib or gzip
+        // stream that ends in this call does not update its window anyway.
+        let single_shot = left == 0 && len == 0;
+        stream.state.flags.update(Flags::SINGLE_SHOT, single_shot);
+
         let err = unsafe { inflate(stream, InflateFlush::NoFlush) };
 
         if err != ReturnCode::Ok {
@@ -275,6 +315,7 @@ pub enum Mode {
 enum Codes {
     #[default]
     Fixed,
+    Fixed64,
     Codes,
     Len,
     Dist,
@@ -305,6 +346,16 @@ impl Flags {
     /// if false, allow invalid distance too far
     const SANE: Self = Self(0b0000_0100);
 
+    /// set if the stream is Deflate64, see [`InflateConfig::deflate64`]
+    const DEFLATE64: Self = Self(0b0000_1000);
+
+    /// reject distances beyond the window size of the header, see [`InflateConfig::strict`]
+    const STRICT: Self = Self(0b0001_0000);
+
+    /// set for the last call of a single-shot decode, see [`uncompress`]: there is no later call
+    /// that could need the window
+    const SINGLE_SHOT: Self = Self(0b0010_0000);
+
     pub(crate) const fn contains(self, other: Self) -> bool {
         debug_assert!(other.0.count_ones() == 1);
 
@@ -343,6 +394,10 @@ pub(crate) struct State<'a> {
     // allocated window if needed (capacity == 0 if unused)
     window: Window<'a>,
 
+    /// A prepared dictionary that logically precedes the window, see [`set_prepared_dictionary`].
+    /// Empty when no dictionary is attached.
+    dictionary: &'a [u8],
+
     //
     /// number of code length code lengths
     ncode: usize,
@@ -388,6 +443,20 @@ pub(crate) struct State<'a> {
 
     error_message: Option<&'static str>,
 
+    /// The limits on the output and the gzip header, see [`InflateConfig`].
+    limits: Limits,
+    /// The limit that stopped decoding, see [`limit_exceeded`].
+    limit_exceeded: Option<Limit>,
+    /// The number of bytes of the gzip header name or comment that have been read.
+    header_field: usize,
+
+    /// The number of blocks that were started, see [`Corruption::block`].
+    blocks: usize,
+    /// The value that caused the error, set just before `Mode::Bad` is entered.
+    invalid_value: Option<InvalidValue>,
+    /// Where decoding stopped at invalid data, see [`InflateStream::corruption`].
+    corruption: Option<Corruption>,
+
     /// place to store gzip header if needed
     head: Option<&'a mut gz_header>,
     dmax: usize,
@@ -404,6 +473,9 @@ pub(crate) struct State<'a> {
 
     /// temporary storage space for code lengths
     lens: [u16; 320],
+    /// the lengths of the code length code of the last dynamic block, by symbol (in `lens` they
+    /// are overwritten by the lengths that they decode)
+    code_length_lengths: [u8; CODE_LENS],
     /// work area for code table building
     work: [u16; 288],
 }
@@ -439,9 +511,11 @@ impl<'a> State<'a> {
             total: 0,
 
             window: Window::empty(),
+            dictionary: &[],
             head: None,
 
             lens: [0u16; 320],
+            code_length_lengths: [0; CODE_LENS],
             work: [0u16; 288],
 
             ncode: 0,
@@ -452,6 +526,14 @@ impl<'a> State<'a> {
 
             error_message: None,
 
+            limits: Limits::default(),
+            limit_exceeded: None,
+            header_field: 0,
+
+            blocks: 0,
+            invalid_value: None,
+            corruption: None,
+
             checksum: 0,
             crc_fold: Crc32Fold::new(),
 
@@ -464,9 +546,18 @@ impl<'a> State<'a> {
         }
     }
 
+    /// The part of the prepared dictionary that can still be referenced: together with the window
+    /// it is at most the maximum distance.
+    fn dictionary(&self) -> &'a [u8] {
+        let reachable = (1usize << self.wbits).saturating_sub(self.window.have());
+        let dictionary = self.dictionary;
+        &dictionary[dictionary.len().saturating_sub(reachable)..]
+    }
+
     fn len_table_ref(&self) -> &[Code] {
         match self.len_table.codes {
             Codes::Fixed => &self::inffixed_tbl::LENFIX,
+            Codes::Fixed64 => &self::inffixed_tbl::LENFIX64,
             Codes::Codes => &self.codes_codes,
             Codes::Len => &self.len_codes,
             Codes::Dist => &self.dist_codes,
@@ -476,6 +567,7 @@ impl<'a> State<'a> {
     fn dist_table_ref(&self) -> &[Code] {
         match self.dist_table.codes {
             Codes::Fixed => &self::inffixed_tbl::DISTFIX,
+            Codes::Fixed64 => &self::inffixed_tbl::DISTFIX64,
             Codes::Codes => &self.codes_codes,
             Codes::Len => &self.len_codes,
             Codes::Dist => &self.dist_codes,
@@ -517,7 +609,27 @@ const fn zswap32(q: u32) -> u32 {
 const INFLATE_FAST_MIN_HAVE: usize = 15;
 const INFLATE_FAST_MIN_LEFT: usize = 260;
 
+/// Like `INFLATE_FAST_MIN_LEFT`, for the longer matches of Deflate64.
+const INFLATE64_FAST_MIN_LEFT: usize = INFLATE_FAST_MIN_LEFT - 258 + MAX_MATCH_64;
+
 impl State<'_> {
+    /// The free output space that `inflate_fast_help` needs.
+    #[inline(always)]
+    fn fast_min_left(&self) -> usize {
+        match self.flags.contains(Flags::DEFLATE64) {
+            true => INFLATE64_FAST_MIN_LEFT,
+            false => INFLATE_FAST_MIN_LEFT,
+        }
+    }
+
+    #[inline(always)]
+    fn inflate_fast(&mut self) {
+        match self.flags.contains(Flags::DEFLATE64) {
+            true => inflate_fast_help::<true>(self, 0),
+            false => inflate_fast_help::<false>(self, 0),
+        }
+    }
+
     // This logic is split into its own function for two reasons
     //
     // - We get to load state to the stack; doing this in all cases is expensive, but doing it just
@@ -531,8 +643,8 @@ impl State<'_> {
         let avail_in = self.bit_reader.bytes_remaining();
         let avail_out = self.writer.remaining();
 
-        if avail_in >= INFLATE_FAST_MIN_HAVE && avail_out >= INFLATE_FAST_MIN_LEFT {
-            inflate_fast_help(self, 0);
+        if avail_in >= INFLATE_FAST_MIN_HAVE && avail_out >= self.fast_min_left() {
+            self.inflate_fast();
             match self.mode {
                 Mode::Len => {}
                 _ => return ControlFlow::Continue(()),
@@ -563,6 +675,7 @@ impl State<'_> {
 
         let len_table = match self.len_table.codes {
             Codes::Fixed => &self::inffixed_tbl::LENFIX[..],
+            Codes::Fixed64 => &self::inffixed_tbl::LENFIX64[..],
             Codes::Codes => &self.codes_codes,
             Codes::Len => &self.len_codes,
             Codes::Dist => &self.dist_codes,
@@ -570,6 +683,7 @@ impl State<'_> {
 
         let dist_table = match self.dist_table.codes {
             Codes::Fixed => &self::inffixed_tbl::DISTFIX[..],
+            Codes::Fixed64 => &self::inffixed_tbl::DISTFIX64[..],
             Codes::Codes => &self.codes_codes,
             Codes::Len => &self.len_codes,
             Codes::Dist => &self.dist_codes,
@@ -585,9 +699,9 @@ impl State<'_> {
                     // space available. This means for many SIMD operations we don't need to process a
                     // remainder; we just copy blindly, and a later operation will overwrite the extra copied
                     // bytes
-                    if avail_in >= INFLATE_FAST_MIN_HAVE && avail_out >= INFLATE_FAST_MIN_LEFT {
+                    if avail_in >= INFLATE_FAST_MIN_HAVE && avail_out >= self.fast_min_left() {
                         restore!();
-                        inflate_fast_help(self, 0);
+                        self.inflate_fast();
                         return ControlFlow::Continue(());
                     }
 
@@ -609,8 +723,12 @@ impl State<'_> {
                         };
                     }
 
+                    let hold = bit_reader.hold();
+                    let mut link_bits = 0;
+
                     if here.op != 0 && here.op & 0xf0 == 0 {
                         let last = here;
+                        link_bits = last.bits;
                         loop {
                             let bits = bit_reader.bits((last.bits + last.op) as usize) as u16;
                             here = len_table[(last.val + (bits >> last.bits)) as usize];
@@ -650,6 +768,7 @@ impl State<'_> {
                         {
                             restore!();
                             let this = &mut *self;
+                            this.invalid_value = Some(invalid_code(hold, link_bits + here.bits));
                             let msg: &'static str = "invalid literal/length code\0";
                             #[cfg(all(feature = "std", test))]
                             dbg!(msg);
@@ -658,7 +777,7 @@ impl State<'_> {
                         }
                     } else {
                         // length code
-                        self.extra = (here.op & MAX_BITS) as usize;
+                        self.extra = extra_bits(here.op);
                         mode = Mode::LenExt;
                         continue 'top;
                     }
@@ -721,8 +840,12 @@ impl State<'_> {
                         };
                     }
 
+                    let hold = bit_reader.hold();
+                    let mut link_bits = 0;
+
                     if here.op & 0xf0 == 0 {
                         let last = here;
+                        link_bits = last.bits;
 
                         loop {
                             let bits = bit_reader.bits((last.bits + last.op) as usize);
@@ -747,6 +870,7 @@ impl State<'_> {
                     if here.op & 64 != 0 {
                         restore!();
                         self.mode = Mode::Bad;
+                        self.invalid_value = Some(invalid_code(hold, link_bits + here.bits));
                         return ControlFlow::Break(self.bad("invalid distance code\0"));
                     }
 
@@ -774,9 +898,10 @@ impl State<'_> {
                         self.back += extra;
                     }
 
-                    if INFLATE_STRICT && self.offset > self.dmax {
+                    if self.flags.contains(Flags::STRICT) && self.offset > self.dmax {
                         restore!();
                         self.mode = Mode::Bad;
+                        self.invalid_value = Some(InvalidValue::Distance(self.offset as u32));
                         return ControlFlow::Break(
                             self.bad("invalid distance code too far back\0"),
                         );
@@ -809,34 +934,49 @@ impl State<'_> {
                         let mut copy = self.offset - copy;
 
                         if copy > self.window.have() {
-                            if self.flags.contains(Flags::SANE) {
-                                restore!();
-                                self.mode = Mode::Bad;
-                                return ControlFlow::Break(
-                                    self.bad("invalid distance too far back\0"),
-                                );
+                            // copy from the prepared dictionary, which precedes the window
+                            let dictionary = self.dictionary();
+                            let back = copy - self.window.have();
+
+                            if back > dictionary.len() {
+                                if self.flags.contains(Flags::SANE) {
+                                    restore!();
+                                    self.mode = Mode::Bad;
+                                    let distance = self.offset as u32;
+                                    self.invalid_value = Some(InvalidValue::Distance(distance));
+                                    return ControlFlow::Break(
+                                        self.bad("invalid distance too far back\0"),
+                                    );
+                                }
+
+                                // TODO INFLATE_ALLOW_INVALID_DISTANCE_TOOFAR_ARRR
+                                panic!("INFLATE_ALLOW_INVALID_DISTANCE_TOOFAR_ARRR")
                             }
 
-                            // TODO INFLATE_ALLOW_INVALID_DISTANCE_TOOFAR_ARRR
-                            panic!("INFLATE_ALLOW_INVALID_DISTANCE_TOOFAR_ARRR")
-                        }
+                            let from = dictionary.len() - back;
+                            let copy = Ord::min(Ord::min(back, self.length), left);
 
-                        let wnext = self.window.next();
-                        let wsize = self.window.size();
+                            writer.extend(&dictionary[from..][..copy]);
 
-                        let from = if copy > wnext {
-                            copy -= wnext;
-                            wsize - copy
+                            copy
                         } else {
-                            wnext - copy
-                        };
+                            let wnext = self.window.next();
+                            let wsize = self.window.size();
 
-                        copy = Ord::min(copy, self.length);
-                        copy = Ord::min(copy, left);
+                            let from = if copy > wnext {
+                                copy -= wnext;
+                                wsize - copy
+                            } else {
+                                wnext - copy
+                            };
 
-                        writer.extend_from_window(&self.window, from..from + copy);
+                            copy = Ord::min(copy, self.length);
+                            copy = Ord::min(copy, left);
 
-                        copy
+                            writer.extend_from_window(&self.window, from..from + copy);
+
+                            copy
+                        }
                     } else {
                         let copy = Ord::min(self.length, left);
                         writer.copy_match(self.offset, copy);
@@ -1005,6 +1145,10 @@ impl State<'_> {
 
                         // self.length (and head.extra_len) represent the length of the extra field
                         self.length = self.bit_reader.hold() as usize;
+                        if self.limits.header_field_exceeded(self.length, false) {
+                            break 'label self.exceeded(Limit::HeaderField);
+                        }
+
                         if let Some(head) = self.head.as_mut() {
                             head.extra_len = self.length as u32;
                         }
@@ -1081,6 +1225,7 @@ impl State<'_> {
                     }
 
                     self.length = 0;
+                    self.header_field = 0;
                     self.mode = Mode::Name;
 
                     continue 'label;
@@ -1102,6 +1247,16 @@ impl State<'_> {
                             None => slice,
                         };
 
+                        // the limit applies whether or not the name is stored
+                        self.header_field += name_slice.len();
+                        let terminated = name_slice.last() == Some(&0);
+                        if self
+                            .limits
+                            .header_field_exceeded(self.header_field, terminated)
+                        {
+                            break 'label self.exceeded(Limit::HeaderField);
+                        }
+
                         // if the header has space, store as much as possible in there
                         if let Some(head) = self.head.as_mut() {
                             if !head.name.is_null() {
@@ -1139,6 +1294,7 @@ impl State<'_> {
                     }
 
                     self.length = 0;
+                    self.header_field = 0;
                     self.mode = Mode::Comment;
 
                     continue 'label;
@@ -1160,6 +1316,16 @@ impl State<'_> {
                             None => slice,
                         };
 
+                        // the limit applies whether or not the comment is stored
+                        self.header_field += comment_slice.len();
+                        let terminated = comment_slice.last() == Some(&0);
+                        if self
+                            .limits
+                            .header_field_exceeded(self.header_field, terminated)
+                        {
+                            break 'label self.exceeded(Limit::HeaderField);
+                        }
+
                         // if the header has space, store as much as possible in there
                         if let Some(head) = self.head.as_mut() {
                             if !head.comment.is_null() {
@@ -1208,6 +1374,10 @@ impl State<'_> {
                             && self.bit_reader.hold() as u32 != (self.checksum & 0xffff)
                         {
                             self.mode = Mode::Bad;
+                            self.invalid_value = Some(InvalidValue::Checksum {
+                                expected: self.bit_reader.hold() as u32,
+                                actual: self.checksum & 0xffff,
+                            });
                             break 'label self.bad("header crc mismatch\0");
                         }
 
@@ -1250,6 +1420,8 @@ impl State<'_> {
                     }
 
                     need_bits!(self, 3);
+                    self.blocks += 1;
+
                     // self.last = self.bit_reader.bits(1) != 0;
                     self.flags
                         .update(Flags::IS_LAST_BLOCK, self.bit_reader.bits(1) != 0);
@@ -1268,15 +1440,13 @@ impl State<'_> {
                         0b01 => {
                             // eprintln!("inflate:     fixed codes block (last = {last})");
 
-                            self.len_table = Table {
-                                codes: Codes::Fixed,
-                                bits: 9,
+                            let codes = match self.flags.contains(Flags::DEFLATE64) {
+                                true => Codes::Fixed64,
+                                false => Codes::Fixed,
                             };
 
-                            self.dist_table = Table {
-                                codes: Codes::Fixed,
-                                bits: 5,
-                            };
+                            self.len_table = Table { codes, bits: 9 };
+                            self.dist_table = Table { codes, bits: 5 };
 
                             self.mode = Mode::Len_;
 
@@ -1330,11 +1500,11 @@ impl State<'_> {
 
                     self.bit_reader.init_bits();
 
+                    self.mode = Mode::CopyBlock;
+
                     if let InflateFlush::Trees = self.flush {
                         break 'label self.inflate_leave(ReturnCode::Ok);
                     } else {
-                        self.mode = Mode::CopyBlock;
-
                         continue 'label;
                     }
                 }
@@ -1388,6 +1558,10 @@ impl State<'_> {
 
                         if self.wrap & 4 != 0 && given_checksum != self.checksum {
                             self.mode = Mode::Bad;
+                            self.invalid_value = Some(InvalidValue::Checksum {
+                                expected: given_checksum,
+                                actual: self.checksum,
+                            });
                             break 'label self.bad("incorrect data check\0");
                         }
 
@@ -1454,8 +1628,12 @@ impl State<'_> {
                         pull_byte!(self);
                     }
 
+                    let hold = self.bit_reader.hold();
+                    let mut link_bits = 0;
+
                     if here.op & 0xf0 == 0 {
                         let last = here;
+                        link_bits = last.bits;
 
                         loop {
                             let bits = self.bit_reader.bits((last.bits + last.op) as usize);
@@ -1477,6 +1655,7 @@ impl State<'_> {
 
                     if here.op & 64 != 0 {
                         self.mode = Mode::Bad;
+                        self.invalid_value = Some(invalid_code(hold, link_bits + here.bits));
                         break 'label self.bad("invalid distance code\0");
                     }
 
@@ -1498,8 +1677,9 @@ impl State<'_> {
                         self.back += extra;
                     }
 
-                    if INFLATE_STRICT && self.offset > self.dmax {
+                    if self.flags.contains(Flags::STRICT) && self.offset > self.dmax {
                         self.mode = Mode::Bad;
+                        self.invalid_value = Some(InvalidValue::Distance(self.offset as u32));
                         break 'label self.bad("invalid distance code too far back\0");
                     }
 
@@ -1531,32 +1711,47 @@ impl State<'_> {
                             let mut copy = self.offset - copy;
 
                             if copy > self.window.have() {
-                                if self.flags.contains(Flags::SANE) {
-                                    self.mode = Mode::Bad;
-                                    break 'label self.bad("invalid distance too far back\0");
+                                // copy from the prepared dictionary, which precedes the window
+                                let dictionary = self.dictionary();
+                                let back = copy - self.window.have();
+
+                                if back > dictionary.len() {
+                                    if self.flags.contains(Flags::SANE) {
+                                        self.mode = Mode::Bad;
+                                        let distance = self.offset as u32;
+                                        self.invalid_value = Some(InvalidValue::Distance(distance));
+                                        break 'label self.bad("invalid distance too far back\0");
+                                    }
+
+                                    // TODO INFLATE_ALLOW_INVALID_DISTANCE_TOOFAR_ARRR
+                                    panic!("INFLATE_ALLOW_INVALID_DISTANCE_TOOFAR_ARRR")
                                 }
 
-                                // TODO INFLATE_ALLOW_INVALID_DISTANCE_TOOFAR_ARRR
-                                panic!("INFLATE_ALLOW_INVALID_DISTANCE_TOOFAR_ARRR")
-                            }
+                                let from = dictionary.len() - back;
+                                let copy = Ord::min(Ord::min(back, self.length), left);
 
-                            let wnext = self.window.next();
-                            let wsize = self.window.size();
+                                self.writer.extend(&dictionary[from..][..copy]);
 
-                            let from = if copy > wnext {
-                                copy -= wnext;
-                                wsize - copy
+                                copy
                             } else {
-                                wnext - copy
-                            };
+                                let wnext = self.window.next();
+                                let wsize = self.window.size();
+
+                                let from = if copy > wnext {
+                                    copy -= wnext;
+                                    wsize - copy
+                                } else {
+                                    wnext - copy
+                                };
 
-                            copy = Ord::min(copy, self.length);
-                            copy = Ord::min(copy, left);
+                                copy = Ord::min(copy, self.length);
+                                copy = Ord::min(copy, left);
 
-                            self.writer
-                                .extend_from_window(&self.window, from..from + copy);
+                                self.writer
+                                    .extend_from_window(&self.window, from..from + copy);
 
-                            copy
+                                copy
+                            }
                         } else {
                             let copy = Ord::min(self.length, left);
                             self.writer.copy_match(self.offset, copy);
@@ -1586,8 +1781,13 @@ impl State<'_> {
                     self.ncode = self.bit_reader.bits(4) as usize + 4;
                     self.bit_reader.drop_bits(4);
 
+                    let max_ndist = match self.flags.contains(Flags::DEFLATE64) {
+                        true => 32,
+                        false => 30,
+                    };
+
                     // TODO pkzit_bug_workaround
-                    if self.nlen > 286 || self.ndist > 30 {
+                    if self.nlen > 286 || self.ndist > max_ndist {
                         self.mode = Mode::Bad;
                         break 'label self.bad("too many length or distance symbols\0");
                     }
@@ -1599,7 +1799,7 @@ impl State<'_> {
                 }
                 Mode::LenLens => {
                     // permutation of code lengths ;
-                    const ORDER: [u16; 19] = [
+                    const ORDER: [u16; CODE_LENS] = [
                         16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
                     ];
 
@@ -1610,17 +1810,21 @@ impl State<'_> {
                         self.bit_reader.drop_bits(3);
                     }
 
-                    while self.have < 19 {
+                    while self.have < CODE_LENS {
                         self.lens[ORDER[self.have] as usize] = 0;
                         self.have += 1;
                     }
 
+                    for (length, &len) in self.code_length_lengths.iter_mut().zip(&self.lens) {
+                        *length = len as u8;
+                    }
+
                     self.len_table.bits = 7;
 
                     let InflateTable::Success(root) = inflate_table(
                         CodeType::Codes,
                         &self.lens,
-                        19,
+                        CODE_LENS,
                         &mut self.codes_codes,
                         self.len_table.bits,
                         &mut self.work,
@@ -1724,10 +1928,15 @@ impl State<'_> {
 
                     // build code tables
 
+                    let deflate64 = self.flags.contains(Flags::DEFLATE64);
+
                     self.len_table.bits = 10;
 
                     let InflateTable::Success(root) = inflate_table(
-                        CodeType::Lens,
+                        match deflate64 {
+                            true => CodeType::Lens64,
+                            false => CodeType::Lens,
+                        },
                         &self.lens,
                         self.nlen,
                         &mut self.len_codes,
@@ -1744,7 +1953,10 @@ impl State<'_> {
                     self.dist_table.bits = 9;
 
                     let InflateTable::Success(root) = inflate_table(
-                        CodeType::Dists,
+                        match deflate64 {
+                            true => CodeType::Dists64,
+                            false => CodeType::Dists,
+                        },
                         &self.lens[self.nlen..],
                         self.ndist,
                         &mut self.dist_codes,
@@ -1789,10 +2001,13 @@ impl State<'_> {
                     continue 'label;
                 }
                 Mode::Bad => {
-                    let msg = "repeated call with bad state\0";
-                    #[cfg(all(feature = "std", test))]
-                    dbg!(msg);
-                    self.error_message = Some(msg);
+                    // keep the message of the error, e.g. from `inflate_fast`
+                    if self.error_message.is_none() {
+                        let msg = "repeated call with bad state\0";
+                        #[cfg(all(feature = "std", test))]
+                        dbg!(msg);
+                        self.error_message = Some(msg);
+                    }
 
                     break 'label ReturnCode::DataError;
                 }
@@ -1808,6 +2023,10 @@ impl State<'_> {
                         need_bits!(self, 32);
                         if (self.wrap & 4) != 0 && self.bit_reader.hold() != self.total as u64 {
                             self.mode = Mode::Bad;
+                            self.invalid_value = Some(InvalidValue::Length {
+                                expected: self.bit_reader.hold() as u32,
+                                actual: self.total as u32,
+                            });
                             break 'label self.bad("incorrect length check\0");
                         }
 
@@ -1821,6 +2040,12 @@ impl State<'_> {
         }
     }
 
+    fn exceeded(&mut self, limit: Limit) -> ReturnCode {
+        self.limit_exceeded = Some(limit);
+        self.mode = Mode::Bad;
+        self.bad(limit.error_message())
+    }
+
     fn bad(&mut self, msg: &'static str) -> ReturnCode {
         #[cfg(all(feature = "std", test))]
         dbg!(msg);
@@ -1856,10 +2081,16 @@ impl State<'_> {
     }
 }
 
-fn inflate_fast_help(state: &mut State, _start: usize) {
+/// Decode symbols while there is plenty of input and output space. `DEFLATE64` selects the longer
+/// lengths and distances of Deflate64; it is a const generic so that the regular path does not
+/// pay for them.
+fn inflate_fast_help<const DEFLATE64: bool>(state: &mut State, _start: usize) {
     let mut bit_reader = BitReader::new(&[]);
     core::mem::swap(&mut bit_reader, &mut state.bit_reader);
 
+    // the bits on entry, from which an invalid code is rebuilt, see `dropped_code`
+    let start = bit_reader;
+
     let mut writer = Writer::new(&mut []);
     core::mem::swap(&mut writer, &mut state.writer);
 
@@ -1875,6 +2106,11 @@ fn inflate_fast_help(state: &mut State, _start: usize) {
 
     let window_size = state.window.size();
 
+    let (dist_extra_bits, min_left) = match DEFLATE64 {
+        true => (MAX_DIST_EXTRA_BITS + 1, INFLATE64_FAST_MIN_LEFT),
+        false => (MAX_DIST_EXTRA_BITS, INFLATE_FAST_MIN_LEFT),
+    };
+
     let mut bad = None;
 
     if bit_reader.bits_in_buffer() < 10 {
@@ -1916,15 +2152,18 @@ fn inflate_fast_help(state: &mut State, _start: usize) {
             if op == 0 {
                 writer.push(here.val as u8);
             } else if op & 16 != 0 {
-                let op = op & MAX_BITS;
-                let mut len = here.val + bit_reader.bits(op as usize) as u16;
-                bit_reader.drop_bits(op);
+                let op = match DEFLATE64 {
+                    true => extra_bits(op),
+                    false => (op & MAX_BITS) as usize,
+                };
+                let mut len = here.val as usize + bit_reader.bits(op) as usize;
+                bit_reader.drop_bits(op as u8);
 
                 here = dcode[(bit_reader.hold() & dmask) as usize];
 
                 // we have two fast-path loads: 10+10 + 15+5 = 40,
                 // but we may need to refill here in the worst case
-                if bit_reader.bits_in_buffer() < MAX_BITS + MAX_DIST_EXTRA_BITS {
+                if bit_reader.bits_in_buffer() < MAX_BITS + dist_extra_bits {
                     bit_reader.refill();
                 }
 
@@ -1934,10 +2173,11 @@ fn inflate_fast_help(state: &mut State, _start: usize) {
 
                     if op & 16 != 0 {
                         let op = op & MAX_BITS;
-                        let dist = here.val + bit_reader.bits(op as usize) as u16;
+                        let dist = here.val as usize + bit_reader.bits(op as usize) as usize;
 
-                        if INFLATE_STRICT && dist as usize > state.dmax {
+                        if state.flags.contains(Flags::STRICT) && dist > state.dmax {
                             bad = Some("invalid distance too far back\0");
+                            state.invalid_value = Some(InvalidValue::Distance(dist as u32));
                             state.mode = Mode::Bad;
                             break 'outer;
                         }
@@ -1947,19 +2187,45 @@ fn inflate_fast_help(state: &mut State, _start: usize) {
                         // max distance in output
                         let written = writer.len();
 
-                        if dist as usize > written {
+                        if dist > written {
                             // copy fropm the window
-                            if (dist as usize - written) > state.window.have() {
-                                if state.flags.contains(Flags::SANE) {
-                                    bad = Some("invalid distance too far back\0");
-                                    state.mode = Mode::Bad;
-                                    break 'outer;
+                            if (dist - written) > state.window.have() {
+                                let dictionary = state.dictionary();
+                                let back = dist - written - state.window.have();
+
+                                if back > dictionary.len() {
+                                    if state.flags.contains(Flags::SANE) {
+                                        bad = Some("invalid distance too far back\0");
+                                        let distance = InvalidValue::Distance(dist as u32);
+                                        state.invalid_value = Some(distance);
+                                        state.mode = Mode::Bad;
+                                        break 'outer;
+                                    }
+
+                                    panic!("INFLATE_ALLOW_INVALID_DISTANCE_TOOFAR_ARRR")
                                 }
 
-                                panic!("INFLATE_ALLOW_INVALID_DISTANCE_TOOFAR_ARRR")
+                                // The match starts in the prepared dictionary. The window has not
+                                // wrapped around yet (otherwise the dictionary would be out of
+                                // reach), so the match continues at the start of the window, and
+                                // then in the output itself.
+                                let copy = Ord::min(back, len);
+                                writer.extend(&dictionary[dictionary.len() - back..][..copy]);
+                                let len = len - copy;
+
+                                let copy = Ord::min(state.window.have(), len);
+                                if copy > 0 {
+                                    writer.extend_from_window(&state.window, 0..copy);
+                                }
+
+                                if len > copy {
+                                    writer.copy_match(dist, len - copy);
+                                }
+
+                                break 'dodist;
                             }
 
-                            let mut op = dist as usize - written;
+                            let mut op = dist - written;
                             let mut from;
 
                             let window_next = state.window.next();
@@ -1983,28 +2249,28 @@ fn inflate_fast_help(state: &mut State, _start: usize) {
                                 op -= window_next;
                                 from = window_size - op;
 
-                                if op < len as usize {
+                                if op < len {
                                     // This case is hit when part of the match is at the end of the
                                     // window, and part of it has wrapped around to the start. Copy
                                     // the end section here, the start section will be copied below.
-                                    len -= op as u16;
+                                    len -= op;
                                     writer.extend_from_window(&state.window, from..from + op);
                                     from = 0;
                                     op = window_next;
                                 }
                             }
 
-                            let copy = Ord::min(op, len as usize);
+                            let copy = Ord::min(op, len);
                             writer.extend_from_window(&state.window, from..from + copy);
 
-                            if op < len as usize {
+                            if op < len {
                                 // here we need some bytes from the output itself
-                                writer.copy_match(dist as usize, len as usize - op);
+                                writer.copy_match(dist, len - op);
                             }
                         } else if extra_safe {
                             todo!()
                         } else {
-                            writer.copy_match(dist as usize, len as usize)
+                            writer.copy_match(dist, len)
                         }
                     } else if (op & 64) == 0 {
                         // 2nd level distance code
@@ -2012,6 +2278,8 @@ fn inflate_fast_help(state: &mut State, _start: usize) {
                         continue 'dodist;
                     } else {
                         bad = Some("invalid distance code\0");
+                        let code = dropped_code(&start, &bit_reader, here.bits);
+                        state.invalid_value = Some(code);
                         state.mode = Mode::Bad;
                         break 'outer;
                     }
@@ -2028,6 +2296,8 @@ fn inflate_fast_help(state: &mut State, _start: usize) {
                 break 'outer;
             } else {
                 bad = Some("invalid literal/length code\0");
+                let code = dropped_code(&start, &bit_reader, here.bits);
+                state.invalid_value = Some(code);
                 state.mode = Mode::Bad;
                 break 'outer;
             }
@@ -2037,7 +2307,7 @@ fn inflate_fast_help(state: &mut State, _start: usize) {
 
         // include the bits in the bit_reader buffer in the count of available bytes
         let remaining = bit_reader.bytes_remaining_including_buffer();
-        if remaining >= INFLATE_FAST_MIN_HAVE && writer.remaining() >= INFLATE_FAST_MIN_LEFT {
+        if remaining >= INFLATE_FAST_MIN_HAVE && writer.remaining() >= min_left {
             continue;
         }
 
@@ -2071,18 +2341,213 @@ pub fn prime(stream: &mut InflateStream, bits: i32, value: i32) -> ReturnCode {
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct InflateConfig {
     pub window_bits: i32,
+    /// Decode Deflate64 (also called enhanced deflate, compression method 9 in ZIP archives)
+    /// instead of deflate. Deflate64 has a 64K window, lengths of up to 65538 bytes and
+    /// distances of up to 65536 bytes.
+    ///
+    /// Deflate64 streams have no zlib or gzip wrapper, so `window_bits` must be negative. The
+    /// window is always 64K, whatever the size of `window_bits`.
+    pub deflate64: bool,
+    /// Stop when the output would exceed this many bytes. Inflate then returns
+    /// [`ReturnCode::DataError`], and [`limit_exceeded`] returns [`Limit::Output`].
+    pub max_output: Option<u64>,
+    /// Stop when the output would exceed this many times the input that has been consumed so far,
+    /// see [`Limit::Ratio`]. Valid deflate data expands by a factor of at most about 1032, but
+    /// data that is nested (e.g. a compressed archive) can expand much more overall.
+    pub max_ratio: Option<u64>,
+    /// Stop when the extra field, file name or comment of a gzip header is longer than this many
+    /// bytes, see [`Limit::HeaderField`].
+    pub max_header_field: Option<usize>,
+    /// Reject distances that reach further back than the window size declared in the zlib
+    /// header (the CINFO field), or than 32K for gzip and raw deflate streams (64K for
+    /// Deflate64). Such streams are not valid, and decoders with a smaller window can't decode
+    /// them. Inflate returns [`ReturnCode::DataError`] for them.
+    pub strict: bool,
 }
 
 impl Default for InflateConfig {
     fn default() -> Self {
         Self {
             window_bits: DEF_WBITS,
+            deflate64: false,
+            max_output: None,
+            max_ratio: None,
+            max_header_field: None,
+            strict: false,
         }
     }
 }
 
+impl InflateConfig {
+    /// Set [`window_bits`](Self::window_bits).
+    pub fn with_window_bits(mut self, window_bits: i32) -> Self {
+        self.window_bits = window_bits;
+        self
+    }
+
+    /// Set [`deflate64`](Self::deflate64).
+    pub fn with_deflate64(mut self, deflate64: bool) -> Self {
+        self.deflate64 = deflate64;
+        self
+    }
+
+    /// Set [`max_output`](Self::max_output).
+    pub fn with_max_output(mut self, max_output: Option<u64>) -> Self {
+        self.max_output = max_output;
+        self
+    }
+
+    /// Set [`max_ratio`](Self::max_ratio).
+    pub fn with_max_ratio(mut self, max_ratio: Option<u64>) -> Self {
+        self.max_ratio = max_ratio;
+        self
+    }
+
+    /// Set [`max_header_field`](Self::max_header_field).
+    pub fn with_max_header_field(mut self, max_header_field: Option<usize>) -> Self {
+        self.max_header_field = max_header_field;
+        self
+    }
+
+    /// Set [`strict`](Self::strict).
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+}
+
+/// A limit of [`InflateConfig`] that was exceeded, see [`limit_exceeded`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    /// The output would exceed [`InflateConfig::max_output`].
+    Output,
+    /// The output would exceed [`InflateConfig::max_ratio`] times the input.
+    Ratio,
+    /// A gzip header field is longer than [`InflateConfig::max_header_field`].
+    HeaderField,
+}
+
+impl Limit {
+    const fn error_message(self) -> &'static str {
+        match self {
+            Limit::Output => "output limit exceeded\0",
+            Limit::Ratio => "expansion ratio limit exceeded\0",
+            Limit::HeaderField => "gzip header field too long\0",
+        }
+    }
+}
+
+/// Where and why inflate stopped at invalid data, see [`InflateStream::corruption`].
+///
+/// Positions are those where the error was detected: just after an invalid code or distance, and
+/// at the start of an invalid header or check value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Corruption {
+    /// The error message, like `msg` of the stream (without the terminating zero).
+    pub message: &'static str,
+    /// The number of whole bytes of compressed data before the error.
+    pub input_offset: u64,
+    /// The number of bits (0..=7) of the byte at `input_offset` before the error. Bits are counted
+    /// from the least significant bit of a byte, like deflate does.
+    pub input_bit: u8,
+    /// The number of bytes of uncompressed data that were produced before the error.
+    pub output_offset: u64,
+    /// The index of the deflate block that was started last, counting from 0, or `None` when the
+    /// error is before the first block, e.g. in a zlib header.
+    pub block: Option<u64>,
+    /// The value that is not valid, for the errors that have one.
+    pub value: Option<InvalidValue>,
+}
+
+/// The value that caused a [`Corruption`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvalidValue {
+    /// A Huffman code of `length` bits that has no symbol. The first bit of the code is the least
+    /// significant bit of `bits`.
+    Code { bits: u16, length: u8 },
+    /// A distance that reaches back further than the available data, or further than the window
+    /// allows, see [`InflateConfig::strict`].
+    Distance(u32),
+    /// A check value in the data that is `expected`, and the `actual` value for the data that was
+    /// decoded: the adler32 or crc32 of the uncompressed data, or the crc of a gzip header.
+    Checksum { expected: u32, actual: u32 },
+    /// A length in the gzip trailer that is `expected`, and the `actual` length of the data that
+    /// was decoded (modulo 2^32).
+    Length { expected: u32, actual: u32 },
+}
+
+/// The invalid code of `length` bits at the start of `hold`.
+fn invalid_code(hold: u64, length: u8) -> InvalidValue {
+    InvalidValue::Code {
+        bits: (hold & ((1 << length) - 1)) as u16,
+        length,
+    }
+}
+
+/// Rebuild an invalid code in the fast path, which does not keep the bits of a code around: the
+/// code was just dropped from `bit_reader`, so it is read again from `start`, the bit reader on
+/// entry of the fast path, either from its bit buffer or from the input after it.
+///
+/// Invalid codes are always in the first level of a table: the symbols without a meaning (length
+/// 286 and 287, distance 30 and 31) cannot be given a code in a dynamic block, and only a single
+/// 1-bit code may be incomplete. So `length` is the length of the table entry.
+#[cold]
+fn dropped_code(start: &BitReader, bit_reader: &BitReader, length: u8) -> InvalidValue {
+    // the position of the code, in bits from the start of the bit buffer on entry
+    let consumed = (bit_reader.as_ptr() as usize - start.as_ptr() as usize) * 8;
+    let position = start.bits_in_buffer() as usize + consumed
+        - bit_reader.bits_in_buffer() as usize
+        - length as usize;
+
+    let mut hold = 0;
+    for i in 0..length as usize {
+        let bit = match (position + i).checked_sub(start.bits_in_buffer() as usize) {
+            None => start.hold() >> (position + i),
+            // SAFETY: the byte is before the position of `bit_reader`, so it is in the input
+            Some(offset) => (unsafe { *start.as_ptr().add(offset / 8) } >> (offset % 8)) as u64,
+        };
+        hold |= (bit & 1) << i;
+    }
+
+    invalid_code(hold, length)
+}
+
+/// The limits of [`InflateConfig`].
+#[derive(Debug, Clone, Copy, Default)]
+struct Limits {
+    max_output: Option<u64>,
+    max_ratio: Option<u64>,
+    max_header_field: Option<usize>,
+}
+
+impl Limits {
+    /// The total output that is allowed after `total_in` bytes of input have been consumed.
+    fn max_total_output(&self, total_in: usize) -> u64 {
+        let max_output = self.max_output.unwrap_or(u64::MAX);
+        let max_ratio = match self.max_ratio {
+            Some(max_ratio) => max_ratio.saturating_mul(total_in as u64),
+            None => u64::MAX,
+        };
+
+        Ord::min(max_output, max_ratio)
+    }
+
+    /// Whether a gzip header field of `len` bytes is too long. A terminating zero is not counted.
+    fn header_field_exceeded(&self, len: usize, terminated: bool) -> bool {
+        let len = len - terminated as usize;
+        self.max_header_field.is_some_and(|max| len > max)
+    }
+}
+
+/// The limit that stopped `stream`, if any. Inflate stops with [`ReturnCode::DataError`] when a
+/// limit of [`InflateConfig`] is exceeded, and this distinguishes that from invalid data.
+pub fn limit_exceeded(stream: &InflateStream) -> Option<Limit> {
+    stream.state.limit_exceeded
+}
+
 /// Initialize the stream in an inflate state
 pub fn init(stream: &mut z_stream, config: InflateConfig) -> ReturnCode {
     stream.msg = core::ptr::null_mut();
@@ -2167,6 +2632,14 @@ pub fn reset_with_config(stream: &mut InflateStream, config: InflateConfig) -> R
         return ReturnCode::StreamError;
     }
 
+    if config.deflate64 {
+        if wrap != 0 {
+            return ReturnCode::StreamError;
+        }
+
+        window_bits = MAX_WBITS + 1;
+    }
+
     if stream.state.window.size() != 0 && stream.state.wbits as i32 != window_bits {
         let mut window = Window::empty();
         core::mem::swap(&mut window, &mut stream.state.window);
@@ -2179,6 +2652,16 @@ pub fn reset_with_config(stream: &mut InflateStream, config: InflateConfig) -> R
 
     stream.state.wrap = wrap as u8;
     stream.state.wbits = window_bits as _;
+    stream
+        .state
+        .flags
+        .update(Flags::DEFLATE64, config.deflate64);
+    stream.state.flags.update(Flags::STRICT, config.strict);
+    stream.state.limits = Limits {
+        max_output: config.max_output,
+        max_ratio: config.max_ratio,
+        max_header_field: config.max_header_field,
+    };
 
     reset(stream)
 }
@@ -2186,8 +2669,12 @@ pub fn reset_with_config(stream: &mut InflateStream, config: InflateConfig) -> R
 pub fn reset(stream: &mut InflateStream) -> ReturnCode {
     // reset the state of the window
     stream.state.window.clear();
+    stream.state.dictionary = &[];
 
     stream.state.error_message = None;
+    stream.state.limit_exceeded = None;
+    stream.state.invalid_value = None;
+    stream.state.corruption = None;
 
     reset_keep(stream)
 }
@@ -2212,10 +2699,15 @@ pub fn reset_keep(stream: &mut InflateStream) -> ReturnCode {
     state.flags.update(Flags::IS_LAST_BLOCK, false);
     state.flags.update(Flags::HAVE_DICT, false);
     state.flags.update(Flags::SANE, true);
+    state.flags.update(Flags::SINGLE_SHOT, false);
     state.gzip_flags = -1;
-    state.dmax = 32768;
+    state.dmax = match state.flags.contains(Flags::DEFLATE64) {
+        true => 1 << 16,
+        false => 1 << 15,
+    };
     state.head = None;
     state.bit_reader = BitReader::new(&[]);
+    state.blocks = 0;
 
     state.next = 0;
     state.len_table = Table::default();
@@ -2245,15 +2737,50 @@ pub unsafe fn inflate(stream: &mut InflateStream, flush: InflateFlush) -> Return
             .bit_reader
             .update_slice(stream.next_in, stream.avail_in as usize)
     };
-    state.writer = Writer::new_uninit(stream.next_out.cast(), stream.avail_out as usize);
+    // With a limit on the output, allow one byte more than the limit, to find out whether the
+    // limit is exceeded. That byte is not reported as output.
+    let max_total = state
+        .limits
+        .max_total_output(stream.total_in as usize + stream.avail_in as usize);
+    let allowed = max_total
+        .saturating_sub(state.total as u64)
+        .saturating_add(1);
+    let avail_out = Ord::min(stream.avail_out as u64, allowed) as usize;
+
+    state.writer = Writer::new_uninit(stream.next_out.cast(), avail_out);
 
     state.in_available = stream.avail_in as _;
-    state.out_available = stream.avail_out as _;
+    state.out_available = avail_out;
+
+    let total_before = state.total;
 
     let mut err = state.dispatch();
 
     let in_read = state.bit_reader.as_ptr() as usize - stream.next_in as usize;
-    let out_written = state.out_available - (state.writer.capacity() - state.writer.len());
+    let mut out_written = state.out_available - (state.writer.capacity() - state.writer.len());
+
+    // `Mode::Check` already adds its output to `state.total`
+    let max_total = state
+        .limits
+        .max_total_output(stream.total_in as usize + in_read);
+    let new_total = (state.total + out_written) as u64;
+    if new_total > max_total {
+        let limit = match state.limits.max_output {
+            Some(max_output) if new_total > max_output => Limit::Output,
+            _ => Limit::Ratio,
+        };
+
+        let excess = (new_total - max_total) as usize;
+        state.total -= excess.saturating_sub(out_written);
+        out_written = out_written.saturating_sub(excess);
+
+        state.limit_exceeded = Some(limit);
+        state.mode = Mode::Bad;
+        state.error_message = Some(limit.error_message());
+        err = ReturnCode::DataError;
+    }
+
+    let produced = state.total + out_written - total_before;
 
     stream.total_in += in_read as z_size;
     state.total += out_written;
@@ -2262,8 +2789,30 @@ pub unsafe fn inflate(stream: &mut InflateStream, flush: InflateFlush) -> Return
     stream.avail_in = state.bit_reader.bytes_remaining() as u32;
     stream.next_in = state.bit_reader.as_ptr() as *mut u8;
 
-    stream.avail_out = (state.writer.capacity() - state.writer.len()) as u32;
-    stream.next_out = state.writer.next_out() as *mut u8;
+    stream.avail_out -= produced as u32;
+    stream.next_out = stream.next_out.wrapping_add(produced);
+
+    if let (Mode::Bad, None, None) = (state.mode, state.corruption, state.limit_exceeded) {
+        // the bits in the buffer have been counted as input, but have not been used yet
+        let bits = state.bit_reader.bits_in_buffer() as usize;
+        let unused = bits.div_ceil(8);
+        let message = state.error_message.unwrap_or("\0");
+
+        // bits that were primed (see `prime`) are before the start of the input
+        let (input_offset, input_bit) = match (stream.total_in as usize).checked_sub(unused) {
+            Some(input_offset) => (input_offset as u64, (unused * 8 - bits) as u8),
+            None => (0, 0),
+        };
+
+        state.corruption = Some(Corruption {
+            message: message.trim_end_matches('\0'),
+            input_offset,
+            input_bit,
+            output_offset: state.total as u64,
+            block: (state.blocks as u64).checked_sub(1),
+            value: state.invalid_value,
+        });
+    }
 
     stream.adler = state.checksum as z_checksum;
 
@@ -2275,10 +2824,13 @@ pub unsafe fn inflate(stream: &mut InflateStream, flush: InflateFlush) -> Return
         )
     };
 
-    let must_update_window = state.window.size() != 0
-        || (out_written != 0
-            && valid_mode(state.mode)
-            && (not_done(state.mode) || !matches!(state.flush, InflateFlush::Finish)));
+    // the checksum of the data before the check value is computed in `Mode::Check`, so a
+    // single-shot decode loses nothing by skipping the window
+    let must_update_window = !state.flags.contains(Flags::SINGLE_SHOT)
+        && (state.window.size() != 0
+            || (out_written != 0
+                && valid_mode(state.mode)
+                && (not_done(state.mode) || !matches!(state.flush, InflateFlush::Finish))));
 
     let update_checksum = state.wrap & 4 != 0;
 
@@ -2437,6 +2989,7 @@ pub unsafe fn copy<'a>(
         dist_table: state.dist_table,
         wbits: state.wbits,
         window: Window::empty(),
+        dictionary: state.dictionary,
         head: None,
         ncode: state.ncode,
         nlen: state.nlen,
@@ -2455,8 +3008,15 @@ pub unsafe fn copy<'a>(
         in_available: state.in_available,
         out_available: state.out_available,
         lens: state.lens,
+        code_length_lengths: state.code_length_lengths,
         work: state.work,
         error_message: state.error_message,
+        limits: state.limits,
+        limit_exceeded: state.limit_exceeded,
+        header_field: state.header_field,
+        blocks: state.blocks,
+        invalid_value: state.invalid_value,
+        corruption: state.corruption,
         flush: state.flush,
         checksum: state.checksum,
         crc_fold: state.crc_fold,
@@ -2531,6 +3091,10 @@ pub fn set_dictionary(stream: &mut InflateStream, dictionary: &[u8]) -> ReturnCo
         }
     }
 
+    insert_dictionary(stream, dictionary)
+}
+
+fn insert_dictionary(stream: &mut InflateStream, dictionary: &[u8]) -> ReturnCode {
     let err = 'blk: {
         // initialize the window if needed
         if stream.state.window.size() == 0 {
@@ -2561,6 +3125,66 @@ pub fn set_dictionary(stream: &mut InflateStream, dictionary: &[u8]) -> ReturnCo
     ReturnCode::Ok
 }
 
+/// A preset dictionary that is shared by many inflate streams, see [`set_prepared_dictionary`].
+#[derive(Debug, Clone, Copy)]
+pub struct PreparedDictionary<'a> {
+    /// The part of the dictionary that can be referenced, at most the maximum window size.
+    bytes: &'a [u8],
+    id: u32,
+}
+
+impl<'a> PreparedDictionary<'a> {
+    pub fn new(dictionary: &'a [u8]) -> Self {
+        let max_window_size = 1 << MAX_WBITS;
+
+        Self {
+            bytes: &dictionary[dictionary.len().saturating_sub(max_window_size)..],
+            id: adler32(1, dictionary),
+        }
+    }
+
+    /// The Adler-32 checksum of the dictionary, which zlib streams use to identify it.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// Like [`set_dictionary`], but the dictionary is not copied into the window. Matches that reach
+/// back into the dictionary read from it directly, so for short messages the window may never be
+/// allocated at all.
+///
+/// When the stream already has history (e.g. for a raw stream that has produced output), the
+/// dictionary is copied into the window after all, just like [`set_dictionary`] does.
+///
+/// # Safety
+///
+/// The stream keeps a reference to the bytes of `dictionary`, which the lifetime of
+/// [`InflateStream`] does not enforce. The caller must guarantee that the bytes stay alive and
+/// are not modified until the stream is reset with [`reset`] (or one of its variants) or ended
+/// with [`end`].
+pub unsafe fn set_prepared_dictionary<'a>(
+    stream: &mut InflateStream<'a>,
+    dictionary: &PreparedDictionary<'a>,
+) -> ReturnCode {
+    if stream.state.wrap != 0 && !matches!(stream.state.mode, Mode::Dict) {
+        return ReturnCode::StreamError;
+    }
+
+    // check for correct dictionary identifier
+    if matches!(stream.state.mode, Mode::Dict) && dictionary.id != stream.state.checksum {
+        return ReturnCode::DataError;
+    }
+
+    if stream.state.window.have() != 0 || !stream.state.dictionary.is_empty() {
+        return insert_dictionary(stream, dictionary.bytes);
+    }
+
+    stream.state.dictionary = dictionary.bytes;
+    stream.state.flags.update(Flags::HAVE_DICT, true);
+
+    ReturnCode::Ok
+}
+
 pub fn end<'a>(stream: &'a mut InflateStream<'a>) -> &'a mut z_stream {
     let alloc = stream.alloc;
 
@@ -2621,9 +3245,1024 @@ mod tests {
             4, 137, 106,
         ];
 
-        let config = InflateConfig { window_bits: 15 };
+        let config = InflateConfig {
+            window_bits: 15,
+            ..Default::default()
+        };
 
         let (_decompressed, err) = uncompress_slice(&mut output, &input, config);
         assert_eq!(err, ReturnCode::DataError);
     }
+
+    #[test]
+    fn single_shot() {
+        use crate::deflate::{self, DeflateConfig};
+
+        let paper = include_bytes!("deflate/test-data/paper-100k.pdf");
+
+        /// Decompress in one call, returning whether a window was allocated.
+        fn decompress(
+            input: &[u8],
+            output: &mut [u8],
+            window_bits: i32,
+            single_shot: bool,
+        ) -> bool {
+            let config = InflateConfig {
+                window_bits,
+                ..Default::default()
+            };
+
+            let mut stream = z_stream::default();
+            assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+            let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+            stream.state.flags.update(Flags::SINGLE_SHOT, single_shot);
+
+            stream.next_in = input.as_ptr() as *mut u8;
+            stream.avail_in = input.len() as _;
+            stream.next_out = output.as_mut_ptr();
+            stream.avail_out = output.len() as _;
+
+            let err = unsafe { inflate(stream, InflateFlush::NoFlush) };
+            assert_eq!(err, ReturnCode::StreamEnd);
+
+            let allocated = stream.state.window.size() != 0;
+            end(stream);
+
+            allocated
+        }
+
+        for window_bits in [15, -15, 31] {
+            for len in [4096, 64 * 1024, paper.len()] {
+                let input = &paper[..len];
+
+                let config = DeflateConfig {
+                    window_bits,
+                    ..DeflateConfig::new(6)
+                };
+                let mut compressed = vec![0; deflate::compress_bound(len)];
+                let (compressed, err) = deflate::compress_slice(&mut compressed, input, config);
+                assert_eq!(err, ReturnCode::Ok);
+
+                let config = InflateConfig {
+                    window_bits,
+                    ..Default::default()
+                };
+
+                let mut output = vec![0; len];
+                let (output, err) = uncompress_slice(&mut output, compressed, config);
+                assert_eq!(err, ReturnCode::Ok);
+                assert!(output == input);
+
+                // the output fills up
+                let mut output = vec![0; len - 1];
+                let (output, err) = uncompress_slice(&mut output, compressed, config);
+                assert_eq!(err, ReturnCode::BufError);
+                assert!(input.starts_with(output));
+
+                let mut output = vec![0; len];
+                assert!(!decompress(compressed, &mut output, window_bits, true));
+                assert!(output == input);
+
+                // a raw stream has no check value, so its output is kept for a later call
+                let allocated = decompress(compressed, &mut output, window_bits, false);
+                assert_eq!(allocated, window_bits < 0);
+            }
+        }
+    }
+
+    #[test]
+    fn prepared_dictionary() {
+        use crate::deflate::{self, DeflateConfig, DeflateStream};
+
+        let paper = include_bytes!("deflate/test-data/paper-100k.pdf");
+        let input = &paper[60_000..70_000];
+
+        fn compress(input: &[u8], dictionary: &[u8], window_bits: i32) -> Vec<u8> {
+            let config = DeflateConfig {
+                window_bits,
+                ..DeflateConfig::default()
+            };
+
+            let mut stream = z_stream::default();
+            assert_eq!(deflate::init(&mut stream, config), ReturnCode::Ok);
+            let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+            assert_eq!(deflate::set_dictionary(stream, dictionary), ReturnCode::Ok);
+
+            let mut output = vec![0; deflate::compress_bound(input.len())];
+            stream.next_in = input.as_ptr() as *mut u8;
+            stream.avail_in = input.len() as _;
+            stream.next_out = output.as_mut_ptr();
+            stream.avail_out = output.len() as _;
+
+            let err = deflate::deflate(stream, crate::DeflateFlush::Finish);
+            assert_eq!(err, ReturnCode::StreamEnd);
+
+            output.truncate(stream.total_out as usize);
+            assert!(deflate::end(stream).is_ok());
+
+            output
+        }
+
+        /// Decompress, with at most `chunk` bytes of output per call.
+        fn decompress(
+            input: &[u8],
+            dictionary: &PreparedDictionary,
+            window_bits: i32,
+            chunk: usize,
+            len: usize,
+        ) -> Vec<u8> {
+            let mut stream = z_stream::default();
+            assert_eq!(
+                init(
+                    &mut stream,
+                    InflateConfig {
+                        window_bits,
+                        ..Default::default()
+                    }
+                ),
+                ReturnCode::Ok
+            );
+            let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+            if window_bits < 0 {
+                assert_eq!(
+                    unsafe { set_prepared_dictionary(stream, dictionary) },
+                    ReturnCode::Ok
+                );
+            }
+
+            let mut output = vec![0; len];
+            stream.next_in = input.as_ptr() as *mut u8;
+            stream.avail_in = input.len() as _;
+
+            loop {
+                let written = stream.total_out as usize;
+                let chunk = Ord::min(chunk, len - written);
+                stream.next_out = output[written..].as_mut_ptr();
+                stream.avail_out = chunk as _;
+
+                let flush = match chunk >= len {
+                    true => InflateFlush::Finish,
+                    false => InflateFlush::NoFlush,
+                };
+
+                match unsafe { inflate(stream, flush) } {
+                    ReturnCode::StreamEnd => break,
+                    ReturnCode::NeedDict => {
+                        assert_eq!(stream.adler as u32, dictionary.id());
+                        assert_eq!(
+                            unsafe { set_prepared_dictionary(stream, dictionary) },
+                            ReturnCode::Ok
+                        );
+                    }
+                    err => assert_eq!(err, ReturnCode::Ok),
+                }
+            }
+
+            // when decompressing in one go, the window was never needed
+            if chunk >= len {
+                assert_eq!(stream.state.window.size(), 0);
+            }
+
+            output.truncate(stream.total_out as usize);
+            end(stream);
+
+            output
+        }
+
+        for dictionary_len in [1000, 20_000, 70_000] {
+            let dictionary = &paper[..dictionary_len];
+            let prepared = PreparedDictionary::new(dictionary);
+
+            for window_bits in [15, -15, 9] {
+                let compressed = compress(input, dictionary, window_bits);
+
+                for chunk in [input.len(), 4096, 7] {
+                    let output =
+                        decompress(&compressed, &prepared, window_bits, chunk, input.len());
+                    assert!(output == input, "{dictionary_len} {window_bits} {chunk}");
+                }
+            }
+        }
+
+        // the wrong dictionary
+        let compressed = compress(input, &paper[..1000], 15);
+        let prepared = PreparedDictionary::new(&paper[..999]);
+
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, InflateConfig::default()), ReturnCode::Ok);
+        let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut output = vec![0; input.len()];
+        stream.next_in = compressed.as_ptr() as *mut u8;
+        stream.avail_in = compressed.len() as _;
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+
+        let err = unsafe { inflate(stream, InflateFlush::NoFlush) };
+        assert_eq!(err, ReturnCode::NeedDict);
+        assert_eq!(
+            unsafe { set_prepared_dictionary(stream, &prepared) },
+            ReturnCode::DataError
+        );
+        end(stream);
+    }
+
+    /// Writes a deflate stream bit by bit, to produce Deflate64 streams for the tests.
+    struct BitWriter {
+        bytes: Vec<u8>,
+        bits: usize,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, len: usize) {
+            for i in 0..len {
+                if self.bits % 8 == 0 {
+                    self.bytes.push(0);
+                }
+                *self.bytes.last_mut().unwrap() |= ((value >> i) as u8 & 1) << (self.bits % 8);
+                self.bits += 1;
+            }
+        }
+
+        /// Huffman codes are written starting with their most significant bit.
+        fn write_code(&mut self, (code, len): (u16, u8)) {
+            let reversed = (code as u32).reverse_bits() >> (32 - len as u32);
+            self.write(reversed, len as usize);
+        }
+
+        fn align(&mut self) {
+            self.bits = self.bytes.len() * 8;
+        }
+    }
+
+    /// The canonical huffman code for these code lengths.
+    fn canonical(lengths: &[u8]) -> Vec<(u16, u8)> {
+        let mut count = [0u16; 16];
+        lengths.iter().for_each(|&len| count[len as usize] += 1);
+        count[0] = 0;
+
+        let mut code = 0;
+        let mut next: [u16; 16] = core::array::from_fn(|len| {
+            if len > 0 {
+                code = (code + count[len - 1]) << 1;
+            }
+            code
+        });
+
+        lengths
+            .iter()
+            .map(|&len| {
+                let code = next[len as usize];
+                next[len as usize] += 1;
+                (code, len)
+            })
+            .collect()
+    }
+
+    enum Symbol {
+        Literal(u8),
+        Match { length: usize, distance: usize },
+    }
+
+    /// The (code, extra bits, extra value) of Deflate64 lengths and distances.
+    fn length_code(length: usize) -> (usize, usize, usize) {
+        const BASE: [usize; 28] = [
+            3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99,
+            115, 131, 163, 195, 227,
+        ];
+        match BASE.iter().rposition(|&base| base <= length) {
+            Some(i) if length < 258 => (257 + i, i.saturating_sub(4) / 4, length - BASE[i]),
+            _ => (285, 16, length - 3),
+        }
+    }
+
+    fn distance_code(distance: usize) -> (usize, usize, usize) {
+        let mut base = 1;
+        for code in 0..32usize {
+            let extra = code.saturating_sub(2) / 2;
+            if distance < base + (1 << extra) {
+                return (code, extra, distance - base);
+            }
+            base += 1 << extra;
+        }
+        unreachable!()
+    }
+
+    fn write_symbols(
+        writer: &mut BitWriter,
+        symbols: &[Symbol],
+        lit_codes: &[(u16, u8)],
+        dist_codes: &[(u16, u8)],
+    ) {
+        for symbol in symbols {
+            match *symbol {
+                Symbol::Literal(byte) => writer.write_code(lit_codes[byte as usize]),
+                Symbol::Match { length, distance } => {
+                    let (code, extra, value) = length_code(length);
+                    writer.write_code(lit_codes[code]);
+                    writer.write(value as u32, extra);
+
+                    let (code, extra, value) = distance_code(distance);
+                    writer.write_code(dist_codes[code]);
+                    writer.write(value as u32, extra);
+                }
+            }
+        }
+
+        writer.write_code(lit_codes[256]);
+    }
+
+    /// A Deflate64 stream of a stored block followed by a fixed or dynamic block with `symbols`,
+    /// and the data it decodes to.
+    fn deflate64_stream(prefix: &[u8], symbols: &[Symbol], dynamic: bool) -> (Vec<u8>, Vec<u8>) {
+        let mut writer = BitWriter {
+            bytes: Vec::new(),
+            bits: 0,
+        };
+
+        // a stored block with the prefix
+        writer.write(0b000, 3);
+        writer.align();
+        writer.write(prefix.len() as u32, 16);
+        writer.write(!prefix.len() as u32, 16);
+        writer.bytes.extend_from_slice(prefix);
+        writer.align();
+
+        let mut lit_lengths = [8u8; 288];
+        lit_lengths[144..256].fill(9);
+        lit_lengths[256..280].fill(7);
+        let dist_lengths = [5u8; 32];
+
+        if dynamic {
+            // the fixed code lengths, but for 286 symbols, and with all 32 distance codes
+            lit_lengths[284] = 7;
+            lit_lengths[285] = 7;
+            let lit_lengths = &lit_lengths[..286];
+
+            writer.write(0b101, 3);
+            writer.write(286 - 257, 5);
+            writer.write(32 - 1, 5);
+            writer.write(10 - 4, 4);
+
+            // code length codes 5, 7, 8 and 9 have 2 bits
+            const ORDER: [usize; 10] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5];
+            let mut code_lengths = [0u8; 19];
+            for symbol in [5, 7, 8, 9] {
+                code_lengths[symbol] = 2;
+            }
+            for symbol in ORDER {
+                writer.write(code_lengths[symbol] as u32, 3);
+            }
+
+            let length_codes = canonical(&code_lengths);
+            for &length in lit_lengths.iter().chain(&dist_lengths) {
+                writer.write_code(length_codes[length as usize]);
+            }
+
+            write_symbols(
+                &mut writer,
+                symbols,
+                &canonical(lit_lengths),
+                &canonical(&dist_lengths),
+            );
+        } else {
+            writer.write(0b011, 3);
+            write_symbols(
+                &mut writer,
+                symbols,
+                &canonical(&lit_lengths),
+                &canonical(&dist_lengths),
+            );
+        }
+
+        let mut output = prefix.to_vec();
+        for symbol in symbols {
+            match *symbol {
+                Symbol::Literal(byte) => output.push(byte),
+                Symbol::Match { length, distance } => {
+                    for _ in 0..length {
+                        output.push(output[output.len() - distance]);
+                    }
+                }
+            }
+        }
+
+        (writer.bytes, output)
+    }
+
+    /// Decompress, with at most `chunk` bytes of input and output per call.
+    fn decompress_chunked(
+        input: &[u8],
+        config: InflateConfig,
+        chunk: usize,
+        len: usize,
+    ) -> (Vec<u8>, ReturnCode) {
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+        let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut output = vec![0; len];
+
+        let err = loop {
+            let read = stream.total_in as usize;
+            let written = stream.total_out as usize;
+            stream.next_in = input[read..].as_ptr() as *mut u8;
+            stream.avail_in = Ord::min(chunk, input.len() - read) as _;
+            stream.next_out = output[written..].as_mut_ptr();
+            stream.avail_out = Ord::min(chunk, len - written) as _;
+
+            match unsafe { inflate(stream, InflateFlush::NoFlush) } {
+                ReturnCode::Ok => {}
+                err => break err,
+            }
+        };
+
+        output.truncate(stream.total_out as usize);
+        end(stream);
+
+        (output, err)
+    }
+
+    #[test]
+    fn deflate64() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        let prefix: Vec<u8> = (0..60_000)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                (seed >> 32) as u8
+            })
+            .collect();
+
+        let symbols = [
+            // distance code 31 and length code 285 with 16 extra bits
+            Symbol::Match {
+                length: 40_000,
+                distance: 50_000,
+            },
+            // distance code 30
+            Symbol::Match {
+                length: 10,
+                distance: 40_000,
+            },
+            Symbol::Literal(b'x'),
+            Symbol::Match {
+                length: 258,
+                distance: 65_536,
+            },
+            // the longest match
+            Symbol::Match {
+                length: 65_538,
+                distance: 1,
+            },
+            Symbol::Literal(b'y'),
+        ];
+
+        let config = InflateConfig {
+            window_bits: -15,
+            deflate64: true,
+            ..Default::default()
+        };
+
+        for dynamic in [false, true] {
+            let (input, expected) = deflate64_stream(&prefix, &symbols, dynamic);
+
+            let mut output = vec![0; expected.len()];
+            let (output, err) = uncompress_slice(&mut output, &input, config);
+            assert_eq!(err, ReturnCode::Ok);
+            assert!(output == expected);
+
+            // with a window, and in the slow path
+            for chunk in [100_000, 4096, 7] {
+                let (output, err) = decompress_chunked(&input, config, chunk, expected.len());
+                assert_eq!(err, ReturnCode::StreamEnd, "{dynamic} {chunk}");
+                assert!(output == expected, "{dynamic} {chunk}");
+            }
+
+            // this is not a valid deflate stream
+            let mut output = vec![0; expected.len()];
+            let config = InflateConfig {
+                window_bits: -15,
+                ..Default::default()
+            };
+            let (_, err) = uncompress_slice(&mut output, &input, config);
+            assert_eq!(err, ReturnCode::DataError);
+        }
+
+        // Deflate64 has no zlib or gzip wrapper
+        for window_bits in [15, 31] {
+            let mut stream = z_stream::default();
+            let config = InflateConfig {
+                window_bits,
+                deflate64: true,
+                ..Default::default()
+            };
+            assert_eq!(init(&mut stream, config), ReturnCode::StreamError);
+        }
+    }
+
+    /// Decompress with at most `chunk` bytes of input and output per call, and report which limit
+    /// was exceeded.
+    fn decompress_limited(
+        input: &[u8],
+        config: InflateConfig,
+        chunk: usize,
+        len: usize,
+    ) -> (Vec<u8>, ReturnCode, Option<Limit>) {
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+        let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut output = vec![0; len];
+
+        let err = loop {
+            let read = stream.total_in as usize;
+            let written = stream.total_out as usize;
+            stream.next_in = input[read..].as_ptr() as *mut u8;
+            stream.avail_in = Ord::min(chunk, input.len() - read) as _;
+            stream.next_out = output[written..].as_mut_ptr();
+            stream.avail_out = Ord::min(chunk, len - written) as _;
+
+            match unsafe { inflate(stream, InflateFlush::NoFlush) } {
+                ReturnCode::Ok => {}
+                err => break err,
+            }
+        };
+
+        // the stream stays stopped
+        if err == ReturnCode::DataError {
+            let total_out = stream.total_out;
+            stream.avail_out = (len - total_out as usize) as _;
+            let again = unsafe { inflate(stream, InflateFlush::NoFlush) };
+            assert_eq!(again, ReturnCode::DataError);
+            assert_eq!(stream.total_out, total_out);
+        }
+
+        let limit = limit_exceeded(stream);
+        output.truncate(stream.total_out as usize);
+        end(stream);
+
+        (output, err, limit)
+    }
+
+    #[test]
+    fn output_limits() {
+        let expected: Vec<u8> = (0..100_000u64).map(|i| (i * i / 7 % 251) as u8).collect();
+
+        let mut buf = vec![0; 200_000];
+        let (input, err) = crate::deflate::compress_slice(&mut buf, &expected, Default::default());
+        assert_eq!(err, ReturnCode::Ok);
+
+        for chunk in [200_000, 4096, 7] {
+            let (output, err, limit) =
+                decompress_limited(input, InflateConfig::default(), chunk, 200_000);
+            assert_eq!((err, limit), (ReturnCode::StreamEnd, None));
+            assert!(output == expected);
+
+            // exactly at the limit is fine
+            let config = InflateConfig {
+                max_output: Some(expected.len() as u64),
+                ..Default::default()
+            };
+            let (output, err, limit) = decompress_limited(input, config, chunk, 200_000);
+            assert_eq!((err, limit), (ReturnCode::StreamEnd, None));
+            assert!(output == expected);
+
+            for max_output in [0, 1, 4095, 50_000, expected.len() as u64 - 1] {
+                let config = InflateConfig {
+                    max_output: Some(max_output),
+                    ..Default::default()
+                };
+                let (output, err, limit) = decompress_limited(input, config, chunk, 200_000);
+                assert_eq!(err, ReturnCode::DataError, "{chunk} {max_output}");
+                assert_eq!(limit, Some(Limit::Output));
+                assert!(output == expected[..max_output as usize]);
+            }
+        }
+    }
+
+    #[test]
+    fn ratio_limit() {
+        let expected = vec![0u8; 1 << 20];
+
+        let mut buf = vec![0; 1 << 16];
+        let (input, err) = crate::deflate::compress_slice(&mut buf, &expected, Default::default());
+        assert_eq!(err, ReturnCode::Ok);
+
+        for chunk in [1 << 21, 4096, 7] {
+            let config = InflateConfig {
+                max_ratio: Some(2000),
+                ..Default::default()
+            };
+            let (output, err, limit) = decompress_limited(input, config, chunk, 1 << 21);
+            assert_eq!((err, limit), (ReturnCode::StreamEnd, None));
+            assert!(output == expected);
+
+            let config = InflateConfig {
+                max_ratio: Some(100),
+                ..Default::default()
+            };
+            let (output, err, limit) = decompress_limited(input, config, chunk, 1 << 21);
+            assert_eq!((err, limit), (ReturnCode::DataError, Some(Limit::Ratio)));
+            assert!(output.iter().all(|&b| b == 0));
+
+            assert!(output.len() <= 100 * input.len(), "{chunk}");
+
+            // the output limit takes precedence in the report when both are exceeded
+            let config = InflateConfig {
+                max_ratio: Some(100),
+                max_output: Some(10),
+                ..Default::default()
+            };
+            let (output, err, limit) = decompress_limited(input, config, chunk, 1 << 21);
+            assert_eq!((err, limit), (ReturnCode::DataError, Some(Limit::Output)));
+            assert_eq!(output.len(), 10);
+        }
+    }
+
+    #[test]
+    fn header_field_limit() {
+        let expected = b"Hello, world!\n".repeat(100);
+
+        let mut buf = vec![0; 4096];
+        let config = crate::deflate::DeflateConfig {
+            window_bits: -15,
+            ..Default::default()
+        };
+        let (raw, err) = crate::deflate::compress_slice(&mut buf, &expected, config);
+        assert_eq!(err, ReturnCode::Ok);
+
+        let field = vec![b'x'; 300];
+
+        // FEXTRA, FNAME and FCOMMENT
+        for flag in [4u8, 8, 16] {
+            let mut input = vec![31, 139, 8, flag, 0, 0, 0, 0, 0, 255];
+            match flag {
+                4 => {
+                    input.extend_from_slice(&(field.len() as u16).to_le_bytes());
+                    input.extend_from_slice(&field);
+                }
+                _ => {
+                    input.extend_from_slice(&field);
+                    input.push(0);
+                }
+            }
+            input.extend_from_slice(raw);
+            input.extend_from_slice(&crate::crc32::crc32(0, &expected).to_le_bytes());
+            input.extend_from_slice(&(expected.len() as u32).to_le_bytes());
+
+            for chunk in [4096, 7] {
+                for max_header_field in [None, Some(300), Some(1000)] {
+                    let config = InflateConfig {
+                        window_bits: 31,
+                        max_header_field,
+                        ..Default::default()
+                    };
+                    let (output, err, limit) = decompress_limited(&input, config, chunk, 4096);
+                    assert_eq!((err, limit), (ReturnCode::StreamEnd, None));
+                    assert!(output == expected);
+                }
+
+                for max_header_field in [0, 299] {
+                    let config = InflateConfig {
+                        window_bits: 31,
+                        max_header_field: Some(max_header_field),
+                        ..Default::default()
+                    };
+                    let (output, err, limit) = decompress_limited(&input, config, chunk, 4096);
+                    assert_eq!(err, ReturnCode::DataError, "{flag} {chunk}");
+                    assert_eq!(limit, Some(Limit::HeaderField));
+                    assert!(output.is_empty());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strict_distance() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let block: Vec<u8> = (0..3000)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                (seed >> 32) as u8
+            })
+            .collect();
+        let expected = block.repeat(10);
+
+        let mut buf = vec![0; 8192];
+        let (input, err) = crate::deflate::compress_slice(&mut buf, &expected, Default::default());
+        assert_eq!(err, ReturnCode::Ok);
+
+        let strict = InflateConfig {
+            strict: true,
+            ..Default::default()
+        };
+
+        // the distances are within the 32K window that the header declares
+        for chunk in [100_000, 7] {
+            let (output, err) = decompress_chunked(input, strict, chunk, expected.len());
+            assert_eq!(err, ReturnCode::StreamEnd);
+            assert!(output == expected);
+        }
+
+        // declare a 2K window, which is smaller than the distances that are used
+        let mut input = input.to_vec();
+        input[0] = 0x38;
+        input[1] &= 0b1110_0000;
+        input[1] += 31 - ((input[0] as u16 * 256 + input[1] as u16) % 31) as u8;
+
+        for chunk in [100_000, 7] {
+            let (output, err) =
+                decompress_chunked(&input, Default::default(), chunk, expected.len());
+            assert_eq!(err, ReturnCode::StreamEnd);
+            assert!(output == expected);
+
+            let (output, err) = decompress_chunked(&input, strict, chunk, expected.len());
+            assert_eq!(err, ReturnCode::DataError, "{chunk}");
+            assert!(output == expected[..output.len()]);
+            assert!(output.len() < 2 * block.len());
+        }
+    }
+
+    /// Decompress with at most `chunk` bytes of input and output per call, and report where the
+    /// data is corrupt.
+    fn decompress_corrupt(
+        input: &[u8],
+        config: InflateConfig,
+        chunk: usize,
+    ) -> (ReturnCode, Option<Corruption>) {
+        let mut stream = z_stream::default();
+        assert_eq!(init(&mut stream, config), ReturnCode::Ok);
+        let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut output = vec![0; 1 << 16];
+
+        let err = loop {
+            let read = stream.total_in as usize;
+            let written = stream.total_out as usize;
+            stream.next_in = input[read..].as_ptr() as *mut u8;
+            stream.avail_in = Ord::min(chunk, input.len() - read) as _;
+            stream.next_out = output[written..].as_mut_ptr();
+            stream.avail_out = Ord::min(chunk, output.len() - written) as _;
+
+            match unsafe { inflate(stream, InflateFlush::NoFlush) } {
+                ReturnCode::Ok => {}
+                err => break err,
+            }
+        };
+
+        // a repeated call does not change the diagnostics
+        let corruption = stream.corruption();
+        if err == ReturnCode::DataError {
+            let again = unsafe { inflate(stream, InflateFlush::NoFlush) };
+            assert_eq!(again, ReturnCode::DataError);
+            assert_eq!(stream.corruption(), corruption);
+        }
+
+        end(stream);
+
+        (err, corruption)
+    }
+
+    #[test]
+    fn corruption_in_block() {
+        let mut lit_lengths = [8u8; 288];
+        lit_lengths[144..256].fill(9);
+        lit_lengths[256..280].fill(7);
+        let lit_codes = canonical(&lit_lengths);
+        let dist_codes = canonical(&[5u8; 32]);
+
+        let raw = InflateConfig {
+            window_bits: -15,
+            ..Default::default()
+        };
+
+        let cases = [
+            (
+                30,
+                "invalid distance code",
+                InvalidValue::Code {
+                    bits: (30u32.reverse_bits() >> 27) as u16,
+                    length: 5,
+                },
+            ),
+            (
+                1,
+                "invalid distance too far back",
+                InvalidValue::Distance(2),
+            ),
+        ];
+
+        for (code, message, value) in cases {
+            // a stored block, and a fixed block with a literal and a match of length 3
+            let mut writer = BitWriter {
+                bytes: Vec::new(),
+                bits: 0,
+            };
+            writer.write(0b000, 3);
+            writer.align();
+            writer.write(0, 16);
+            writer.write(0xffff, 16);
+            writer.write(0b011, 3);
+            writer.write_code(lit_codes[b'a' as usize]);
+            writer.write_code(lit_codes[257]);
+            writer.write_code(dist_codes[code]);
+
+            // the error is after 5 bytes and 3 + 8 + 7 + 5 = 23 bits
+            let expected = Corruption {
+                message,
+                input_offset: 5 + 2,
+                input_bit: 7,
+                output_offset: 1,
+                block: Some(1),
+                value: Some(value),
+            };
+
+            // enough input for the fast path
+            let mut input = writer.bytes;
+            input.resize(100, 0);
+
+            for chunk in [1 << 16, 7] {
+                let (err, corruption) = decompress_corrupt(&input, raw, chunk);
+                assert_eq!(err, ReturnCode::DataError);
+                assert_eq!(corruption, Some(expected), "{chunk}");
+            }
+        }
+    }
+
+    #[test]
+    fn corruption_invalid_code() {
+        let raw = InflateConfig {
+            window_bits: -15,
+            ..Default::default()
+        };
+
+        let mut lit_lengths = [8u8; 288];
+        lit_lengths[144..256].fill(9);
+        lit_lengths[256..280].fill(7);
+
+        // the bits of a code, in the order of the stream
+        let bits = |(code, len): (u16, u8)| ((code as u32).reverse_bits() >> (32 - len)) as u16;
+
+        // a fixed block with length symbol 286, which is in the table but is not valid
+        let length = {
+            let lit_codes = canonical(&lit_lengths);
+
+            let mut writer = BitWriter {
+                bytes: Vec::new(),
+                bits: 0,
+            };
+            writer.write(0b011, 3);
+            writer.write_code(lit_codes[b'a' as usize]);
+            writer.write_code(lit_codes[286]);
+
+            let value = InvalidValue::Code {
+                bits: bits(lit_codes[286]),
+                length: 8,
+            };
+            (writer.bytes, "invalid literal/length code", value)
+        };
+
+        // a fixed block with distance symbol 31
+        let distance = {
+            let lit_codes = canonical(&lit_lengths);
+            let dist_codes = canonical(&[5u8; 32]);
+
+            let mut writer = BitWriter {
+                bytes: Vec::new(),
+                bits: 0,
+            };
+            writer.write(0b011, 3);
+            writer.write_code(lit_codes[b'a' as usize]);
+            writer.write_code(lit_codes[257]);
+            writer.write_code(dist_codes[31]);
+
+            let value = InvalidValue::Code {
+                bits: bits(dist_codes[31]),
+                length: 5,
+            };
+            (writer.bytes, "invalid distance code", value)
+        };
+
+        for (mut input, message, value) in [length, distance] {
+            // enough input for the fast path
+            input.resize(100, 0);
+
+            // the fast path, and the slow path
+            for chunk in [1 << 16, 7] {
+                let (err, corruption) = decompress_corrupt(&input, raw, chunk);
+                assert_eq!(err, ReturnCode::DataError);
+                let corruption = corruption.unwrap();
+                assert_eq!(corruption.message, message);
+                assert_eq!(corruption.value, Some(value), "{chunk}");
+            }
+
+            // the code may start in bits that were buffered before the fast path was entered
+            for split in 1..input.len() {
+                let mut stream = z_stream::default();
+                assert_eq!(init(&mut stream, raw), ReturnCode::Ok);
+                let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+                let mut output = vec![0; 1 << 16];
+                stream.next_out = output.as_mut_ptr();
+                stream.avail_out = output.len() as _;
+
+                let mut err = ReturnCode::Ok;
+                for part in [&input[..split], &input[split..]] {
+                    stream.next_in = part.as_ptr() as *mut u8;
+                    stream.avail_in = part.len() as _;
+                    err = unsafe { inflate(stream, InflateFlush::NoFlush) };
+                    if err != ReturnCode::Ok {
+                        break;
+                    }
+                }
+
+                assert_eq!(err, ReturnCode::DataError);
+                assert_eq!(stream.corruption().unwrap().value, Some(value), "{split}");
+
+                end(stream);
+            }
+        }
+    }
+
+    #[test]
+    fn corruption_in_wrapper() {
+        let expected = b"Hello, world!\n".repeat(100);
+
+        let mut buf = vec![0; 4096];
+        let (zlib, err) = crate::deflate::compress_slice(&mut buf, &expected, Default::default());
+        assert_eq!(err, ReturnCode::Ok);
+
+        for chunk in [4096, 7] {
+            let (err, corruption) = decompress_corrupt(zlib, Default::default(), chunk);
+            assert_eq!((err, corruption), (ReturnCode::StreamEnd, None));
+
+            // the header
+            let mut input = zlib.to_vec();
+            input[1] ^= 1;
+            let (err, corruption) = decompress_corrupt(&input, Default::default(), chunk);
+            assert_eq!(err, ReturnCode::DataError);
+            let corruption = corruption.unwrap();
+            assert_eq!(corruption.message, "incorrect header check");
+            assert_eq!((corruption.input_offset, corruption.input_bit), (0, 0));
+            assert_eq!((corruption.block, corruption.value), (None, None));
+
+            // the adler32 checksum
+            let mut input = zlib.to_vec();
+            *input.last_mut().unwrap() ^= 1;
+            let (err, corruption) = decompress_corrupt(&input, Default::default(), chunk);
+            assert_eq!(err, ReturnCode::DataError);
+            let actual = crate::adler32::adler32(1, &expected);
+            let corruption = corruption.unwrap();
+            assert_eq!(corruption.message, "incorrect data check");
+            assert_eq!(corruption.input_offset, input.len() as u64 - 4);
+            assert_eq!(corruption.output_offset, expected.len() as u64);
+            assert_eq!(corruption.block, Some(0));
+            assert_eq!(
+                corruption.value,
+                Some(InvalidValue::Checksum {
+                    expected: actual ^ 1,
+                    actual,
+                })
+            );
+
+            // the length in a gzip trailer
+            let config = crate::deflate::DeflateConfig {
+                window_bits: 31,
+                ..Default::default()
+            };
+            let mut buf = vec![0; 4096];
+            let (gzip, err) = crate::deflate::compress_slice(&mut buf, &expected, config);
+            assert_eq!(err, ReturnCode::Ok);
+
+            let mut input = gzip.to_vec();
+            let n = input.len();
+            input[n - 4] ^= 1;
+            let config = InflateConfig {
+                window_bits: 31,
+                ..Default::default()
+            };
+            let (err, corruption) = decompress_corrupt(&input, config, chunk);
+            assert_eq!(err, ReturnCode::DataError);
+            assert_eq!(
+                corruption.unwrap().value,
+                Some(InvalidValue::Length {
+                    expected: expected.len() as u32 ^ 1,
+                    actual: expected.len() as u32,
+                })
+            );
+
+            // a limit is not corruption
+            let config = InflateConfig {
+                max_output: Some(10),
+                ..Default::default()
+            };
+            let (err, corruption) = decompress_corrupt(zlib, config, chunk);
+            assert_eq!((err, corruption), (ReturnCode::DataError, None));
+        }
+    }
 }
@@ -0,0 +1,335 @@
+//! The block structure of a deflate stream.
+//!
+//! [`Blocks`] decodes a stream and yields a [`Block`] for every deflate block, with its position
+//! and the header of the block. The choices that an encoder makes for block boundaries and code
+//! lengths are characteristic, so this is useful to analyze which encoder produced a stream.
+//!
+//! This is built on [`InflateFlush::Block`] and [`InflateFlush::Trees`], which stop inflate at the
+//! start of a block and after its header.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{
+    c_api::z_stream,
+    inflate::{self, Codes, InflateConfig, InflateStream, Mode, CODE_LENS},
+    InflateFlush, ReturnCode,
+};
+
+/// A deflate block, see [`Blocks`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Block {
+    /// The offset in bits of the block header in the compressed data. Bits are counted from the
+    /// least significant bit of a byte, like deflate does.
+    pub input_bit_offset: u64,
+    /// The offset of the data of the block in the uncompressed data.
+    pub output_offset: u64,
+    /// The type of the block.
+    pub block_type: BlockType,
+    /// Whether this is the last block of the stream.
+    pub last: bool,
+}
+
+/// The type of a deflate block, with its header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BlockType {
+    /// A block of uncompressed data, with its length.
+    Stored { length: u16 },
+    /// A block that uses the fixed Huffman codes.
+    Fixed,
+    /// A block that defines its own Huffman codes.
+    Dynamic(DynamicHeader),
+}
+
+/// The header of a dynamic block.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DynamicHeader {
+    /// The number of literal/length codes, minus 257.
+    pub hlit: u8,
+    /// The number of distance codes, minus 1.
+    pub hdist: u8,
+    /// The number of code length codes, minus 4.
+    pub hclen: u8,
+    /// The lengths of the code length code, by symbol (not in the order of the header).
+    pub code_length_lengths: [u8; CODE_LENS],
+    /// The lengths of the literal/length code, `hlit + 257` of them.
+    pub literal_lengths: Vec<u8>,
+    /// The lengths of the distance code, `hdist + 1` of them.
+    pub distance_lengths: Vec<u8>,
+}
+
+/// An iterator over the blocks of a deflate, zlib or gzip stream, see the
+/// [module documentation](self).
+///
+/// Only the first stream is decoded, e.g. of a gzip file with several members. When the data is
+/// invalid or incomplete, the iterator yields an error and then stops.
+pub struct Blocks<'a> {
+    stream: z_stream,
+    input: &'a [u8],
+    /// The uncompressed data is decoded into this buffer, and then discarded.
+    output: Vec<u8>,
+    total_in: usize,
+    total_out: u64,
+    /// The offsets of the block that is started but whose header has not been decoded yet.
+    start: Option<(u64, u64)>,
+    done: bool,
+}
+
+impl<'a> Blocks<'a> {
+    /// Iterate over the blocks of `input`, which is decoded with `config`.
+    pub fn new(input: &'a [u8], config: InflateConfig) -> Result<Self, ReturnCode> {
+        let mut stream = z_stream::default();
+        let err = inflate::init(&mut stream, config);
+        if err != ReturnCode::Ok {
+            return Err(err);
+        }
+
+        // inflate does not stop before the first block of a raw stream
+        let start = match config.window_bits < 0 {
+            true => Some((0, 0)),
+            false => None,
+        };
+
+        Ok(Self {
+            stream,
+            input,
+            output: vec![0; 1 << 16],
+            total_in: 0,
+            total_out: 0,
+            start,
+            done: false,
+        })
+    }
+
+    fn next_help(&mut self) -> Result<Option<Block>, ReturnCode> {
+        // SAFETY: the stream was successfully initialized
+        let stream = unsafe { InflateStream::from_stream_mut(&mut self.stream) }.unwrap();
+
+        loop {
+            let avail_in = Ord::min(self.input.len() - self.total_in, u32::MAX as usize);
+
+            stream.next_in = self.input[self.total_in..].as_ptr() as *mut u8;
+            stream.avail_in = avail_in as _;
+            stream.next_out = self.output.as_mut_ptr();
+            stream.avail_out = self.output.len() as _;
+
+            let mode = stream.state.mode;
+
+            // stop at the start of every block, and after every block header
+            let err = unsafe { inflate::inflate(stream, InflateFlush::Trees) };
+
+            self.total_in += avail_in - stream.avail_in as usize;
+            self.total_out += (self.output.len() - stream.avail_out as usize) as u64;
+
+            match err {
+                ReturnCode::Ok => {}
+                ReturnCode::StreamEnd => return Ok(None),
+                // a block header may be decoded from the bits that are already buffered, which
+                // does not count as progress
+                ReturnCode::BufError if stream.state.mode as u8 != mode as u8 => {}
+                // there is no more input, so the data is incomplete
+                ReturnCode::BufError => return Err(ReturnCode::DataError),
+                err => return Err(err),
+            }
+
+            let state = &stream.state;
+
+            if let Mode::Type = state.mode {
+                let bits = state.bit_reader.bits_in_buffer() as u64;
+                self.start = Some((self.total_in as u64 * 8 - bits, self.total_out));
+                continue;
+            }
+
+            let block_type = match state.mode {
+                Mode::CopyBlock => BlockType::Stored {
+                    length: state.length as u16,
+                },
+                Mode::Len_ => match state.len_table.codes {
+                    Codes::Fixed | Codes::Fixed64 => BlockType::Fixed,
+                    _ => {
+                        let lens = state.lens.map(|len| len as u8);
+
+                        BlockType::Dynamic(DynamicHeader {
+                            hlit: (state.nlen - 257) as u8,
+                            hdist: (state.ndist - 1) as u8,
+                            hclen: (state.ncode - 4) as u8,
+                            code_length_lengths: state.code_length_lengths,
+                            literal_lengths: lens[..state.nlen].to_vec(),
+                            distance_lengths: lens[state.nlen..][..state.ndist].to_vec(),
+                        })
+                    }
+                },
+                // inflate also stops in the middle of a stored block when the output is full
+                _ => continue,
+            };
+
+            // also in the middle of a stored block
+            let Some((input_bit_offset, output_offset)) = self.start.take() else {
+                continue;
+            };
+
+            return Ok(Some(Block {
+                input_bit_offset,
+                output_offset,
+                block_type,
+                last: state.flags.contains(inflate::Flags::IS_LAST_BLOCK),
+            }));
+        }
+    }
+}
+
+impl Iterator for Blocks<'_> {
+    type Item = Result<Block, ReturnCode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.next_help();
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+
+        result.transpose()
+    }
+}
+
+impl Drop for Blocks<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the stream was successfully initialized
+        if let Some(stream) = unsafe { InflateStream::from_stream_mut(&mut self.stream) } {
+            inflate::end(stream);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::deflate::{self, DeflateConfig, Strategy};
+    use crate::inflate::index::Index;
+
+    const INPUT: &[u8] = include_bytes!("../deflate/test-data/paper-100k.pdf");
+
+    fn compress(config: DeflateConfig) -> Vec<u8> {
+        let mut output = vec![0; deflate::compress_bound(INPUT.len())];
+        let (output, err) = deflate::compress_slice(&mut output, INPUT, config);
+        assert_eq!(err, ReturnCode::Ok);
+        output.to_vec()
+    }
+
+    fn collect(input: &[u8], window_bits: i32) -> Vec<Block> {
+        let config = InflateConfig {
+            window_bits,
+            ..Default::default()
+        };
+
+        let blocks = Blocks::new(input, config).unwrap();
+        blocks.collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn dynamic() {
+        for (window_bits, header_bits) in [(15, 16), (31, 80), (-15, 0)] {
+            let compressed = compress(DeflateConfig {
+                window_bits,
+                ..DeflateConfig::new(6)
+            });
+
+            let blocks = collect(&compressed, window_bits);
+            assert!(blocks.len() > 3);
+            assert_eq!(blocks[0].input_bit_offset, header_bits);
+            assert_eq!(blocks[0].output_offset, 0);
+
+            let (last, rest) = blocks.split_last().unwrap();
+            assert!(last.last);
+            assert!(rest.iter().all(|block| !block.last));
+
+            for pair in blocks.windows(2) {
+                assert!(pair[0].input_bit_offset < pair[1].input_bit_offset);
+                assert!(pair[0].output_offset <= pair[1].output_offset);
+            }
+
+            for block in &blocks {
+                let BlockType::Dynamic(header) = &block.block_type else {
+                    panic!("{block:?}");
+                };
+
+                assert_eq!(header.literal_lengths.len(), header.hlit as usize + 257);
+                assert_eq!(header.distance_lengths.len(), header.hdist as usize + 1);
+
+                // the end-of-block symbol is always used
+                assert_ne!(header.literal_lengths[256], 0);
+
+                // the code length code is complete
+                let kraft: u32 = (header.code_length_lengths.iter())
+                    .filter(|&&len| len != 0)
+                    .map(|&len| 1 << (7 - len))
+                    .sum();
+                assert_eq!(kraft, 1 << 7);
+            }
+
+            // the same block boundaries as the access points of an index
+            let index = Index::build(&compressed, window_bits, 0).unwrap();
+            for point in &index.points {
+                let offset = point.input_offset * 8 - point.bits as u64;
+                assert!(blocks.iter().any(|block| block.input_bit_offset == offset
+                    && block.output_offset == point.output_offset));
+            }
+        }
+    }
+
+    #[test]
+    fn stored_and_fixed() {
+        let compressed = compress(DeflateConfig::new(0));
+        let blocks = collect(&compressed, 15);
+
+        let mut output_offset = 0;
+        for block in &blocks {
+            let BlockType::Stored { length } = block.block_type else {
+                panic!("{block:?}");
+            };
+
+            assert_eq!(block.output_offset, output_offset);
+            output_offset += length as u64;
+        }
+        assert_eq!(output_offset, INPUT.len() as u64);
+
+        let compressed = compress(DeflateConfig {
+            strategy: Strategy::Fixed,
+            ..DeflateConfig::new(6)
+        });
+        let blocks = collect(&compressed, 15);
+        // stored blocks are still used for data that does not compress
+        assert!(blocks
+            .iter()
+            .any(|block| block.block_type == BlockType::Fixed));
+        assert!(blocks
+            .iter()
+            .all(|block| !matches!(block.block_type, BlockType::Dynamic(_))));
+        assert!(blocks.last().unwrap().last);
+    }
+
+    #[test]
+    fn invalid() {
+        let compressed = compress(DeflateConfig::new(6));
+
+        let config = InflateConfig::default();
+        let mut blocks = Blocks::new(&compressed[..compressed.len() / 2], config).unwrap();
+        assert!(matches!(blocks.next(), Some(Ok(_))));
+        assert_eq!(
+            blocks.find(Result::is_err),
+            Some(Err(ReturnCode::DataError))
+        );
+        assert_eq!(blocks.next(), None);
+
+        let config = InflateConfig {
+            window_bits: 14,
+            deflate64: true,
+            ..Default::default()
+        };
+        assert!(Blocks::new(&compressed, config).is_err());
+    }
+}
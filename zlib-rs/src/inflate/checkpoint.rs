@@ -0,0 +1,867 @@
+//! Suspend and resume decompression.
+//!
+//! [`save`] serializes the state of a paused inflate stream into a versioned byte blob, and
+//! [`restore`] loads it into another stream, possibly in another process. The restored stream
+//! continues where the original stopped, with the input and output that the original would have
+//! received next, and produces identical output.
+//!
+//! The blob contains the decoder state, the bits that have been read but not used yet, the code
+//! lengths from which the dynamic Huffman tables are rebuilt, the running checksum and the window.
+//! The window is at most 32K (64K for Deflate64), so that is also the size of a checkpoint.
+//!
+//! A gzip header that is being filled in (see [`get_header`]) is not part of the checkpoint.
+//!
+//! [`get_header`]: crate::inflate::get_header
+
+use alloc::vec::Vec;
+
+use crate::{c_api::Z_DEFLATED, crc32::Crc32Fold, ReturnCode, MAX_WBITS, MIN_WBITS};
+
+use super::{
+    bitreader::BitReader, inflate_table, window::Window, CodeType, Codes, Flags, InflateStream,
+    InflateTable, Mode, Table, CODE_LENS, MAX_MATCH_64,
+};
+
+/// Identifies the serialized format.
+const MAGIC: [u8; 4] = *b"zckp";
+const VERSION: u8 = 3;
+
+/// All modes, in the order of their discriminants.
+const MODES: [Mode; 31] = [
+    Mode::Head,
+    Mode::Flags,
+    Mode::Time,
+    Mode::Os,
+    Mode::ExLen,
+    Mode::Extra,
+    Mode::Name,
+    Mode::Comment,
+    Mode::HCrc,
+    Mode::Sync,
+    Mode::Mem,
+    Mode::Length,
+    Mode::Type,
+    Mode::TypeDo,
+    Mode::Stored,
+    Mode::CopyBlock,
+    Mode::Check,
+    Mode::Len_,
+    Mode::Len,
+    Mode::Lit,
+    Mode::LenExt,
+    Mode::Dist,
+    Mode::DistExt,
+    Mode::Match,
+    Mode::Table,
+    Mode::LenLens,
+    Mode::CodeLens,
+    Mode::DictId,
+    Mode::Dict,
+    Mode::Done,
+    Mode::Bad,
+];
+
+/// Serialize the state of `stream`, which can be resumed with [`restore`].
+pub fn save(stream: &InflateStream) -> Vec<u8> {
+    let state = &stream.state;
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(VERSION);
+
+    bytes.push(state.mode as u8);
+    bytes.push(state.flags.0);
+    bytes.push(state.wbits);
+    bytes.push(state.wrap);
+
+    bytes.extend_from_slice(&state.gzip_flags.to_le_bytes());
+    bytes.extend_from_slice(&state.checksum.to_le_bytes());
+    bytes.extend_from_slice(&state.crc_fold.finish().to_le_bytes());
+
+    bytes.extend_from_slice(&stream.total_in.to_le_bytes());
+    bytes.extend_from_slice(&(state.total as u64).to_le_bytes());
+
+    for field in [
+        state.ncode,
+        state.nlen,
+        state.ndist,
+        state.have,
+        state.next,
+        state.length,
+        state.offset,
+        state.extra,
+        state.was,
+        state.dmax,
+        state.header_field,
+        state.blocks,
+    ] {
+        bytes.extend_from_slice(&(field as u32).to_le_bytes());
+    }
+
+    // `back` is -1 when it is not tracked
+    bytes.extend_from_slice(&(state.back as i64).to_le_bytes());
+
+    let bits = state.bit_reader.bits_in_buffer();
+    bytes.push(bits);
+    bytes.extend_from_slice(&(state.bit_reader.hold() & low_bits(bits)).to_le_bytes());
+
+    for table in [state.len_table, state.dist_table] {
+        bytes.push(table.codes as u8);
+        bytes.push(table.bits as u8);
+    }
+
+    bytes.extend(state.lens.iter().map(|&len| len as u8));
+    bytes.extend_from_slice(&state.code_length_lengths);
+
+    // the history: the part of a prepared dictionary that can still be referenced, followed by
+    // the window in order
+    let window = state.window.as_slice();
+    let (older, newer) = window.split_at(state.window.next());
+    let history = [state.dictionary(), newer, older];
+    let len: usize = history.iter().map(|part| part.len()).sum();
+    bytes.extend_from_slice(&(len as u32).to_le_bytes());
+    for part in history {
+        bytes.extend_from_slice(part);
+    }
+
+    bytes
+}
+
+/// Restore a checkpoint that was created with [`save`] into `stream`, which must be an initialized
+/// inflate stream. Its configuration is replaced by that of the checkpoint. Returns
+/// [`ReturnCode::DataError`] when the bytes are not a valid checkpoint.
+pub fn restore(stream: &mut InflateStream, bytes: &[u8]) -> ReturnCode {
+    match Checkpoint::parse(bytes) {
+        Ok(checkpoint) => checkpoint.apply(stream),
+        Err(err) => err,
+    }
+}
+
+struct Checkpoint<'a> {
+    mode: Mode,
+    flags: Flags,
+    wbits: u8,
+    wrap: u8,
+    gzip_flags: i32,
+    checksum: u32,
+    crc: u32,
+    total_in: u64,
+    total: u64,
+    fields: [usize; 12],
+    back: usize,
+    bits: u8,
+    hold: u64,
+    len_table: Table,
+    dist_table: Table,
+    lens: &'a [u8],
+    code_lens: &'a [u8],
+    history: &'a [u8],
+}
+
+impl<'a> Checkpoint<'a> {
+    fn parse(mut bytes: &'a [u8]) -> Result<Self, ReturnCode> {
+        fn take<'a, const N: usize>(bytes: &mut &'a [u8]) -> Result<&'a [u8; N], ReturnCode> {
+            take_slice(bytes, N).map(|slice| slice.try_into().unwrap())
+        }
+
+        fn take_slice<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], ReturnCode> {
+            if bytes.len() < n {
+                return Err(ReturnCode::DataError);
+            }
+
+            let (head, tail) = bytes.split_at(n);
+            *bytes = tail;
+            Ok(head)
+        }
+
+        fn take_table(bytes: &mut &[u8]) -> Result<Table, ReturnCode> {
+            let &[codes, bits] = take(bytes)?;
+
+            let codes = match codes {
+                0 => Codes::Fixed,
+                1 => Codes::Fixed64,
+                2 => Codes::Codes,
+                3 => Codes::Len,
+                4 => Codes::Dist,
+                _ => return Err(ReturnCode::DataError),
+            };
+
+            Ok(Table {
+                codes,
+                bits: bits as usize,
+            })
+        }
+
+        if *take(&mut bytes)? != MAGIC || *take(&mut bytes)? != [VERSION] {
+            return Err(ReturnCode::DataError);
+        }
+
+        let &[mode, flags, wbits, wrap] = take(&mut bytes)?;
+        let Some(&mode) = MODES.get(mode as usize) else {
+            return Err(ReturnCode::DataError);
+        };
+
+        let gzip_flags = i32::from_le_bytes(*take(&mut bytes)?);
+        let checksum = u32::from_le_bytes(*take(&mut bytes)?);
+        let crc = u32::from_le_bytes(*take(&mut bytes)?);
+        let total_in = u64::from_le_bytes(*take(&mut bytes)?);
+        let total = u64::from_le_bytes(*take(&mut bytes)?);
+
+        let mut fields = [0; 12];
+        for field in &mut fields {
+            *field = u32::from_le_bytes(*take(&mut bytes)?) as usize;
+        }
+
+        let back = i64::from_le_bytes(*take(&mut bytes)?) as usize;
+
+        let &[bits] = take(&mut bytes)?;
+        let hold = u64::from_le_bytes(*take(&mut bytes)?);
+
+        let len_table = take_table(&mut bytes)?;
+        let dist_table = take_table(&mut bytes)?;
+
+        let lens = take_slice(&mut bytes, 320)?;
+        let code_lens = take_slice(&mut bytes, CODE_LENS)?;
+
+        let len = u32::from_le_bytes(*take(&mut bytes)?);
+        let history = take_slice(&mut bytes, len as usize)?;
+
+        let checkpoint = Self {
+            mode,
+            flags: Flags(flags),
+            wbits,
+            wrap,
+            gzip_flags,
+            checksum,
+            crc,
+            total_in,
+            total,
+            fields,
+            back,
+            bits,
+            hold,
+            len_table,
+            dist_table,
+            lens,
+            code_lens,
+            history,
+        };
+
+        match bytes.is_empty() && checkpoint.is_valid() {
+            true => Ok(checkpoint),
+            false => Err(ReturnCode::DataError),
+        }
+    }
+
+    /// Check every field against the mode, so that the restored stream only reaches states that
+    /// inflate itself can reach: indices stay in bounds, and the tables are the ones that the mode
+    /// decodes with.
+    fn is_valid(&self) -> bool {
+        let [ncode, nlen, ndist, have, next, length, offset, extra, was, dmax, header_field, _] =
+            self.fields;
+
+        let deflate64 = self.flags.contains(Flags::DEFLATE64);
+        let (max_match, max_ndist) = match deflate64 {
+            true => (MAX_MATCH_64, 32),
+            false => (258, 30),
+        };
+
+        // `SINGLE_SHOT` is only set during `uncompress`, which does not hand out its stream
+        let known = Flags::IS_LAST_BLOCK.0
+            | Flags::HAVE_DICT.0
+            | Flags::SANE.0
+            | Flags::DEFLATE64.0
+            | Flags::STRICT.0;
+
+        // the window size is not known before the zlib or gzip header is read
+        let window = match (deflate64, self.wbits) {
+            (true, wbits) => wbits as i32 == MAX_WBITS + 1 && self.wrap == 0,
+            (false, 0) => matches!(self.mode, Mode::Head) && self.history.is_empty(),
+            (false, wbits) => (MIN_WBITS..=MAX_WBITS).contains(&(wbits as i32)),
+        };
+        let max_dist = 1 << self.wbits;
+
+        let gzip = (0..=0x1fff).contains(&self.gzip_flags) && self.gzip_flags & 0xff == Z_DEFLATED;
+
+        let valid = self.flags.0 & !known == 0
+            && window
+            && self.wrap < 8
+            && self.history.len() <= max_dist
+            && (self.gzip_flags == -1 || self.gzip_flags == 0 || gzip)
+            && self.bits < 64
+            && self.hold & !low_bits(self.bits) == 0
+            && (self.back == usize::MAX || self.back < 64)
+            && ncode <= CODE_LENS
+            && nlen <= 288
+            && ndist <= 32
+            && have <= self.lens.len()
+            && next == 0
+            && extra <= 16
+            && was <= max_match
+            && dmax.is_power_of_two()
+            && (1 << MIN_WBITS..=1 << 16).contains(&dmax)
+            && self.lens.iter().all(|&len| len <= 15)
+            && self.code_lens.iter().all(|&len| len <= 7)
+            && self.len_table.bits <= 10
+            && self.dist_table.bits <= 9;
+
+        if !valid {
+            return false;
+        }
+
+        // the code lengths of a dynamic block
+        let dynamic = (257..=286).contains(&nlen) && (1..=max_ndist).contains(&ndist);
+
+        // the tables that a block is decoded with
+        let tables = match (self.len_table.codes, self.dist_table.codes) {
+            (Codes::Fixed, Codes::Fixed) => !deflate64,
+            (Codes::Fixed64, Codes::Fixed64) => deflate64,
+            (Codes::Len, Codes::Dist) => dynamic,
+            _ => false,
+        };
+        let fixed = matches!(self.len_table.codes, Codes::Fixed | Codes::Fixed64);
+        let tables = tables && (!fixed || (self.len_table.bits == 9 && self.dist_table.bits == 5));
+
+        match self.mode {
+            Mode::Head => self.gzip_flags == -1,
+            Mode::Flags => self.gzip_flags == -1 && self.wrap & 2 != 0,
+            Mode::Time | Mode::Os | Mode::ExLen | Mode::HCrc => gzip && self.wrap & 2 != 0,
+            Mode::Extra => gzip && self.wrap & 2 != 0 && length <= u16::MAX as usize,
+            // `length` is the part of the field that was copied into the gzip header
+            Mode::Name | Mode::Comment => gzip && self.wrap & 2 != 0 && length <= header_field,
+            Mode::DictId | Mode::Dict => self.gzip_flags == 0,
+            Mode::Sync => have <= 4,
+            Mode::Type | Mode::TypeDo | Mode::Stored | Mode::Check | Mode::Length => true,
+            Mode::CopyBlock => length <= u16::MAX as usize,
+            Mode::Table => true,
+            Mode::LenLens => dynamic && (4..=CODE_LENS).contains(&ncode) && have <= ncode,
+            Mode::CodeLens => {
+                dynamic
+                    && matches!(self.len_table.codes, Codes::Codes)
+                    && self.len_table.bits <= 7
+                    && have <= nlen + ndist
+            }
+            Mode::Len_ | Mode::Len => tables,
+            Mode::Lit => tables && length < 256,
+            Mode::LenExt => tables && (3..=max_match).contains(&length),
+            Mode::Dist => tables && (3..=max_match).contains(&length) && was == length,
+            Mode::DistExt => {
+                tables
+                    && (3..=max_match).contains(&length)
+                    && was == length
+                    && (1..=max_dist).contains(&offset)
+            }
+            Mode::Match => {
+                tables && (1..=was).contains(&length) && (1..=max_dist).contains(&offset)
+            }
+            Mode::Mem | Mode::Bad => true,
+            // inflate never enters this mode
+            Mode::Done => false,
+        }
+    }
+
+    fn apply(self, stream: &mut InflateStream) -> ReturnCode {
+        // the window is allocated for the size of the checkpoint below
+        if stream.state.window.size() != 0 && stream.state.wbits != self.wbits {
+            let mut window = Window::empty();
+            core::mem::swap(&mut window, &mut stream.state.window);
+
+            let (ptr, len) = window.into_raw_parts();
+            assert_ne!(len, 0);
+            // SAFETY: window is discarded after this deallocation.
+            unsafe { stream.alloc.deallocate(ptr, len) };
+        }
+
+        let err = super::reset(stream);
+        if err != ReturnCode::Ok {
+            return err;
+        }
+
+        let state = &mut *stream.state;
+
+        state.mode = self.mode;
+        state.flags = self.flags;
+        state.wbits = self.wbits;
+        state.wrap = self.wrap;
+        state.gzip_flags = self.gzip_flags;
+        state.checksum = self.checksum;
+        state.crc_fold = Crc32Fold::resume(self.crc);
+
+        [
+            state.ncode,
+            state.nlen,
+            state.ndist,
+            state.have,
+            state.next,
+            state.length,
+            state.offset,
+            state.extra,
+            state.was,
+            state.dmax,
+            state.header_field,
+            state.blocks,
+        ] = self.fields;
+        state.back = self.back;
+
+        state.bit_reader = BitReader::new(&[]);
+        state.bit_reader.prime(Ord::min(self.bits, 32), self.hold);
+        if self.bits > 32 {
+            state.bit_reader.prime(self.bits - 32, self.hold >> 32);
+        }
+
+        for (len, &value) in state.lens.iter_mut().zip(self.lens) {
+            *len = value as u16;
+        }
+        state.code_length_lengths.copy_from_slice(self.code_lens);
+
+        // rebuild the tables that are used in the current mode
+        let deflate64 = state.flags.contains(Flags::DEFLATE64);
+        let decoding = matches!(
+            state.mode,
+            Mode::Len_
+                | Mode::Len
+                | Mode::Lit
+                | Mode::LenExt
+                | Mode::Dist
+                | Mode::DistExt
+                | Mode::Match
+        );
+
+        let lens = state.code_length_lengths.map(u16::from);
+
+        let tables = [
+            (
+                self.len_table,
+                matches!(self.len_table.codes, Codes::Codes)
+                    && matches!(state.mode, Mode::CodeLens),
+                CodeType::Codes,
+                7,
+                &lens[..],
+                &mut state.codes_codes[..],
+            ),
+            (
+                self.len_table,
+                matches!(self.len_table.codes, Codes::Len) && decoding,
+                match deflate64 {
+                    true => CodeType::Lens64,
+                    false => CodeType::Lens,
+                },
+                10,
+                &state.lens[..state.nlen],
+                &mut state.len_codes[..],
+            ),
+            (
+                self.dist_table,
+                matches!(self.dist_table.codes, Codes::Dist) && decoding,
+                match deflate64 {
+                    true => CodeType::Dists64,
+                    false => CodeType::Dists,
+                },
+                9,
+                &state.lens[state.nlen..][..state.ndist],
+                &mut state.dist_codes[..],
+            ),
+        ];
+
+        for (table, used, code_type, root, lens, codes) in tables {
+            if !used {
+                continue;
+            }
+
+            let InflateTable::Success(root) =
+                inflate_table(code_type, lens, lens.len(), codes, root, &mut state.work)
+            else {
+                return ReturnCode::DataError;
+            };
+
+            if root != table.bits {
+                return ReturnCode::DataError;
+            }
+        }
+
+        state.len_table = self.len_table;
+        state.dist_table = self.dist_table;
+
+        if !self.history.is_empty() {
+            // reuse the window when it has the right size, `reset` cleared it
+            if state.window.size() == 0 {
+                match Window::new_in(&stream.alloc, state.wbits as usize) {
+                    Some(window) => state.window = window,
+                    None => return ReturnCode::MemError,
+                }
+            }
+
+            state.window.extend(
+                self.history,
+                state.gzip_flags,
+                false,
+                &mut state.checksum,
+                &mut state.crc_fold,
+            );
+        }
+
+        state.total = self.total as usize;
+        stream.total_in = self.total_in as _;
+        stream.total_out = self.total as _;
+        stream.adler = state.checksum as _;
+
+        ReturnCode::Ok
+    }
+}
+
+fn low_bits(bits: u8) -> u64 {
+    match bits {
+        64.. => u64::MAX,
+        _ => (1 << bits) - 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        c_api::z_stream,
+        deflate::{self, DeflateConfig},
+        inflate::{self, InflateConfig},
+        InflateFlush,
+    };
+
+    const INPUT: &[u8] = include_bytes!("../deflate/test-data/paper-100k.pdf");
+
+    fn compress(config: DeflateConfig) -> Vec<u8> {
+        let mut output = vec![0; deflate::compress_bound(INPUT.len())];
+        let (output, err) = deflate::compress_slice(&mut output, INPUT, config);
+        assert_eq!(err, ReturnCode::Ok);
+        output.to_vec()
+    }
+
+    fn with_stream<T>(config: InflateConfig, f: impl FnOnce(&mut InflateStream) -> T) -> T {
+        let mut stream = z_stream::default();
+        assert_eq!(inflate::init(&mut stream, config), ReturnCode::Ok);
+
+        // SAFETY: the stream was successfully initialized
+        let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let result = f(stream);
+
+        inflate::end(stream);
+
+        result
+    }
+
+    /// Decompress in small steps, and continue every step in a new stream restored from a
+    /// checkpoint of the previous one.
+    fn decompress_with_checkpoints(compressed: &[u8], config: InflateConfig) -> Vec<u8> {
+        let mut output = vec![0; INPUT.len() + 100];
+        let mut checkpoint = with_stream(config, |stream| save(stream));
+
+        let (mut total_in, mut total_out) = (0, 0);
+        loop {
+            let (err, checkpoint_) = with_stream(InflateConfig::default(), |stream| {
+                assert_eq!(restore(stream, &checkpoint), ReturnCode::Ok);
+                assert_eq!(stream.total_in as usize, total_in);
+                assert_eq!(stream.total_out as usize, total_out);
+
+                let input = &compressed[total_in..];
+                let input = &input[..Ord::min(input.len(), 1013)];
+                let end = Ord::min(output.len(), total_out + 777);
+                let out = &mut output[total_out..end];
+
+                stream.next_in = input.as_ptr() as *mut u8;
+                stream.avail_in = input.len() as _;
+                stream.next_out = out.as_mut_ptr();
+                stream.avail_out = out.len() as _;
+
+                let err = unsafe { inflate::inflate(stream, InflateFlush::NoFlush) };
+
+                total_in = stream.total_in as usize;
+                total_out = stream.total_out as usize;
+
+                (err, save(stream))
+            });
+
+            checkpoint = checkpoint_;
+
+            match err {
+                ReturnCode::Ok => {}
+                ReturnCode::StreamEnd => break,
+                err => panic!("{err:?}"),
+            }
+        }
+
+        assert_eq!(total_in, compressed.len());
+        output.truncate(total_out);
+        output
+    }
+
+    #[test]
+    fn round_trip() {
+        for (deflate_config, inflate_config) in [
+            (DeflateConfig::new(6), InflateConfig::default()),
+            (
+                DeflateConfig {
+                    window_bits: 31,
+                    ..DeflateConfig::new(9)
+                },
+                InflateConfig {
+                    window_bits: 47,
+                    ..Default::default()
+                },
+            ),
+            (
+                DeflateConfig {
+                    window_bits: -10,
+                    ..DeflateConfig::new(1)
+                },
+                InflateConfig {
+                    window_bits: -10,
+                    ..Default::default()
+                },
+            ),
+            (
+                DeflateConfig {
+                    window_bits: -15,
+                    deflate64: true,
+                    ..DeflateConfig::new(6)
+                },
+                InflateConfig {
+                    window_bits: -15,
+                    deflate64: true,
+                    ..Default::default()
+                },
+            ),
+        ] {
+            let compressed = compress(deflate_config);
+            let output = decompress_with_checkpoints(&compressed, inflate_config);
+            assert!(output == INPUT);
+        }
+    }
+
+    /// A checkpoint of a stream that is decoding a dynamic block.
+    fn partial_checkpoint() -> Vec<u8> {
+        let compressed = compress(DeflateConfig::new(6));
+
+        with_stream(InflateConfig::default(), |stream| {
+            let mut output = vec![0; 1000];
+            stream.next_in = compressed.as_ptr() as *mut u8;
+            stream.avail_in = 500;
+            stream.next_out = output.as_mut_ptr();
+            stream.avail_out = output.len() as _;
+
+            let err = unsafe { inflate::inflate(stream, InflateFlush::NoFlush) };
+            assert_eq!(err, ReturnCode::Ok);
+
+            save(stream)
+        })
+    }
+
+    #[test]
+    fn corrupted() {
+        let checkpoint = partial_checkpoint();
+
+        with_stream(InflateConfig::default(), |stream| {
+            assert_eq!(restore(stream, &checkpoint), ReturnCode::Ok);
+
+            // restoring again reuses the window
+            let window = stream.state.window.as_ptr();
+            assert_eq!(restore(stream, &checkpoint), ReturnCode::Ok);
+            assert_eq!(stream.state.window.as_ptr(), window);
+
+            assert_eq!(
+                restore(stream, &checkpoint[..checkpoint.len() - 1]),
+                ReturnCode::DataError
+            );
+
+            let mut bytes = checkpoint.clone();
+            bytes[4] = VERSION + 1;
+            assert_eq!(restore(stream, &bytes), ReturnCode::DataError);
+
+            let mut bytes = checkpoint.clone();
+            bytes[5] = MODES.len() as u8;
+            assert_eq!(restore(stream, &bytes), ReturnCode::DataError);
+        });
+    }
+
+    #[test]
+    fn invalid_fields() {
+        // offsets into the serialized checkpoint
+        const FLAGS: usize = 6;
+        const WBITS: usize = 7;
+        const WRAP: usize = 8;
+        const GZIP_FLAGS: usize = 9;
+        const BACK: usize = 85;
+        const BITS: usize = 93;
+        const HOLD: usize = 94;
+        const LEN_TABLE: usize = 102;
+        const DIST_TABLE: usize = 104;
+        const LENS: usize = 106;
+        const CODE_LENS_AT: usize = LENS + 320;
+
+        // indices into the fields
+        const NCODE: usize = 0;
+        const NLEN: usize = 1;
+        const NDIST: usize = 2;
+        const HAVE: usize = 3;
+        const NEXT: usize = 4;
+        const LENGTH: usize = 5;
+        const OFFSET: usize = 6;
+        const EXTRA: usize = 7;
+        const WAS: usize = 8;
+        const DMAX: usize = 9;
+
+        fn set(bytes: &mut [u8], at: usize, value: &[u8]) {
+            bytes[at..][..value.len()].copy_from_slice(value);
+        }
+
+        fn field(bytes: &mut [u8], index: usize, value: u32) {
+            set(bytes, 37 + 4 * index, &value.to_le_bytes());
+        }
+
+        fn mode(bytes: &mut [u8], mode: Mode) {
+            bytes[5] = mode as u8;
+        }
+
+        /// A match of length 5 that started with length 10, 100 bytes back.
+        fn in_match(bytes: &mut [u8]) {
+            mode(bytes, Mode::Match);
+            field(bytes, LENGTH, 5);
+            field(bytes, WAS, 10);
+            field(bytes, OFFSET, 100);
+        }
+
+        let checkpoint = partial_checkpoint();
+        assert_eq!(checkpoint[LEN_TABLE], Codes::Len as u8);
+        assert_eq!(checkpoint[DIST_TABLE], Codes::Dist as u8);
+
+        let restore_with = |mutate: fn(&mut [u8])| {
+            let mut bytes = checkpoint.clone();
+            mutate(&mut bytes);
+            with_stream(InflateConfig::default(), |stream| restore(stream, &bytes))
+        };
+
+        let valid: [fn(&mut [u8]); 4] = [
+            |_| {},
+            in_match,
+            |bytes| {
+                mode(bytes, Mode::Lit);
+                field(bytes, LENGTH, 255);
+            },
+            |bytes| {
+                mode(bytes, Mode::DistExt);
+                field(bytes, LENGTH, 258);
+                field(bytes, WAS, 258);
+                field(bytes, OFFSET, 1 << 15);
+            },
+        ];
+
+        for mutate in valid {
+            assert_eq!(restore_with(mutate), ReturnCode::Ok);
+        }
+
+        let invalid: &[fn(&mut [u8])] = &[
+            // fields that are checked in every mode
+            |bytes| bytes[FLAGS] |= Flags::SINGLE_SHOT.0,
+            |bytes| bytes[FLAGS] |= 0x80,
+            |bytes| bytes[FLAGS] |= Flags::DEFLATE64.0,
+            |bytes| bytes[WBITS] = 7,
+            |bytes| bytes[WBITS] = 16,
+            |bytes| bytes[WBITS] = 0,
+            |bytes| bytes[WRAP] = 8,
+            |bytes| set(bytes, GZIP_FLAGS, &0x0109i32.to_le_bytes()),
+            |bytes| set(bytes, GZIP_FLAGS, &0x2008i32.to_le_bytes()),
+            |bytes| bytes[BITS] = 64,
+            |bytes| {
+                bytes[BITS] = 3;
+                set(bytes, HOLD, &0xffu64.to_le_bytes());
+            },
+            |bytes| set(bytes, BACK, &64i64.to_le_bytes()),
+            |bytes| field(bytes, NCODE, 20),
+            |bytes| field(bytes, NLEN, 289),
+            |bytes| field(bytes, NDIST, 33),
+            |bytes| field(bytes, HAVE, 321),
+            |bytes| field(bytes, NEXT, 1),
+            |bytes| field(bytes, EXTRA, 17),
+            |bytes| field(bytes, WAS, 259),
+            |bytes| field(bytes, DMAX, 1000),
+            |bytes| field(bytes, DMAX, 1 << 7),
+            |bytes| field(bytes, DMAX, 1 << 17),
+            |bytes| bytes[LENS] = 16,
+            |bytes| bytes[CODE_LENS_AT] = 8,
+            |bytes| bytes[LEN_TABLE + 1] = 11,
+            |bytes| bytes[DIST_TABLE + 1] = 10,
+            // the tables must be the ones that the mode decodes with
+            |bytes| bytes[LEN_TABLE] = Codes::Codes as u8,
+            |bytes| bytes[LEN_TABLE] = Codes::Fixed as u8,
+            |bytes| {
+                bytes[LEN_TABLE] = Codes::Fixed64 as u8;
+                bytes[DIST_TABLE] = Codes::Fixed64 as u8;
+            },
+            |bytes| field(bytes, NLEN, 256),
+            |bytes| field(bytes, NDIST, 0),
+            |bytes| mode(bytes, Mode::CodeLens),
+            // fields that depend on the mode
+            |bytes| {
+                mode(bytes, Mode::Lit);
+                field(bytes, LENGTH, 256);
+            },
+            |bytes| {
+                mode(bytes, Mode::LenExt);
+                field(bytes, LENGTH, 2);
+            },
+            |bytes| {
+                mode(bytes, Mode::Dist);
+                field(bytes, LENGTH, 10);
+                field(bytes, WAS, 11);
+            },
+            |bytes| {
+                in_match(bytes);
+                field(bytes, LENGTH, 0);
+            },
+            |bytes| {
+                in_match(bytes);
+                field(bytes, LENGTH, 11);
+            },
+            |bytes| {
+                in_match(bytes);
+                field(bytes, OFFSET, 0);
+            },
+            |bytes| {
+                in_match(bytes);
+                field(bytes, OFFSET, (1 << 15) + 1);
+            },
+            |bytes| {
+                mode(bytes, Mode::LenLens);
+                field(bytes, NCODE, 3);
+            },
+            |bytes| {
+                mode(bytes, Mode::LenLens);
+                field(bytes, NCODE, 4);
+                field(bytes, HAVE, 5);
+            },
+            |bytes| {
+                mode(bytes, Mode::CopyBlock);
+                field(bytes, LENGTH, 1 << 16);
+            },
+            |bytes| {
+                mode(bytes, Mode::Sync);
+                field(bytes, HAVE, 5);
+            },
+            |bytes| mode(bytes, Mode::Head),
+            |bytes| mode(bytes, Mode::Extra),
+            |bytes| mode(bytes, Mode::Done),
+        ];
+
+        for (i, mutate) in invalid.iter().enumerate() {
+            assert_eq!(restore_with(*mutate), ReturnCode::DataError, "mutation {i}");
+        }
+    }
+
+    #[test]
+    fn modes() {
+        for (i, mode) in MODES.into_iter().enumerate() {
+            assert_eq!(mode as usize, i);
+        }
+    }
+}
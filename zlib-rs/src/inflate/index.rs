@@ -0,0 +1,449 @@
+//! Random access into compressed data.
+//!
+//! Decompressing a range from the middle of a deflate stream normally means decompressing
+//! everything before it. An [`Index`] records access points in a single pass over the data: block
+//! boundaries, together with the 32K of uncompressed data before them. Decoding can start at an
+//! access point by priming a raw inflate stream with the bits before the boundary and setting the
+//! window as a dictionary, see [`AccessPoint::prime`].
+//!
+//! This is the approach of `examples/zran.c` in the zlib distribution.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{
+    c_api::z_stream,
+    deflate::{self, DeflateConfig},
+    inflate::{self, InflateConfig, InflateStream},
+    InflateFlush, ReturnCode, MAX_WBITS,
+};
+
+/// The size of the window that is stored with every access point.
+const WINDOW_SIZE: usize = 1 << MAX_WBITS;
+
+/// Identifies the serialized format, see [`Index::serialize`].
+const MAGIC: [u8; 4] = *b"zidx";
+const VERSION: u8 = 1;
+
+/// A position in the compressed data where decoding can start.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AccessPoint {
+    /// The offset of the access point in the uncompressed data.
+    pub output_offset: u64,
+    /// The offset of the first full byte of the block in the compressed data.
+    pub input_offset: u64,
+    /// The number of bits (0..=7) of the block in the byte before `input_offset`, which are the
+    /// high bits of that byte.
+    pub bits: u8,
+    /// The uncompressed data before the access point, at most 32K.
+    pub window: Vec<u8>,
+}
+
+impl AccessPoint {
+    /// Prepare `stream` to decode from this access point. The stream must be a freshly initialized
+    /// raw inflate stream (negative `window_bits`), and `input` is the complete compressed data.
+    /// Inflate then continues with the input at [`input_offset`](Self::input_offset).
+    pub fn prime(&self, stream: &mut InflateStream, input: &[u8]) -> ReturnCode {
+        if self.bits != 0 {
+            let Some(&byte) = (self.input_offset as usize)
+                .checked_sub(1)
+                .and_then(|i| input.get(i))
+            else {
+                return ReturnCode::DataError;
+            };
+
+            let err = inflate::prime(stream, self.bits as i32, (byte >> (8 - self.bits)) as i32);
+            if err != ReturnCode::Ok {
+                return err;
+            }
+        }
+
+        inflate::set_dictionary(stream, &self.window)
+    }
+}
+
+/// The access points of a deflate, zlib or gzip stream, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Index {
+    /// The access points, in order of their offsets. The first is at the start of the data.
+    pub points: Vec<AccessPoint>,
+    /// The length of the uncompressed data.
+    pub length: u64,
+}
+
+impl Index {
+    /// Decompress `input` and record an access point about every `span` bytes of output. The
+    /// `window_bits` are those of [`InflateConfig`], e.g. 47 to accept both zlib and gzip.
+    ///
+    /// Only the first stream is indexed, e.g. of a gzip file with several members. Returns
+    /// [`ReturnCode::DataError`] when the data is invalid or incomplete.
+    pub fn build(input: &[u8], window_bits: i32, span: u64) -> Result<Self, ReturnCode> {
+        let config = InflateConfig {
+            window_bits,
+            ..Default::default()
+        };
+
+        let mut stream = z_stream::default();
+        let err = inflate::init(&mut stream, config);
+        if err != ReturnCode::Ok {
+            return Err(err);
+        }
+
+        // SAFETY: the stream was successfully initialized
+        let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let result = Self::build_help(stream, input, span);
+
+        inflate::end(stream);
+
+        result
+    }
+
+    fn build_help(stream: &mut InflateStream, input: &[u8], span: u64) -> Result<Self, ReturnCode> {
+        let mut index = Index {
+            points: Vec::new(),
+            length: 0,
+        };
+
+        // inflate does not stop before the first block of a raw stream
+        if stream.state.wrap == 0 {
+            index.points.push(AccessPoint {
+                output_offset: 0,
+                input_offset: 0,
+                bits: 0,
+                window: Vec::new(),
+            });
+        }
+
+        // the last WINDOW_SIZE bytes of output, in a circular buffer
+        let mut window = vec![0u8; WINDOW_SIZE];
+
+        let mut total_in = 0;
+        let mut total_out = 0;
+        let mut last = 0;
+
+        loop {
+            let pos = total_out as usize % WINDOW_SIZE;
+            let avail_in = Ord::min(input.len() - total_in, u32::MAX as usize);
+
+            stream.next_in = input[total_in..].as_ptr() as *mut u8;
+            stream.avail_in = avail_in as _;
+            stream.next_out = window[pos..].as_mut_ptr();
+            stream.avail_out = (WINDOW_SIZE - pos) as _;
+
+            // stop at the end of every block header
+            let err = unsafe { inflate::inflate(stream, InflateFlush::Block) };
+
+            total_in += avail_in - stream.avail_in as usize;
+            total_out += ((WINDOW_SIZE - pos) - stream.avail_out as usize) as u64;
+
+            match err {
+                ReturnCode::Ok => {}
+                ReturnCode::StreamEnd => break,
+                // there is no more input, so the data is incomplete
+                ReturnCode::BufError => return Err(ReturnCode::DataError),
+                err => return Err(err),
+            }
+
+            // bit 7: at a block boundary, bit 6: the last block has been started
+            let data_type = stream.data_type;
+            if data_type & 128 == 0 || data_type & 64 != 0 {
+                continue;
+            }
+
+            if !(total_out == 0 || total_out - last > span) {
+                continue;
+            }
+
+            let end = total_out as usize % WINDOW_SIZE;
+            let window = match total_out < WINDOW_SIZE as u64 {
+                true => window[..end].to_vec(),
+                false => [&window[end..], &window[..end]].concat(),
+            };
+
+            index.points.push(AccessPoint {
+                output_offset: total_out,
+                input_offset: total_in as u64,
+                bits: (data_type & 7) as u8,
+                window,
+            });
+
+            last = total_out;
+        }
+
+        index.length = total_out;
+
+        Ok(index)
+    }
+
+    /// Decompress the bytes at `offset` in the uncompressed data into `output`, starting from the
+    /// closest access point before it. `input` is the data that the index was built from. Returns
+    /// the number of bytes written, which is less than `output.len()` at the end of the data.
+    pub fn extract(
+        &self,
+        input: &[u8],
+        offset: u64,
+        output: &mut [u8],
+    ) -> Result<usize, ReturnCode> {
+        if offset >= self.length || output.is_empty() {
+            return Ok(0);
+        }
+
+        let index = self
+            .points
+            .partition_point(|point| point.output_offset <= offset);
+        let Some(point) = index.checked_sub(1).map(|index| &self.points[index]) else {
+            return Err(ReturnCode::DataError);
+        };
+
+        let Some(block) = input.get(point.input_offset as usize..) else {
+            return Err(ReturnCode::DataError);
+        };
+
+        let config = InflateConfig {
+            window_bits: -MAX_WBITS,
+            ..Default::default()
+        };
+
+        let mut stream = z_stream::default();
+        let err = inflate::init(&mut stream, config);
+        if err != ReturnCode::Ok {
+            return Err(err);
+        }
+
+        // SAFETY: the stream was successfully initialized
+        let stream = unsafe { InflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let result = match point.prime(stream, input) {
+            ReturnCode::Ok => {
+                Self::extract_help(stream, block, offset - point.output_offset, output)
+            }
+            err => Err(err),
+        };
+
+        inflate::end(stream);
+
+        result
+    }
+
+    fn extract_help(
+        stream: &mut InflateStream,
+        input: &[u8],
+        mut skip: u64,
+        output: &mut [u8],
+    ) -> Result<usize, ReturnCode> {
+        let mut discard = vec![0u8; Ord::min(skip, WINDOW_SIZE as u64) as usize];
+
+        let mut total_in = 0;
+        let mut written = 0;
+
+        while written < output.len() {
+            let out = match skip {
+                0 => &mut output[written..],
+                _ => &mut discard[..Ord::min(skip, WINDOW_SIZE as u64) as usize],
+            };
+            let avail_out = Ord::min(out.len(), u32::MAX as usize);
+            let avail_in = Ord::min(input.len() - total_in, u32::MAX as usize);
+
+            stream.next_in = input[total_in..].as_ptr() as *mut u8;
+            stream.avail_in = avail_in as _;
+            stream.next_out = out.as_mut_ptr();
+            stream.avail_out = avail_out as _;
+
+            let err = unsafe { inflate::inflate(stream, InflateFlush::NoFlush) };
+
+            total_in += avail_in - stream.avail_in as usize;
+            let produced = avail_out - stream.avail_out as usize;
+            match skip {
+                0 => written += produced,
+                _ => skip -= produced as u64,
+            }
+
+            match err {
+                ReturnCode::Ok => {}
+                ReturnCode::StreamEnd => break,
+                ReturnCode::BufError => return Err(ReturnCode::DataError),
+                err => return Err(err),
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Serialize the index. The windows of the access points are compressed, so the index is
+    /// usually much smaller than 32K per access point.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.length.to_le_bytes());
+        bytes.extend_from_slice(&(self.points.len() as u64).to_le_bytes());
+
+        let config = DeflateConfig {
+            window_bits: -MAX_WBITS,
+            ..DeflateConfig::new(9)
+        };
+
+        let mut compressed = vec![0; deflate::compress_bound(WINDOW_SIZE)];
+        for point in &self.points {
+            let (compressed, err) = deflate::compress_slice(&mut compressed, &point.window, config);
+            assert_eq!(err, ReturnCode::Ok);
+
+            bytes.extend_from_slice(&point.output_offset.to_le_bytes());
+            bytes.extend_from_slice(&point.input_offset.to_le_bytes());
+            bytes.push(point.bits);
+            bytes.extend_from_slice(&(point.window.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(compressed);
+        }
+
+        bytes
+    }
+
+    /// Deserialize an index that was serialized with [`Index::serialize`]. Returns
+    /// [`ReturnCode::DataError`] when the bytes are not a valid index.
+    pub fn deserialize(mut bytes: &[u8]) -> Result<Self, ReturnCode> {
+        fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], ReturnCode> {
+            if bytes.len() < n {
+                return Err(ReturnCode::DataError);
+            }
+
+            let (head, tail) = bytes.split_at(n);
+            *bytes = tail;
+            Ok(head)
+        }
+
+        fn take_u64(bytes: &mut &[u8]) -> Result<u64, ReturnCode> {
+            Ok(u64::from_le_bytes(take(bytes, 8)?.try_into().unwrap()))
+        }
+
+        if take(&mut bytes, 4)? != MAGIC || take(&mut bytes, 1)? != [VERSION] {
+            return Err(ReturnCode::DataError);
+        }
+
+        let length = take_u64(&mut bytes)?;
+        let count = take_u64(&mut bytes)?;
+
+        let config = InflateConfig {
+            window_bits: -MAX_WBITS,
+            ..Default::default()
+        };
+
+        let mut points = Vec::new();
+        for _ in 0..count {
+            let output_offset = take_u64(&mut bytes)?;
+            let input_offset = take_u64(&mut bytes)?;
+            let bits = take(&mut bytes, 1)?[0];
+            let window_len = u16::from_le_bytes(take(&mut bytes, 2)?.try_into().unwrap());
+            let compressed_len = u32::from_le_bytes(take(&mut bytes, 4)?.try_into().unwrap());
+            let compressed = take(&mut bytes, compressed_len as usize)?;
+
+            let previous = points
+                .last()
+                .map_or(0, |point: &AccessPoint| point.output_offset);
+            if bits > 7 || window_len as usize > WINDOW_SIZE || output_offset < previous {
+                return Err(ReturnCode::DataError);
+            }
+
+            let mut window = vec![0; window_len as usize];
+            let (decompressed, err) = inflate::uncompress_slice(&mut window, compressed, config);
+            if err != ReturnCode::Ok || decompressed.len() != window_len as usize {
+                return Err(ReturnCode::DataError);
+            }
+
+            points.push(AccessPoint {
+                output_offset,
+                input_offset,
+                bits,
+                window,
+            });
+        }
+
+        if !bytes.is_empty() {
+            return Err(ReturnCode::DataError);
+        }
+
+        Ok(Self { points, length })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &[u8] = include_bytes!("../deflate/test-data/paper-100k.pdf");
+
+    fn compress(window_bits: i32) -> Vec<u8> {
+        let config = DeflateConfig {
+            window_bits,
+            ..DeflateConfig::new(6)
+        };
+
+        let mut output = vec![0; deflate::compress_bound(INPUT.len())];
+        let (output, err) = deflate::compress_slice(&mut output, INPUT, config);
+        assert_eq!(err, ReturnCode::Ok);
+        output.to_vec()
+    }
+
+    #[test]
+    fn extract() {
+        for (window_bits, index_window_bits) in [(15, 15), (31, 31), (-15, -15), (31, 47)] {
+            let compressed = compress(window_bits);
+
+            let index = Index::build(&compressed, index_window_bits, 16 * 1024).unwrap();
+            assert_eq!(index.length, INPUT.len() as u64);
+            assert!(index.points.len() > 3);
+            assert_eq!(index.points[0].output_offset, 0);
+            assert!(index.points.iter().any(|point| point.bits != 0));
+
+            for offset in [0, 1, 16 * 1024, 40_000, 65_000, INPUT.len() - 100] {
+                let mut output = [0; 1000];
+                let n = index
+                    .extract(&compressed, offset as u64, &mut output)
+                    .unwrap();
+                let expected = &INPUT[offset..Ord::min(offset + 1000, INPUT.len())];
+                assert_eq!(&output[..n], expected, "{window_bits} {offset}");
+            }
+
+            // past the end
+            let mut output = [0; 10];
+            assert_eq!(
+                index.extract(&compressed, INPUT.len() as u64, &mut output),
+                Ok(0)
+            );
+        }
+    }
+
+    #[test]
+    fn serialize() {
+        let compressed = compress(31);
+        let index = Index::build(&compressed, 31, 32 * 1024).unwrap();
+
+        let bytes = index.serialize();
+        assert!(bytes.len() < index.points.len() * WINDOW_SIZE / 2);
+        assert_eq!(Index::deserialize(&bytes), Ok(index));
+
+        for n in [0, 4, 20, bytes.len() - 1] {
+            assert_eq!(Index::deserialize(&bytes[..n]), Err(ReturnCode::DataError));
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(Index::deserialize(&trailing), Err(ReturnCode::DataError));
+    }
+
+    #[test]
+    fn invalid_input() {
+        let compressed = compress(15);
+
+        let truncated = &compressed[..compressed.len() / 2];
+        assert_eq!(
+            Index::build(truncated, 15, 16 * 1024),
+            Err(ReturnCode::DataError)
+        );
+
+        let mut corrupted = compressed.clone();
+        corrupted[0] ^= 0xff;
+        assert!(Index::build(&corrupted, 15, 16 * 1024).is_err());
+    }
+}
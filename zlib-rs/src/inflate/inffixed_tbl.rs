@@ -1,5 +1,7 @@
 use crate::Code;
 
+use super::OP_EXTRA_16;
+
 const fn code(op: u8, bits: u8, val: u16) -> Code {
     Code { op, bits, val }
 }
@@ -553,3 +555,29 @@ pub(crate) const DISTFIX: [Code; 32] = [
     code(22, 5, 193),
     code(64, 5, 0),
 ];
+
+/// The fixed literal/length table of Deflate64: length code 285 has 16 extra bits and a base of 3.
+pub(crate) const LENFIX64: [Code; 512] = {
+    let mut table = LENFIX;
+
+    let mut i = 0;
+    while i < table.len() {
+        if table[i].op == 16 && table[i].val == 258 {
+            table[i] = code(OP_EXTRA_16 | 16, table[i].bits, 3);
+        }
+        i += 1;
+    }
+
+    table
+};
+
+/// The fixed distance table of Deflate64: distance codes 30 and 31 are valid.
+pub(crate) const DISTFIX64: [Code; 32] = {
+    let mut table = DISTFIX;
+
+    // the table is indexed by the bit-reversed code
+    table[0b01111] = code(30, 5, 32769);
+    table[0b11111] = code(30, 5, 49153);
+
+    table
+};
@@ -2,10 +2,16 @@
 
 use crate::{Code, ENOUGH_DISTS, ENOUGH_LENS};
 
+use super::OP_EXTRA_16;
+
 pub(crate) enum CodeType {
     Codes,
     Lens,
     Dists,
+    /// The length codes of Deflate64
+    Lens64,
+    /// The distance codes of Deflate64
+    Dists64,
 }
 
 const MAX_BITS: usize = 15;
@@ -40,6 +46,45 @@ const LEXT: [u16; 31] = [
     16, 16, 16, 16, 16, 16, 16, 16, 17, 17, 17, 17, 18, 18, 18, 18, 19, 19, 19, 19, 20, 20, 20, 20,
     21, 21, 21, 21, 16, 77, 202,
 ];
+/// Length codes 257..285 base, Deflate64
+const LBASE64: [u16; 31] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 3, 0, 0,
+];
+/// Length codes 257..285 extra, Deflate64. Length code 285 has 16 extra bits, see `OP_EXTRA_16`.
+const LEXT64: [u16; 31] = [
+    16,
+    16,
+    16,
+    16,
+    16,
+    16,
+    16,
+    16,
+    17,
+    17,
+    17,
+    17,
+    18,
+    18,
+    18,
+    18,
+    19,
+    19,
+    19,
+    19,
+    20,
+    20,
+    20,
+    20,
+    21,
+    21,
+    21,
+    21,
+    OP_EXTRA_16 as u16 | 16,
+    77,
+    202,
+];
 /// Distance codes 0..29 base
 const DBASE: [u16; 32] = [
     1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
@@ -51,6 +96,17 @@ const DEXT: [u16; 32] = [
     27, 27, 28, 28, 29, 29, 64, 64,
 ];
 
+/// Distance codes 0..31 base, Deflate64
+const DBASE64: [u16; 32] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577, 32769, 49153,
+];
+/// Distance codes 0..31 extra, Deflate64
+const DEXT64: [u16; 32] = [
+    16, 16, 16, 16, 17, 17, 18, 18, 19, 19, 20, 20, 21, 21, 22, 22, 23, 23, 24, 24, 25, 25, 26, 26,
+    27, 27, 28, 28, 29, 29, 30, 30,
+];
+
 #[repr(i32)]
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) enum InflateTable {
@@ -133,16 +189,18 @@ pub(crate) fn inflate_table(
         CodeType::Codes => (&[] as &[_], &[] as &[_], 20),
         CodeType::Lens => (&LBASE[..], &LEXT[..], 257),
         CodeType::Dists => (&DBASE[..], &DEXT[..], 0),
+        CodeType::Lens64 => (&LBASE64[..], &LEXT64[..], 257),
+        CodeType::Dists64 => (&DBASE64[..], &DEXT64[..], 0),
     };
 
     let used = 1 << root;
 
     /* check available table space */
-    if matches!(codetype, CodeType::Lens) && used > ENOUGH_LENS {
+    if matches!(codetype, CodeType::Lens | CodeType::Lens64) && used > ENOUGH_LENS {
         return InflateTable::EnoughIsNotEnough;
     }
 
-    if matches!(codetype, CodeType::Dists) && used > ENOUGH_DISTS {
+    if matches!(codetype, CodeType::Dists | CodeType::Dists64) && used > ENOUGH_DISTS {
         return InflateTable::EnoughIsNotEnough;
     }
 
@@ -228,11 +286,11 @@ pub(crate) fn inflate_table(
             /* check for enough space */
             used += 1usize << curr;
 
-            if matches!(codetype, CodeType::Lens) && used > ENOUGH_LENS {
+            if matches!(codetype, CodeType::Lens | CodeType::Lens64) && used > ENOUGH_LENS {
                 return InflateTable::EnoughIsNotEnough;
             }
 
-            if matches!(codetype, CodeType::Dists) && used > ENOUGH_DISTS {
+            if matches!(codetype, CodeType::Dists | CodeType::Dists64) && used > ENOUGH_DISTS {
                 return InflateTable::EnoughIsNotEnough;
             }
 
@@ -289,7 +347,7 @@ mod test {
         assert_eq!(ret, InflateTable::EnoughIsNotEnough);
     }
 
-    fn build_fixed_length_table(work: &mut [u16]) -> [Code; 512] {
+    fn build_fixed_length_table(codetype: CodeType, work: &mut [u16]) -> [Code; 512] {
         let mut lens = [0; 288];
 
         // literal/length table
@@ -313,7 +371,7 @@ mod test {
 
         let mut next = [Code::default(); 512];
         let bits = 9;
-        inflate_table(CodeType::Lens, &lens, 288, &mut next, bits, work);
+        inflate_table(codetype, &lens, 288, &mut next, bits, work);
 
         core::array::from_fn(|i| {
             let mut code = next[i];
@@ -327,12 +385,16 @@ mod test {
     #[test]
     fn generate_fixed_length_table() {
         let mut work = [0; 512];
-        let generated = build_fixed_length_table(&mut work);
+        let generated = build_fixed_length_table(CodeType::Lens, &mut work);
 
         assert_eq!(generated, crate::inflate::inffixed_tbl::LENFIX);
+
+        let generated = build_fixed_length_table(CodeType::Lens64, &mut work);
+
+        assert_eq!(generated, crate::inflate::inffixed_tbl::LENFIX64);
     }
 
-    fn build_fixed_distance_table(work: &mut [u16]) -> [Code; 32] {
+    fn build_fixed_distance_table(codetype: CodeType, work: &mut [u16]) -> [Code; 32] {
         let mut lens = [0; 288];
 
         let mut sym = 0;
@@ -343,7 +405,7 @@ mod test {
 
         let mut next = [Code::default(); 32];
         let bits = 5;
-        inflate_table(CodeType::Dists, &lens, 32, &mut next, bits, work);
+        inflate_table(codetype, &lens, 32, &mut next, bits, work);
 
         next
     }
@@ -351,8 +413,12 @@ mod test {
     #[test]
     fn generate_fixed_distance_table() {
         let mut work = [0; 512];
-        let generated = build_fixed_distance_table(&mut work);
+        let generated = build_fixed_distance_table(CodeType::Dists, &mut work);
 
         assert_eq!(generated, crate::inflate::inffixed_tbl::DISTFIX);
+
+        let generated = build_fixed_distance_table(CodeType::Dists64, &mut work);
+
+        assert_eq!(generated, crate::inflate::inffixed_tbl::DISTFIX64);
     }
 }
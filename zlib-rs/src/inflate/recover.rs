@@ -0,0 +1,510 @@
+//! Recovery of data from damaged streams.
+//!
+//! [`recover`] decodes as much as possible of a deflate, zlib or gzip stream that is damaged, e.g.
+//! a backup that was partially overwritten. When decoding fails, it searches forward for a point
+//! where decoding can resume: a plausible block header at any bit position, or the block after a
+//! full flush marker (the `00 00 ff ff` that [`inflate::sync`] searches for). The data that is
+//! skipped is reported as a [`Gap`].
+//!
+//! The data after a gap can refer back to data that was lost. The window is filled with a
+//! placeholder byte, so those references produce the placeholder instead.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{
+    c_api::z_stream,
+    inflate::{self, Codes, InflateConfig, InflateStream, Mode, PreparedDictionary},
+    InflateFlush, ReturnCode, MAX_WBITS,
+};
+
+/// The size of the window that is filled with the placeholder.
+const WINDOW_SIZE: usize = 1 << MAX_WBITS;
+
+/// The length of the largest trailer, that of gzip.
+const GZIP_TRAILER: usize = 8;
+
+/// The size of the chunks of output.
+const CHUNK: usize = 1 << 16;
+
+/// A part of the compressed data that was skipped, see [`recover`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Gap {
+    /// The error that started the gap, see [`Corruption::message`](inflate::Corruption::message).
+    pub message: &'static str,
+    /// The offset in bits in the compressed data where the error was detected. The data was
+    /// damaged somewhere before this point.
+    pub start_bit: u64,
+    /// The offset in bits in the compressed data where decoding resumed, or the end of the data
+    /// when it could not resume.
+    pub end_bit: u64,
+    /// The offset in the recovered data of the gap: the data that was decoded after the gap starts
+    /// here.
+    pub output_offset: u64,
+}
+
+/// The data that could be recovered from a damaged stream, see [`recover`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Recovery {
+    /// The recovered data.
+    pub data: Vec<u8>,
+    /// The parts of the compressed data that were skipped, in order.
+    pub gaps: Vec<Gap>,
+}
+
+/// Decompress as much of `input` as possible, see the [module documentation](self). The
+/// `window_bits` are those of [`InflateConfig`], e.g. 47 to accept both zlib and gzip. References
+/// to data that was lost in a gap produce the `placeholder` byte.
+///
+/// Several streams in a row are decoded, like the members of a gzip file. The data before a gap
+/// may include some bytes that were decoded from damaged data before the error was detected.
+pub fn recover(input: &[u8], window_bits: i32, placeholder: u8) -> Result<Recovery, ReturnCode> {
+    let window = vec![placeholder; WINDOW_SIZE];
+    let window = PreparedDictionary::new(&window);
+
+    let mut recovery = Recovery {
+        data: Vec::new(),
+        gaps: Vec::new(),
+    };
+
+    // a raw stream to try out positions where decoding could resume
+    let mut trial = Decoder::new(-MAX_WBITS)?;
+
+    let end = input.len() as u64 * 8;
+
+    // the start of the current part, and whether it starts at a block after a gap
+    let mut start = 0;
+    let mut resumed = false;
+    // the length of the trailer of the current stream, which a raw stream does not read
+    let mut trailer = 0;
+
+    while start < end {
+        let mut decoder = match resumed {
+            true => Decoder::new(-MAX_WBITS)?,
+            false => Decoder::new(window_bits)?,
+        };
+
+        let stream = decoder.stream();
+        let offset = prepare(stream, input, start, resumed.then_some(&window));
+        let err = decode(stream, &input[offset..], &mut recovery.data);
+        let position = position(stream, offset);
+
+        // only a stream whose header could be decoded has a trailer
+        if !resumed && stream.state.blocks > 0 {
+            // -1 when there is no header, 0 for zlib, and the flags of a gzip header otherwise
+            trailer = match stream.state.gzip_flags {
+                -1 => 0,
+                0 => 4,
+                _ => GZIP_TRAILER as u64,
+            };
+        }
+
+        let message = match err {
+            ReturnCode::StreamEnd => {
+                // continue with the next stream, if any
+                let next = position.div_ceil(8) + if resumed { trailer } else { 0 };
+                start = next * 8;
+                resumed = false;
+                continue;
+            }
+            ReturnCode::DataError => match stream.corruption() {
+                Some(corruption) => corruption.message,
+                None => return Err(err),
+            },
+            // the input ended before the stream did
+            ReturnCode::BufError => "unexpected end of data",
+            err => return Err(err),
+        };
+
+        // resume after the position where decoding failed (which also makes progress when it
+        // failed right away)
+        let from = Ord::max(position, start + 1);
+        let resume = find_resume(&mut trial, input, from, &window);
+
+        recovery.gaps.push(Gap {
+            message,
+            start_bit: position,
+            end_bit: resume.unwrap_or(end),
+            output_offset: recovery.data.len() as u64,
+        });
+
+        match resume {
+            Some(bit) => {
+                start = bit;
+                resumed = true;
+            }
+            None => break,
+        }
+    }
+
+    Ok(recovery)
+}
+
+/// An inflate stream that is ended when it is dropped.
+struct Decoder {
+    stream: z_stream,
+}
+
+impl Decoder {
+    fn new(window_bits: i32) -> Result<Self, ReturnCode> {
+        let config = InflateConfig {
+            window_bits,
+            ..Default::default()
+        };
+
+        let mut stream = z_stream::default();
+        match inflate::init(&mut stream, config) {
+            ReturnCode::Ok => Ok(Self { stream }),
+            err => Err(err),
+        }
+    }
+
+    fn stream(&mut self) -> &mut InflateStream<'_> {
+        // SAFETY: the stream was successfully initialized
+        unsafe { InflateStream::from_stream_mut(&mut self.stream) }.unwrap()
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        inflate::end(self.stream());
+    }
+}
+
+/// Prepare `stream` to decode from bit `start` of `input`, with the placeholder `window` for a raw
+/// stream that resumes after a gap. Returns the offset of the input that follows.
+fn prepare<'a>(
+    stream: &mut InflateStream<'a>,
+    input: &[u8],
+    start: u64,
+    window: Option<&PreparedDictionary<'a>>,
+) -> usize {
+    let offset = (start / 8) as usize;
+    let bits = (start % 8) as u8;
+
+    if let Some(window) = window {
+        // SAFETY: the window of `recover` outlives the decoders, which are reset before they are
+        // used again
+        let err = unsafe { inflate::set_prepared_dictionary(stream, window) };
+        debug_assert_eq!(err, ReturnCode::Ok);
+    }
+
+    if bits == 0 {
+        return offset;
+    }
+
+    // the high bits of the byte
+    let err = inflate::prime(stream, (8 - bits) as i32, (input[offset] >> bits) as i32);
+    debug_assert_eq!(err, ReturnCode::Ok);
+
+    offset + 1
+}
+
+/// Decompress `input` into `output` until the end of the stream, or an error.
+fn decode(stream: &mut InflateStream, input: &[u8], output: &mut Vec<u8>) -> ReturnCode {
+    let mut chunk = vec![0; CHUNK];
+
+    loop {
+        let read = stream.total_in as usize;
+        stream.next_in = input[read..].as_ptr() as *mut u8;
+        stream.avail_in = Ord::min(input.len() - read, u32::MAX as usize) as _;
+        stream.next_out = chunk.as_mut_ptr();
+        stream.avail_out = chunk.len() as _;
+
+        let err = unsafe { inflate::inflate(stream, InflateFlush::NoFlush) };
+
+        output.extend_from_slice(&chunk[..chunk.len() - stream.avail_out as usize]);
+
+        if err != ReturnCode::Ok {
+            return err;
+        }
+    }
+}
+
+/// The offset in bits in the complete input of `stream`, whose input starts at `offset`.
+fn position(stream: &InflateStream, offset: usize) -> u64 {
+    (offset + stream.total_in as usize) as u64 * 8 - stream.state.bit_reader.bits_in_buffer() as u64
+}
+
+/// The first position at or after bit `from` where decoding can resume: a plausible block header,
+/// or the block after a full flush marker.
+fn find_resume(
+    trial: &mut Decoder,
+    input: &[u8],
+    from: u64,
+    window: &PreparedDictionary,
+) -> Option<u64> {
+    let from_byte = from.div_ceil(8) as usize;
+    if from_byte >= input.len() {
+        return None;
+    }
+
+    let marker = match inflate::syncsearch(0, &input[from_byte..]) {
+        (4, len) => Some((from_byte + len) as u64 * 8),
+        _ => None,
+    };
+
+    // the block after the marker is a fine place to resume, but a block before it is earlier
+    let limit = marker.unwrap_or(input.len() as u64 * 8);
+    (from..limit)
+        .find(|&bit| plausible_header(input, bit) && decodes(trial, input, bit, window))
+        .or(marker)
+}
+
+/// `n` bits (at most 32) at bit `bit` of `input`, the first bit in the least significant bit.
+fn bits(input: &[u8], bit: u64, n: u32) -> Option<u32> {
+    let mut value = 0;
+    for i in 0..n as u64 {
+        let byte = *input.get(((bit + i) / 8) as usize)?;
+        value |= ((byte >> ((bit + i) % 8)) as u32 & 1) << i;
+    }
+
+    Some(value)
+}
+
+/// Whether there could be a stored or dynamic block at bit `bit`. Fixed blocks have a header of
+/// just 3 bits, so they are too likely to appear by chance.
+fn plausible_header(input: &[u8], bit: u64) -> bool {
+    match bits(input, bit + 1, 2) {
+        // a stored block, whose length is followed by its complement
+        Some(0b00) => {
+            let offset = (bit + 3).div_ceil(8) as usize;
+            match input.get(offset..offset + 4) {
+                Some(&[a, b, c, d]) => u16::from_le_bytes([a, b]) == !u16::from_le_bytes([c, d]),
+                _ => false,
+            }
+        }
+        // a dynamic block, whose code length code must be complete
+        Some(0b10) => {
+            let (Some(hlit), Some(hdist), Some(hclen)) = (
+                bits(input, bit + 3, 5),
+                bits(input, bit + 8, 5),
+                bits(input, bit + 13, 4),
+            ) else {
+                return false;
+            };
+
+            if hlit > 29 || hdist > 29 {
+                return false;
+            }
+
+            let mut kraft = 0;
+            for i in 0..hclen as u64 + 4 {
+                match bits(input, bit + 17 + 3 * i, 3) {
+                    Some(0) => {}
+                    Some(len) => kraft += 1 << (7 - len),
+                    None => return false,
+                }
+            }
+
+            kraft == 1 << 7
+        }
+        _ => false,
+    }
+}
+
+/// Whether decoding from bit `bit` succeeds for long enough to trust it. Random data often decodes
+/// as a fixed block, and now and then has the length and its complement of a stored block. A
+/// dynamic block that decodes is much less likely, because its codes must be complete.
+fn decodes(trial: &mut Decoder, input: &[u8], bit: u64, window: &PreparedDictionary) -> bool {
+    let stream = trial.stream();
+    inflate::reset(stream);
+
+    let offset = prepare(stream, input, bit, Some(window));
+    let input = &input[offset..];
+
+    let mut chunk = vec![0; CHUNK];
+
+    // the evidence of the blocks that were decoded, and of the current block
+    let mut score = 0;
+    let mut pending = 0;
+
+    loop {
+        let read = stream.total_in as usize;
+        stream.next_in = input[read..].as_ptr() as *mut u8;
+        stream.avail_in = Ord::min(input.len() - read, u32::MAX as usize) as _;
+        stream.next_out = chunk.as_mut_ptr();
+        stream.avail_out = chunk.len() as _;
+
+        let mode = stream.state.mode;
+
+        // stop at the start of every block, and after every block header
+        match unsafe { inflate::inflate(stream, InflateFlush::Trees) } {
+            ReturnCode::Ok => {}
+            // a block header may be decoded from the bits that are already buffered
+            ReturnCode::BufError if stream.state.mode as u8 != mode as u8 => {}
+            // the end of the input is a good reason for a stream to end
+            ReturnCode::StreamEnd => {
+                let unused = input.len() - stream.total_in as usize;
+                return score + pending >= 2 || unused <= GZIP_TRAILER;
+            }
+            _ => return false,
+        }
+
+        let state = &stream.state;
+        match state.mode {
+            Mode::Type => score += core::mem::take(&mut pending),
+            Mode::CopyBlock => pending = 1,
+            Mode::Len_ => match state.len_table.codes {
+                Codes::Fixed | Codes::Fixed64 => pending = 0,
+                _ => pending = 2,
+            },
+            _ => {}
+        }
+
+        if score >= 2 {
+            return true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::deflate::{self, DeflateConfig, DeflateStream};
+    use crate::inflate::blocks::Blocks;
+    use crate::DeflateFlush;
+
+    const INPUT: &[u8] = include_bytes!("../deflate/test-data/paper-100k.pdf");
+
+    const PLACEHOLDER: u8 = b'?';
+
+    /// Compress to gzip, with a full flush every `flush_every` bytes.
+    fn compress(flush_every: usize) -> Vec<u8> {
+        let config = DeflateConfig {
+            window_bits: 31,
+            ..DeflateConfig::new(6)
+        };
+
+        let mut stream = z_stream::default();
+        assert_eq!(deflate::init(&mut stream, config), ReturnCode::Ok);
+        let stream = unsafe { DeflateStream::from_stream_mut(&mut stream) }.unwrap();
+
+        let mut output = vec![0; deflate::compress_bound(INPUT.len()) + 1024];
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+
+        for chunk in INPUT.chunks(flush_every) {
+            stream.next_in = chunk.as_ptr() as *mut u8;
+            stream.avail_in = chunk.len() as _;
+
+            let last = chunk.as_ptr_range().end == INPUT.as_ptr_range().end;
+            let flush = match last {
+                true => DeflateFlush::Finish,
+                false => DeflateFlush::FullFlush,
+            };
+            let err = deflate::deflate(stream, flush);
+            assert!(matches!(err, ReturnCode::Ok | ReturnCode::StreamEnd));
+        }
+
+        output.truncate(stream.total_out as usize);
+        let _ = deflate::end(stream);
+
+        output
+    }
+
+    /// Overwrite `len` bytes at `offset` with noise.
+    fn damage(input: &mut [u8], offset: usize, len: usize) {
+        let mut seed = 0x853c_49e6_748f_ea9bu64;
+        for byte in &mut input[offset..][..len] {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            *byte = (seed >> 32) as u8;
+        }
+    }
+
+    #[test]
+    fn undamaged() {
+        let compressed = compress(usize::MAX);
+        let recovery = recover(&compressed, 31, PLACEHOLDER).unwrap();
+        assert!(recovery.gaps.is_empty());
+        assert!(recovery.data == INPUT);
+
+        // two gzip members
+        let twice = [&compressed[..], &compressed[..]].concat();
+        let recovery = recover(&twice, 31, PLACEHOLDER).unwrap();
+        assert!(recovery.gaps.is_empty());
+        assert!(recovery.data == [INPUT, INPUT].concat());
+    }
+
+    #[test]
+    fn full_flush() {
+        let mut compressed = compress(16 * 1024);
+        let len = compressed.len();
+        damage(&mut compressed, len / 3, 200);
+
+        let recovery = recover(&compressed, 31, PLACEHOLDER).unwrap();
+        assert_eq!(recovery.gaps.len(), 1, "{:?}", recovery.gaps);
+
+        let gap = &recovery.gaps[0];
+        assert!(gap.start_bit < gap.end_bit);
+        assert!(gap.end_bit > (len / 3 + 200) as u64 * 8);
+
+        // nothing after the gap refers to data before it
+        let tail = &recovery.data[gap.output_offset as usize..];
+        assert!(INPUT.ends_with(tail));
+        assert!(tail.len() > INPUT.len() / 2);
+    }
+
+    #[test]
+    fn block_header() {
+        let mut compressed = compress(usize::MAX);
+        let len = compressed.len();
+        damage(&mut compressed, len / 3, 200);
+
+        let recovery = recover(&compressed, 31, PLACEHOLDER).unwrap();
+        assert_eq!(recovery.gaps.len(), 1, "{:?}", recovery.gaps);
+        let gap = &recovery.gaps[0];
+
+        // decoding resumes at a block of the undamaged data
+        let original = compress(usize::MAX);
+        let blocks = Blocks::new(
+            &original,
+            InflateConfig {
+                window_bits: 31,
+                ..Default::default()
+            },
+        );
+        let blocks: Vec<_> = blocks.unwrap().collect::<Result<_, _>>().unwrap();
+        let index = blocks
+            .iter()
+            .position(|block| block.input_bit_offset == gap.end_bit)
+            .unwrap();
+
+        // the data before the damaged block is intact
+        let damaged = blocks
+            .iter()
+            .rposition(|block| block.input_bit_offset <= (len / 3) as u64 * 8);
+        let intact = blocks[damaged.unwrap()].output_offset as usize;
+        assert!(recovery.data[..intact] == INPUT[..intact]);
+
+        // the data after the gap is intact, except for references to lost data
+        let tail = &recovery.data[gap.output_offset as usize..];
+        let expected = &INPUT[blocks[index].output_offset as usize..];
+        assert_eq!(tail.len(), expected.len());
+        assert!(tail
+            .iter()
+            .zip(expected)
+            .all(|(&a, &b)| a == b || a == PLACEHOLDER));
+        assert!(tail.iter().filter(|&&b| b == PLACEHOLDER).count() < tail.len() / 2);
+    }
+
+    #[test]
+    fn truncated() {
+        let compressed = compress(usize::MAX);
+        let truncated = &compressed[..compressed.len() / 2];
+
+        let recovery = recover(truncated, 31, PLACEHOLDER).unwrap();
+        assert_eq!(recovery.gaps.len(), 1);
+        assert_eq!(recovery.gaps[0].message, "unexpected end of data");
+        assert_eq!(recovery.gaps[0].end_bit, truncated.len() as u64 * 8);
+        assert!(INPUT.starts_with(&recovery.data));
+
+        assert_eq!(
+            recover(&compressed, 7, PLACEHOLDER),
+            Err(ReturnCode::StreamError)
+        );
+    }
+}
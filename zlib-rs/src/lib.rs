@@ -10,6 +10,8 @@ pub mod c_api;
 mod cpu_features;
 pub mod crc32;
 pub mod deflate;
+#[cfg(feature = "rust-allocator")]
+pub mod dictionary;
 pub mod inflate;
 pub mod read_buf;
 mod weak_slice;
@@ -28,12 +30,13 @@ macro_rules! trace {
 }
 
 /// Maximum size of the dynamic table.  The maximum number of code structures is
-/// 1924, which is the sum of 1332 for literal/length codes and 592 for distance
+/// 1926, which is the sum of 1332 for literal/length codes and 594 for distance
 /// codes.  These values were found by exhaustive searches using the program
 /// examples/enough.c found in the zlib distributions.  The arguments to that
 /// program are the number of symbols, the initial root table size, and the
 /// maximum bit length of a code.  "enough 286 10 15" for literal/length codes
-/// returns 1332, and "enough 30 9 15" for distance codes returns 592.
+/// returns 1332, and "enough 32 9 15" for the distance codes of Deflate64 returns
+/// 594 (592 for the 30 distance codes of deflate).
 /// The initial root table size (10 or 9) is found in the fifth argument of the
 /// inflate_table() calls in inflate.c and infback.c.  If the root table size is
 /// changed, then these maximum sizes would be need to be recalculated and
@@ -41,7 +44,7 @@ macro_rules! trace {
 #[allow(unused)]
 pub(crate) const ENOUGH: usize = ENOUGH_LENS + ENOUGH_DISTS;
 pub(crate) const ENOUGH_LENS: usize = 1332;
-pub(crate) const ENOUGH_DISTS: usize = 592;
+pub(crate) const ENOUGH_DISTS: usize = 594;
 
 /// initial adler-32 hash value
 pub(crate) const ADLER32_INITIAL_VALUE: usize = 1;